@@ -0,0 +1,85 @@
+use crate::{ParseError, Serialise};
+use std::{
+    fmt::{self, Display},
+    net::{IpAddr, SocketAddr},
+};
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum IpAddress {
+    IpV4([u8; 4]),
+    IpV6([u16; 8]),
+}
+
+impl Default for IpAddress {
+    fn default() -> Self {
+        Self::IpV4([0; 4])
+    }
+}
+
+impl IpAddress {
+    pub fn from_message(msg: &mut std::slice::Iter<u8>, is_ipv6: bool) -> Result<Self, ParseError> {
+        if is_ipv6 {
+            let mut parts: [u16; 8] = [0; 8];
+
+            for part in parts.iter_mut() {
+                let part1 = *msg.next().ok_or(ParseError::MissingMessagePart)? as u16;
+                let part2 = *msg.next().ok_or(ParseError::MissingMessagePart)? as u16;
+                *part = (part1 << 8) | part2;
+            }
+
+            Ok(IpAddress::IpV6(parts))
+        } else {
+            let mut parts: [u8; 4] = [0; 4];
+
+            for part in parts.iter_mut() {
+                *part = *msg.next().ok_or(ParseError::MissingMessagePart)?;
+            }
+
+            Ok(IpAddress::IpV4(parts))
+        }
+    }
+}
+
+impl Display for IpAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IpAddress::IpV4(ip) => write!(f, "{}/4", ip.map(|n| n.to_string()).join(".")),
+            IpAddress::IpV6(ip) => write!(f, "{}/6", ip.map(|n| format!("{n:x}")).join(":")),
+        }
+    }
+}
+
+impl From<IpAddr> for IpAddress {
+    fn from(value: IpAddr) -> Self {
+        match value {
+            IpAddr::V4(ip) => IpAddress::IpV4(ip.octets()),
+            IpAddr::V6(ip) => IpAddress::IpV6(ip.segments()),
+        }
+    }
+}
+
+impl From<SocketAddr> for IpAddress {
+    fn from(value: SocketAddr) -> Self {
+        value.ip().into()
+    }
+}
+
+impl From<IpAddress> for IpAddr {
+    fn from(value: IpAddress) -> Self {
+        match value {
+            IpAddress::IpV4(octets) => Self::from(octets),
+            IpAddress::IpV6(segments) => Self::from(segments),
+        }
+    }
+}
+
+impl Serialise for IpAddress {
+    fn serialise(self) -> Vec<u8> {
+        let mut output = Vec::new();
+        match self {
+            IpAddress::IpV4(octets) => output.extend(octets),
+            IpAddress::IpV6(hexets) => hexets.iter().for_each(|hex| output.extend(hex.serialise())),
+        }
+        output
+    }
+}
@@ -0,0 +1,368 @@
+//! A blocking client, which like the server's plain TCP listener opens one
+//! connection per request.
+
+use crate::{
+    describe,
+    request::{self, LobbySettings},
+    Cursor, DatabaseError, Flags, GetRequest, IpAddress, ParseError, Region, OK, TOKEN_SIZE,
+};
+use std::{
+    fmt::{self, Display},
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpStream, UdpSocket},
+    time::Duration,
+};
+
+/// The newest protocol version the client speaks, and the one it sends
+/// unless told otherwise.
+pub const LATEST_VERSION: u8 = 2;
+
+/// What went wrong with a request, by the response code the server sent.
+#[derive(Debug)]
+pub enum Error {
+    /// Connecting, sending or receiving failed.
+    Io(io::Error),
+    /// The server could not read the request, codes 40 to 49.
+    Parse(ParseError),
+    /// The lobby store refused the request, codes 50 to 60 and 63.
+    Database(DatabaseError),
+    /// Too many requests of this type from this ip, wait before retrying (61).
+    RateLimited,
+    /// The server is handling as many connections as it allows (62).
+    TooManyConnections,
+    /// The request did not arrive in time (101).
+    TimedOut,
+    /// A code this client does not know.
+    Unknown(u8),
+    /// The response body is not laid out as the request's response should be.
+    Malformed,
+}
+
+impl Error {
+    /// The response code the server sent, `None` if it sent none.
+    pub fn code(&self) -> Option<u8> {
+        match self {
+            Error::Io(_) | Error::Malformed => None,
+            Error::Parse(err) => Some(*err as u8),
+            Error::Database(err) => Some(*err as u8),
+            Error::RateLimited => Some(61),
+            Error::TooManyConnections => Some(62),
+            Error::TimedOut => Some(101),
+            Error::Unknown(code) => Some(*code),
+        }
+    }
+
+    pub fn from_code(code: u8) -> Self {
+        if let Ok(err) = ParseError::try_from(code) {
+            return Self::Parse(err);
+        }
+        if let Ok(err) = DatabaseError::try_from(code) {
+            return Self::Database(err);
+        }
+        match code {
+            61 => Self::RateLimited,
+            62 => Self::TooManyConnections,
+            101 => Self::TimedOut,
+            _ => Self::Unknown(code),
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self, self.code()) {
+            (Error::Io(err), _) => write!(f, "connection failed: {err}"),
+            (_, Some(code)) => write!(f, "{code} {}", describe(code).unwrap_or("Unknown")),
+            (_, None) => write!(f, "malformed response"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+/// What the host gets back on Create.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Created {
+    /// Sent back with Modify and Destroy, keep it secret.
+    pub token: [u8; TOKEN_SIZE],
+    /// For sharing, the only way to find a private lobby.
+    pub join_code: String,
+}
+
+/// A lobby as listed in a page.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Listing {
+    pub flags: Flags,
+    pub region: Region,
+    pub host_ip: IpAddress,
+    pub host_port: u16,
+    pub max_players: u8,
+    pub name: String,
+    pub current_players: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Page {
+    pub lobbies: Vec<Listing>,
+    /// Versions 0 and 1, 0 in version 2.
+    pub page_number: u16,
+    /// Versions 0 and 1, 0 in version 2.
+    pub total_pages: u16,
+    /// Every lobby the request lists, on any page. Version 2 only.
+    pub total_lobbies: Option<u32>,
+    /// To send in `GetRequest::cursor` for the next page, `None` on the last
+    /// page. Version 2 only.
+    pub next: Option<Cursor>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Client {
+    address: SocketAddr,
+    version: u8,
+    timeout: Duration,
+}
+
+impl Client {
+    pub fn new(address: SocketAddr) -> Self {
+        Self {
+            address,
+            version: LATEST_VERSION,
+            timeout: Duration::from_secs(5),
+        }
+    }
+
+    /// Speaks an older protocol version, for servers without the latest.
+    /// Modify and Destroy still go as version 1, only it carries the token.
+    pub fn with_version(mut self, version: u8) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// For connecting, and for each read and write.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// The ip this machine reaches the server from, which the server checks
+    /// a host's requests against. Connecting a UDP socket sends nothing.
+    pub fn local_address(&self) -> io::Result<IpAddress> {
+        let unspecified = match self.address {
+            SocketAddr::V4(_) => SocketAddr::from(([0; 4], 0)),
+            SocketAddr::V6(_) => SocketAddr::from(([0u16; 8], 0)),
+        };
+        let socket = UdpSocket::bind(unspecified)?;
+        socket.connect(self.address)?;
+        Ok(socket.local_addr()?.into())
+    }
+
+    pub fn create(&self, lobby: &LobbySettings) -> Result<Created, Error> {
+        let body = self.send(&request::create(self.version, lobby))?;
+        let mut reader = Reader(&body);
+        let token = reader
+            .bytes(TOKEN_SIZE)?
+            .try_into()
+            .map_err(|_| Error::Malformed)?;
+        let join_code = reader.string()?;
+        Ok(Created { token, join_code })
+    }
+
+    /// Replaces the lobby's settings, the host and port pick which lobby.
+    pub fn modify(
+        &self,
+        lobby: &LobbySettings,
+        current_players: u8,
+        token: &[u8; TOKEN_SIZE],
+    ) -> Result<(), Error> {
+        let message = request::modify(self.version, lobby, current_players, token);
+        self.send(&message).map(drop)
+    }
+
+    pub fn destroy(
+        &self,
+        host_ip: IpAddress,
+        port: u16,
+        token: &[u8; TOKEN_SIZE],
+    ) -> Result<(), Error> {
+        let message = request::destroy(self.version, host_ip, port, token);
+        self.send(&message).map(drop)
+    }
+
+    pub fn get(&self, request: &GetRequest) -> Result<Page, Error> {
+        let body = self.send(&request::get(self.version, request))?;
+        decode_page(&body, self.version)
+    }
+
+    /// Sends one message, returning the response body without its length.
+    fn send(&self, message: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut stream = TcpStream::connect_timeout(&self.address, self.timeout)?;
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+
+        // A zero length byte starts a long frame with a u16 length.
+        let mut framed = match u8::try_from(message.len()) {
+            Ok(length) if length > 0 => vec![length],
+            _ => {
+                let length = u16::try_from(message.len()).map_err(|_| Error::Malformed)?;
+                let mut framed = vec![0];
+                framed.extend(length.to_be_bytes());
+                framed
+            }
+        };
+        framed.extend(message);
+        stream.write_all(&framed)?;
+
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response)?;
+        let (&code, rest) = response.split_first().ok_or(Error::Malformed)?;
+        if code != OK {
+            return Err(Error::from_code(code));
+        }
+        if rest.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut reader = Reader(rest);
+        let length = reader.u16()? as usize;
+        let body = reader.bytes(length)?;
+        reader.end()?;
+        Ok(body.to_vec())
+    }
+}
+
+/// Reads a Get response body as the server sends it for `version`.
+pub fn decode_page(body: &[u8], version: u8) -> Result<Page, Error> {
+    let mut reader = Reader(body);
+
+    let mut page = Page {
+        lobbies: Vec::new(),
+        page_number: 0,
+        total_pages: 0,
+        total_lobbies: None,
+        next: None,
+    };
+
+    match version {
+        // `[u16 bytes of lobbies]`, each `[u8 length][lobby]`, then
+        // `[u8 page number][u8 total pages]`.
+        0 => {
+            let length = reader.u16()? as usize;
+            let mut listed = Reader(reader.bytes(length)?);
+            while !listed.0.is_empty() {
+                let length = listed.u8()? as usize;
+                page.lobbies.push(decode_listing(listed.bytes(length)?)?);
+            }
+            page.page_number = reader.u8()? as u16;
+            page.total_pages = reader.u8()? as u16;
+        }
+        // `[u16 lobby count]`, each `[u16 length][lobby]`, then
+        // `[u16 page number][u16 total pages]`.
+        1 => {
+            page.lobbies = decode_listings(&mut reader)?;
+            page.page_number = reader.u16()?;
+            page.total_pages = reader.u16()?;
+        }
+        // `[u32 total lobbies][u16 lobby count]`, each `[u16 length][lobby]`,
+        // then `[u16 cursor length][cursor]`.
+        _ => {
+            page.total_lobbies = Some(reader.u32()?);
+            page.lobbies = decode_listings(&mut reader)?;
+            let length = reader.u16()? as usize;
+            let cursor = reader.bytes(length)?;
+            if !cursor.is_empty() {
+                page.next = Some(Cursor::try_from(cursor).map_err(|_| Error::Malformed)?);
+            }
+        }
+    }
+    reader.end()?;
+
+    Ok(page)
+}
+
+/// `[u16 lobby count]`, each `[u16 length][lobby]`.
+fn decode_listings(reader: &mut Reader) -> Result<Vec<Listing>, Error> {
+    let mut lobbies = Vec::new();
+    for _ in 0..reader.u16()? {
+        let length = reader.u16()? as usize;
+        lobbies.push(decode_listing(reader.bytes(length)?)?);
+    }
+    Ok(lobbies)
+}
+
+/// `[Flags][Region][IpV(4/6) Address][Port][Max Players][Lobby Name][Current Players]`
+fn decode_listing(fields: &[u8]) -> Result<Listing, Error> {
+    let mut reader = Reader(fields);
+    let flags = Flags::from(reader.u8()?);
+    let region = reader.u8()?.try_into().map_err(|_| Error::Malformed)?;
+    let mut address = reader.0.iter();
+    let host_ip =
+        IpAddress::from_message(&mut address, flags.is_ipv6()).map_err(|_| Error::Malformed)?;
+    reader.0 = address.as_slice();
+
+    let listing = Listing {
+        flags,
+        region,
+        host_ip,
+        host_port: reader.u16()?,
+        max_players: reader.u8()?,
+        name: reader.string()?,
+        current_players: reader.u8()?,
+    };
+    reader.end()?;
+    Ok(listing)
+}
+
+/// Takes fields off the front of a response, any that is cut short is
+/// `Malformed`.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, length: usize) -> Result<&'a [u8], Error> {
+        if self.0.len() < length {
+            return Err(Error::Malformed);
+        }
+        let (bytes, rest) = self.0.split_at(length);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, Error> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// `[u8 length][bytes]`, any invalid UTF-8 replaced.
+    fn string(&mut self) -> Result<String, Error> {
+        let length = self.u8()? as usize;
+        Ok(String::from_utf8_lossy(self.bytes(length)?).into_owned())
+    }
+
+    fn end(&self) -> Result<(), Error> {
+        match self.0 {
+            [] => Ok(()),
+            _ => Err(Error::Malformed),
+        }
+    }
+}
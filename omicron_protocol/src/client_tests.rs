@@ -0,0 +1,271 @@
+use crate::{
+    client::decode_page, request, Client, Cursor, DatabaseError, Error, Filter, Flags, GetRequest,
+    IpAddress, Listing, LobbySettings, Page, ParseError, Predicates, Region, Serialise,
+};
+use std::{
+    io::{Read, Write},
+    net::{SocketAddr, TcpListener},
+    thread::{self, JoinHandle},
+};
+
+/// Answers one request with `response`, returning the framed message it got.
+fn answer_once(response: Vec<u8>) -> (SocketAddr, JoinHandle<Vec<u8>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut length = [0];
+        stream.read_exact(&mut length).unwrap();
+        let mut message = vec![0; length[0] as usize];
+        stream.read_exact(&mut message).unwrap();
+        stream.write_all(&response).unwrap();
+        message
+    });
+    (address, server)
+}
+
+fn lobby() -> LobbySettings {
+    LobbySettings {
+        host_ip: IpAddress::IpV4([127, 0, 0, 1]),
+        host_port: 7777,
+        region: Region::Europe,
+        max_players: 8,
+        name: String::from("Lobby"),
+        public: true,
+        password: String::from("pw"),
+    }
+}
+
+fn listing() -> Listing {
+    Listing {
+        flags: Flags::new(true, true, false),
+        region: Region::Oceania,
+        host_ip: IpAddress::IpV6([0xfd00, 0, 0, 0, 0, 0, 0, 1]),
+        host_port: 7777,
+        max_players: 4,
+        name: String::from("Café"),
+        current_players: 2,
+    }
+}
+
+/// The listing's fields as the server sends them.
+fn listing_fields() -> Vec<u8> {
+    let mut fields = vec![0b011, 32];
+    fields.extend([0xfd, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+    fields.extend([0x1e, 0x61, 4, 5]);
+    fields.extend("Café".as_bytes());
+    fields.push(2);
+    fields
+}
+
+#[test]
+fn builds_requests() {
+    let mut create = vec![0x11, 0b110, 127, 0, 0, 1, 0x1e, 0x61, 4, 8, 5];
+    create.extend(b"Lobby\x02pw");
+    assert_eq!(request::create(1, &lobby()), create);
+
+    let token = [7; 16];
+    let mut modify = request::create(1, &lobby());
+    modify[0] = 0x21;
+    modify.push(3);
+    modify.extend(token);
+    assert_eq!(request::modify(1, &lobby(), 3, &token), modify);
+    // Version 0 Modify takes a password, not a token.
+    assert_eq!(request::modify(0, &lobby(), 3, &token), modify);
+
+    let mut destroy = vec![0x41, 0, 127, 0, 0, 1, 0x1e, 0x61];
+    destroy.extend(token);
+    for version in [0, 1] {
+        assert_eq!(
+            request::destroy(version, IpAddress::IpV4([127, 0, 0, 1]), 7777, &token),
+            destroy
+        );
+    }
+
+    let get = GetRequest {
+        filter: Filter::PlayerCountDescending,
+        regions: vec![Region::Asia, Region::Oceania],
+        page_num: 300,
+        search: Some(String::from("ab")),
+        page_size: Some(40),
+        cursor: Some(cursor()),
+        predicates: Predicates {
+            hide_password: true,
+            players: 1..=7,
+            ipv6: Some(true),
+            ..Predicates::default()
+        },
+    };
+    assert_eq!(
+        request::get(1, &get),
+        [0x81, 0x83, 34, 1, 44, 2, b'a', b'b']
+    );
+
+    // Version 2 sends the page size, predicates and cursor instead of the
+    // page number.
+    let mut v2 = vec![0x82, 0x83, 34, 40, 0b1010, 1, 7, 0];
+    v2.push(cursor_bytes().len() as u8);
+    v2.extend(cursor_bytes());
+    v2.extend(b"\x02ab");
+    assert_eq!(request::get(2, &get), v2);
+}
+
+fn cursor() -> Cursor {
+    Cursor {
+        filter: Filter::PlayerCountDescending,
+        lobby_name: String::from("Café"),
+        current_players: 2,
+        key: String::from("[fd00::1]:7777"),
+    }
+}
+
+/// `[u8 filter][u8 current players][u8 name length][name][key]`
+fn cursor_bytes() -> Vec<u8> {
+    let mut bytes = vec![3, 2, 5];
+    bytes.extend("Café[fd00::1]:7777".as_bytes());
+    bytes
+}
+
+#[test]
+fn cursors() {
+    assert_eq!(cursor().serialise(), cursor_bytes());
+    assert_eq!(Cursor::try_from(&cursor_bytes()[..]), Ok(cursor()));
+
+    let invalid = ParseError::InvalidCursor;
+    // Cut short, no key, a filter that does not sort, and a name that is not UTF-8.
+    assert_eq!(Cursor::try_from(&cursor_bytes()[..6]), Err(invalid));
+    assert_eq!(Cursor::try_from(&cursor_bytes()[..8]), Err(invalid));
+    assert_eq!(Cursor::try_from(&[255, 0, 0, b'k'][..]), Err(invalid));
+    assert_eq!(Cursor::try_from(&[0, 0, 1, 0xff, b'k'][..]), Err(invalid));
+}
+
+#[test]
+fn creates() {
+    let mut body = vec![9; 16];
+    body.extend(b"\x06ABC123");
+    let mut response = vec![10, 0, body.len() as u8];
+    response.extend(&body);
+
+    // Every version answers with the token and join code.
+    for version in [0, 1, 2] {
+        let (address, server) = answer_once(response.clone());
+        let client = Client::new(address).with_version(version);
+        let created = client.create(&lobby()).unwrap();
+        assert_eq!(created.token, [9; 16]);
+        assert_eq!(created.join_code, "ABC123");
+        assert_eq!(server.join().unwrap(), request::create(version, &lobby()));
+    }
+}
+
+#[test]
+fn decodes_pages() {
+    // Version 1: `[u16 count]`, each `[u16 length][lobby]`, `[u16 page][u16 pages]`.
+    let mut v1 = vec![0, 1, 0, listing_fields().len() as u8];
+    v1.extend(listing_fields());
+    v1.extend([1, 2, 0, 3]);
+    let expected = Page {
+        lobbies: vec![listing()],
+        page_number: 258,
+        total_pages: 3,
+        total_lobbies: None,
+        next: None,
+    };
+    assert_eq!(decode_page(&v1, 1).unwrap(), expected);
+
+    // Version 2: `[u32 total]`, `[u16 count]`, each `[u16 length][lobby]`,
+    // `[u16 cursor length][cursor]`.
+    let mut v2 = vec![0, 1, 0, 44, 0, 1, 0, listing_fields().len() as u8];
+    v2.extend(listing_fields());
+    v2.extend([0, cursor_bytes().len() as u8]);
+    v2.extend(cursor_bytes());
+    let page = decode_page(&v2, 2).unwrap();
+    assert_eq!(page.lobbies, [listing()]);
+    assert_eq!(
+        (page.total_lobbies, page.next),
+        (Some(65580), Some(cursor()))
+    );
+
+    // The last page has no cursor.
+    let last = decode_page(&[0, 0, 0, 0, 0, 0, 0, 0], 2).unwrap();
+    assert_eq!((last.total_lobbies, last.next), (Some(0), None));
+
+    // Version 0: `[u16 bytes]`, each `[u8 length][lobby]`, `[u8 page][u8 pages]`.
+    let mut v0 = vec![
+        0,
+        listing_fields().len() as u8 + 1,
+        listing_fields().len() as u8,
+    ];
+    v0.extend(listing_fields());
+    v0.extend([2, 3]);
+    let page = decode_page(&v0, 0).unwrap();
+    assert_eq!(page.lobbies, [listing()]);
+    assert_eq!((page.page_number, page.total_pages), (2, 3));
+
+    assert!(matches!(
+        decode_page(&v0[..v0.len() - 1], 0),
+        Err(Error::Malformed)
+    ));
+    assert!(matches!(
+        decode_page(&[0, 0, 0, 0, 0], 1),
+        Err(Error::Malformed)
+    ));
+}
+
+#[test]
+fn errors_by_code() {
+    let (address, _) = answer_once(vec![55]);
+    let token = [0; 16];
+    let refused = Client::new(address).destroy(IpAddress::default(), 1, &token);
+    assert!(matches!(
+        refused,
+        Err(Error::Database(DatabaseError::InvalidCredentials))
+    ));
+
+    assert!(matches!(
+        Error::from_code(43),
+        Error::Parse(ParseError::InvalidRegion)
+    ));
+    assert!(matches!(
+        Error::from_code(63),
+        Error::Database(DatabaseError::TooManyLobbies)
+    ));
+    assert!(matches!(Error::from_code(61), Error::RateLimited));
+    assert!(matches!(Error::from_code(62), Error::TooManyConnections));
+    assert!(matches!(Error::from_code(101), Error::TimedOut));
+    assert!(matches!(Error::from_code(99), Error::Unknown(99)));
+
+    // Named as in the README.
+    assert_eq!(Error::from_code(45).to_string(), "45 Mismatched Ip");
+    assert_eq!(Error::from_code(51).to_string(), "51 Lobby Already Exists");
+    assert_eq!(Error::from_code(99).to_string(), "99 Unknown");
+}
+
+#[test]
+fn predicates() {
+    let hide_full = Predicates {
+        hide_full: true,
+        ipv6: Some(false),
+        ..Predicates::default()
+    };
+    assert_eq!(hide_full.serialise(), [0b101, 0, 255]);
+    assert_eq!(Predicates::try_from([0b101, 0, 255]), Ok(hide_full.clone()));
+    assert_eq!(Predicates::try_from([0, 0, 255]), Ok(Predicates::default()));
+    // Both families, and bits no predicate has yet.
+    let invalid = Err(ParseError::InvalidFilter);
+    assert_eq!(Predicates::try_from([0b1100, 0, 255]), invalid);
+    assert_eq!(Predicates::try_from([0x10, 0, 255]), invalid);
+
+    let (ipv4, ipv6_with_password) = (Flags::new(false, true, false), Flags::from(0b111));
+    assert!(hide_full.allow(&ipv4, 3, 4));
+    assert!(!hide_full.allow(&ipv4, 4, 4));
+    assert!(!hide_full.allow(&ipv6_with_password, 3, 4));
+
+    let players = Predicates {
+        hide_password: true,
+        players: 2..=3,
+        ..Predicates::default()
+    };
+    assert!(players.allow(&ipv4, 2, 8) && players.allow(&ipv4, 3, 8));
+    assert!(!players.allow(&ipv4, 1, 8) && !players.allow(&ipv4, 4, 8));
+    assert!(!players.allow(&ipv6_with_password, 2, 8));
+}
@@ -0,0 +1,190 @@
+//! The lobby protocol's types, shared by the server and its clients, and a
+//! blocking client that sends Create, Modify, Destroy and Get. See the
+//! server's README for the byte layouts.
+
+mod address;
+pub mod client;
+#[cfg(test)]
+mod client_tests;
+mod message;
+pub mod request;
+
+pub use address::IpAddress;
+pub use client::{Client, Created, Error, Listing, Page};
+pub use message::{Cursor, Filter, Flags, GetRequest, Predicates, Region, Types};
+pub use request::LobbySettings;
+
+/// The length of a host token, handed out on Create.
+pub const TOKEN_SIZE: usize = 16;
+
+/// The response code of a request that succeeded.
+pub const OK: u8 = 10;
+
+pub trait Serialise {
+    fn serialise(self) -> Vec<u8>;
+}
+
+impl Serialise for String {
+    fn serialise(self) -> Vec<u8> {
+        let mut output = Vec::new();
+        output.push(self.len() as u8);
+        output.extend(self.bytes().collect::<Vec<u8>>());
+        output
+    }
+}
+
+impl Serialise for u16 {
+    fn serialise(self) -> Vec<u8> {
+        vec![(self >> 8) as u8, (self & 0xFF) as u8]
+    }
+}
+
+impl Serialise for u32 {
+    fn serialise(self) -> Vec<u8> {
+        self.to_be_bytes().to_vec()
+    }
+}
+
+impl<T: Serialise + Copy> Serialise for Vec<T> {
+    fn serialise(self) -> Vec<u8> {
+        let mut temp = Vec::new();
+        self.iter().for_each(|el| temp.extend(el.serialise()));
+        let mut output = Vec::new();
+        output.extend((temp.len() as u16).serialise());
+        output.extend(temp);
+        output
+    }
+}
+
+/// Bytes as lowercase hex digits, how tokens and cursors are written out of
+/// the binary protocol.
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// The bytes `hex` wrote, `None` unless every pair is a hex digit.
+pub fn unhex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(&hex[index..index + 2], 16).ok())
+        .collect()
+}
+
+/// What a response code means, as the README's table names it.
+pub fn describe(code: u8) -> Option<&'static str> {
+    let meaning = match code {
+        10 => "Success",
+        40 => "Empty Message",
+        41 => "Invalid Type",
+        42 => "Missing Message Part",
+        43 => "Invalid Region",
+        44 => "Invalid Name",
+        45 => "Mismatched Ip",
+        46 => "Out of Date",
+        47 => "Invalid Filter",
+        48 => "Invalid Cursor",
+        49 => "Invalid Password",
+        50 => "Not Initialised",
+        51 => "Lobby Already Exists",
+        52 => "Lobby Does Not Exist",
+        53 => "Failed to Hash Password",
+        54 => "Failed to Verify Password",
+        55 => "Invalid Credentials",
+        56 => "Invalid Filter",
+        57 => "Bad Message",
+        58 => "Storage Failure",
+        59 => "Failed to Generate Token",
+        60 => "Lobby Full",
+        61 => "Rate Limited",
+        62 => "Too Many Connections",
+        63 => "Too Many Lobbies",
+        64 => "Cookie Required",
+        101 => "Connection Timed Out",
+        _ => return None,
+    };
+    Some(meaning)
+}
+
+/// Why the server could not read a message, sent as its response code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ParseError {
+    EmptyMessage = 40,
+    InvalidType = 41,
+    MissingMessagePart = 42,
+    InvalidRegion = 43,
+    InvalidName = 44,
+    MismatchedIP = 45,
+    OutOfDate = 46,
+    InvalidFilter = 47,
+    InvalidCursor = 48,
+    /// Only the HTTP gateway, the binary protocol says `InvalidName`.
+    InvalidPassword = 49,
+    // 50+ is reserved currently
+}
+
+impl TryFrom<u8> for ParseError {
+    type Error = u8;
+
+    fn try_from(code: u8) -> Result<Self, u8> {
+        let err = match code {
+            40 => Self::EmptyMessage,
+            41 => Self::InvalidType,
+            42 => Self::MissingMessagePart,
+            43 => Self::InvalidRegion,
+            44 => Self::InvalidName,
+            45 => Self::MismatchedIP,
+            46 => Self::OutOfDate,
+            47 => Self::InvalidFilter,
+            48 => Self::InvalidCursor,
+            49 => Self::InvalidPassword,
+            _ => return Err(code),
+        };
+        Ok(err)
+    }
+}
+
+/// Why the lobby store refused a request, sent as its response code.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DatabaseError {
+    NotInitialised = 50,
+    LobbyAlreadyExists = 51,
+    LobbyDoesNotExist = 52,
+    FailedToHashPassword = 53,
+    FailedToVerifyPassword = 54,
+    InvalidCredentials = 55,
+    InvalidFilter = 56,
+    BadMessage = 57,
+    StorageFailure = 58,
+    FailedToGenerateToken = 59,
+    LobbyFull = 60,
+    // 61 and 62 are the limiter's refusals, 64 asks a UDP client for a cookie.
+    TooManyLobbies = 63,
+}
+
+impl TryFrom<u8> for DatabaseError {
+    type Error = u8;
+
+    fn try_from(code: u8) -> Result<Self, u8> {
+        let err = match code {
+            50 => Self::NotInitialised,
+            51 => Self::LobbyAlreadyExists,
+            52 => Self::LobbyDoesNotExist,
+            53 => Self::FailedToHashPassword,
+            54 => Self::FailedToVerifyPassword,
+            55 => Self::InvalidCredentials,
+            56 => Self::InvalidFilter,
+            57 => Self::BadMessage,
+            58 => Self::StorageFailure,
+            59 => Self::FailedToGenerateToken,
+            60 => Self::LobbyFull,
+            63 => Self::TooManyLobbies,
+            _ => return Err(code),
+        };
+        Ok(err)
+    }
+}
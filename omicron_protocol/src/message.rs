@@ -0,0 +1,385 @@
+use crate::{ParseError, Serialise};
+use std::{ops::RangeInclusive, str::FromStr};
+
+/// A message's type, in the high nibble of its first byte.
+#[repr(u8)]
+pub enum Types {
+    None = 0x0,
+    Create = 0x1,
+    Modify = 0x2,
+    Heartbeat = 0x3,
+    Destroy = 0x4,
+    Rotate = 0x5,
+    Lookup = 0x6,
+    Join = 0x7,
+    Get = 0x8,
+    Session = 0x9,
+    Subscribe = 0xA,
+}
+
+impl From<u8> for Types {
+    fn from(value: u8) -> Self {
+        match value {
+            0x1 => Self::Create,
+            0x2 => Self::Modify,
+            0x3 => Self::Heartbeat,
+            0x4 => Self::Destroy,
+            0x5 => Self::Rotate,
+            0x6 => Self::Lookup,
+            0x7 => Self::Join,
+            0x8 => Self::Get,
+            0x9 => Self::Session,
+            0xA => Self::Subscribe,
+            _ => Self::None,
+        }
+    }
+}
+
+impl From<Types> for u8 {
+    fn from(value: Types) -> Self {
+        value as u8
+    }
+}
+
+#[derive(Default, PartialEq, Eq, Debug, Clone)]
+pub struct Flags {
+    is_ipv6: bool,
+    is_public: bool,
+    has_password: bool,
+}
+
+impl Serialise for Flags {
+    fn serialise(self) -> Vec<u8> {
+        let mut output = 0;
+        if self.is_ipv6 {
+            output |= 1;
+        }
+        if self.is_public {
+            output |= 2;
+        }
+        if self.has_password {
+            output |= 4;
+        }
+        vec![output]
+    }
+}
+
+impl Flags {
+    pub fn is_ipv6(&self) -> bool {
+        self.is_ipv6
+    }
+
+    pub fn is_public(&self) -> bool {
+        self.is_public
+    }
+
+    pub fn has_password(&self) -> bool {
+        self.has_password
+    }
+}
+
+impl Flags {
+    pub fn new(is_ipv6: bool, is_public: bool, has_password: bool) -> Self {
+        Self {
+            is_ipv6,
+            is_public,
+            has_password,
+        }
+    }
+}
+
+impl From<u8> for Flags {
+    fn from(value: u8) -> Self {
+        Self {
+            is_ipv6: value & 0x1 != 0,
+            is_public: value & 0x2 != 0,
+            has_password: value & 0x4 != 0,
+        }
+    }
+}
+
+#[repr(u8)]
+#[derive(Default, Debug, PartialEq, Eq, Clone)]
+pub enum Region {
+    #[default]
+    Africa = 1,
+    Asia = 2,
+    Europe = 4,
+    NorthAmerica = 8,
+    SouthAmerica = 16,
+    Oceania = 32,
+}
+
+impl Serialise for Region {
+    fn serialise(self) -> Vec<u8> {
+        vec![self as u8]
+    }
+}
+
+impl TryInto<Region> for u8 {
+    type Error = ParseError;
+
+    fn try_into(self) -> Result<Region, Self::Error> {
+        let region = match self {
+            1 => Region::Africa,
+            2 => Region::Asia,
+            4 => Region::Europe,
+            8 => Region::NorthAmerica,
+            16 => Region::SouthAmerica,
+            32 => Region::Oceania,
+            _ => Err(ParseError::InvalidRegion)?,
+        };
+
+        Ok(region)
+    }
+}
+
+impl FromStr for Region {
+    type Err = ParseError;
+
+    /// The region with this `name`.
+    fn from_str(name: &str) -> Result<Self, ParseError> {
+        Region::get_regions(u8::MAX)
+            .into_iter()
+            .find(|region| region.name() == name)
+            .ok_or(ParseError::InvalidRegion)
+    }
+}
+
+impl Region {
+    /// The region as a metrics label.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Region::Africa => "africa",
+            Region::Asia => "asia",
+            Region::Europe => "europe",
+            Region::NorthAmerica => "north_america",
+            Region::SouthAmerica => "south_america",
+            Region::Oceania => "oceania",
+        }
+    }
+
+    pub fn get_regions(value: u8) -> Vec<Region> {
+        let mut output = Vec::new();
+
+        if value & 1 == 1 {
+            output.push(Region::Africa);
+        }
+        if value & 2 == 2 {
+            output.push(Region::Asia);
+        }
+        if value & 4 == 4 {
+            output.push(Region::Europe);
+        }
+        if value & 8 == 8 {
+            output.push(Region::NorthAmerica);
+        }
+        if value & 16 == 16 {
+            output.push(Region::SouthAmerica);
+        }
+        if value & 32 == 32 {
+            output.push(Region::Oceania);
+        }
+
+        if output.is_empty() {
+            output = vec![
+                Region::Africa,
+                Region::Asia,
+                Region::Europe,
+                Region::NorthAmerica,
+                Region::SouthAmerica,
+                Region::Oceania,
+            ];
+        }
+
+        output
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct GetRequest {
+    pub filter: Filter,
+    pub regions: Vec<Region>,
+    /// Versions 0 and 1, version 2 pages with `cursor` instead.
+    pub page_num: u16,
+    pub search: Option<String>,
+    /// Lobbies per page, the server's default if `None`. Version 2 only.
+    pub page_size: Option<u8>,
+    /// Where the previous page ended, the first page if `None`. Version 2 only.
+    pub cursor: Option<Cursor>,
+    /// Version 2 only, earlier versions list every lobby.
+    pub predicates: Predicates,
+}
+
+/// Where a page of Get ended: the sort value of its last lobby and that
+/// lobby's key. The next page starts right after it, so lobbies coming and
+/// going in between do not shift the pages. Clients send it back as they got
+/// it, its layout is the server's business:
+/// `[u8 filter][u8 current players][u8 name length][name][key]`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Cursor {
+    pub filter: Filter,
+    pub lobby_name: String,
+    pub current_players: u8,
+    /// The host's `ip:port`, which orders lobbies sorting alike.
+    pub key: String,
+}
+
+impl Serialise for &Cursor {
+    fn serialise(self) -> Vec<u8> {
+        let mut output = vec![self.filter as u8, self.current_players];
+        output.extend(self.lobby_name.clone().serialise());
+        output.extend(self.key.as_bytes());
+        output
+    }
+}
+
+impl TryFrom<&[u8]> for Cursor {
+    type Error = ParseError;
+
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        let (&[filter, current_players, name_length], rest) =
+            bytes.split_first_chunk().ok_or(ParseError::InvalidCursor)?;
+        let (name, key) = rest
+            .split_at_checked(name_length as usize)
+            .ok_or(ParseError::InvalidCursor)?;
+        let filter = match filter.try_into() {
+            Ok(Filter::Search) | Err(_) => return Err(ParseError::InvalidCursor),
+            Ok(filter) => filter,
+        };
+        let string = |bytes| {
+            std::str::from_utf8(bytes)
+                .map(str::to_owned)
+                .map_err(|_| ParseError::InvalidCursor)
+        };
+        if key.is_empty() {
+            return Err(ParseError::InvalidCursor);
+        }
+
+        Ok(Self {
+            filter,
+            lobby_name: string(name)?,
+            current_players,
+            key: string(key)?,
+        })
+    }
+}
+
+/// What a Get leaves out besides other regions and names: a lobby is listed
+/// only if it passes every one. The default passes every lobby. Sent as
+/// `[u8 bits][u8 fewest players][u8 most players]`, the bits being 1 to hide
+/// full lobbies, 2 to hide lobbies with a password, 4 for IPv4 hosts only and
+/// 8 for IPv6 hosts only.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Predicates {
+    pub hide_full: bool,
+    pub hide_password: bool,
+    /// The current player counts to list.
+    pub players: RangeInclusive<u8>,
+    /// IPv6 hosts only if `Some(true)`, IPv4 hosts only if `Some(false)`.
+    pub ipv6: Option<bool>,
+}
+
+impl Default for Predicates {
+    fn default() -> Self {
+        Self {
+            hide_full: false,
+            hide_password: false,
+            players: 0..=u8::MAX,
+            ipv6: None,
+        }
+    }
+}
+
+impl Serialise for &Predicates {
+    fn serialise(self) -> Vec<u8> {
+        let bits = self.hide_full as u8
+            | (self.hide_password as u8) << 1
+            | match self.ipv6 {
+                None => 0,
+                Some(false) => 4,
+                Some(true) => 8,
+            };
+        vec![bits, *self.players.start(), *self.players.end()]
+    }
+}
+
+impl TryFrom<[u8; 3]> for Predicates {
+    type Error = ParseError;
+
+    /// Unknown bits, or both families, are an `InvalidFilter`.
+    fn try_from([bits, fewest, most]: [u8; 3]) -> Result<Self, Self::Error> {
+        let ipv6 = match bits & 0b1100 {
+            0 => None,
+            4 => Some(false),
+            8 => Some(true),
+            _ => return Err(ParseError::InvalidFilter),
+        };
+        if bits & !0b1111 != 0 {
+            return Err(ParseError::InvalidFilter);
+        }
+        Ok(Self {
+            hide_full: bits & 1 != 0,
+            hide_password: bits & 2 != 0,
+            players: fewest..=most,
+            ipv6,
+        })
+    }
+}
+
+impl Predicates {
+    /// Whether a lobby with these fields passes every predicate.
+    pub fn allow(&self, flags: &Flags, current_players: u8, max_players: u8) -> bool {
+        (!self.hide_full || current_players < max_players)
+            && (!self.hide_password || !flags.has_password())
+            && self.players.contains(&current_players)
+            && self.ipv6.is_none_or(|ipv6| flags.is_ipv6() == ipv6)
+    }
+}
+
+impl GetRequest {
+    /// Whether the request would list a lobby with these fields, on any page.
+    pub fn matches(
+        &self,
+        flags: &Flags,
+        region: &Region,
+        lobby_name: &str,
+        current_players: u8,
+        max_players: u8,
+    ) -> bool {
+        flags.is_public()
+            && self.regions.contains(region)
+            && self.predicates.allow(flags, current_players, max_players)
+            && self
+                .search
+                .as_ref()
+                .is_none_or(|search| lobby_name.to_lowercase().contains(&search.to_lowercase()))
+    }
+}
+
+#[repr(u8)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Filter {
+    NameAscending = 0,
+    NameDescending = 1,
+    PlayerCountAscending = 2,
+    PlayerCountDescending = 3,
+    Search = 255,
+}
+
+impl TryInto<Filter> for u8 {
+    type Error = ParseError;
+
+    fn try_into(self) -> Result<Filter, Self::Error> {
+        let filter = match self {
+            0 => Filter::NameAscending,
+            1 => Filter::NameDescending,
+            2 => Filter::PlayerCountAscending,
+            3 => Filter::PlayerCountDescending,
+            255 => Filter::Search,
+            _ => Err(ParseError::InvalidFilter)?,
+        };
+
+        Ok(filter)
+    }
+}
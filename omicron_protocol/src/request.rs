@@ -0,0 +1,110 @@
+//! Request messages as the server parses them, without the TCP length
+//! prefix. Every version lays these out alike, except that version 1 sends
+//! the Get page number as a `u16` and reads strings as UTF-8, and version 2
+//! sends a page size, predicates and cursor in its place. Modify and Destroy
+//! carry the host token, which version 0 has no room for, so they go as
+//! version 1 at least.
+
+use crate::{Flags, GetRequest, IpAddress, Region, Serialise, Types, TOKEN_SIZE};
+
+/// A lobby as its host describes it in a Create or Modify.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LobbySettings {
+    /// Must be the address the server sees the request come from.
+    pub host_ip: IpAddress,
+    pub host_port: u16,
+    pub region: Region,
+    pub max_players: u8,
+    pub name: String,
+    pub public: bool,
+    /// Empty for a lobby anyone may join.
+    pub password: String,
+}
+
+fn type_byte(message_type: Types, version: u8) -> u8 {
+    (u8::from(message_type) << 4) | (version & 0xF)
+}
+
+/// `[Flags][IpV(4/6) Address][Port][Region][Max Players][Lobby Name][Password]`
+fn lobby_fields(lobby: &LobbySettings) -> Vec<u8> {
+    let flags = Flags::new(
+        matches!(lobby.host_ip, IpAddress::IpV6(_)),
+        lobby.public,
+        !lobby.password.is_empty(),
+    );
+    let mut output = flags.serialise();
+    output.extend(lobby.host_ip.serialise());
+    output.extend(lobby.host_port.serialise());
+    output.extend(lobby.region.clone().serialise());
+    output.push(lobby.max_players);
+    output.extend(lobby.name.clone().serialise());
+    output.extend(lobby.password.clone().serialise());
+    output
+}
+
+/// `[IpV][IpV(4/6) Address][Port]`
+fn host(host_ip: IpAddress, port: u16) -> Vec<u8> {
+    let mut output = vec![matches!(host_ip, IpAddress::IpV6(_)) as u8];
+    output.extend(host_ip.serialise());
+    output.extend(port.serialise());
+    output
+}
+
+pub fn create(version: u8, lobby: &LobbySettings) -> Vec<u8> {
+    let mut message = vec![type_byte(Types::Create, version)];
+    message.extend(lobby_fields(lobby));
+    message
+}
+
+pub fn modify(
+    version: u8,
+    lobby: &LobbySettings,
+    current_players: u8,
+    token: &[u8; TOKEN_SIZE],
+) -> Vec<u8> {
+    let mut message = vec![type_byte(Types::Modify, version.max(1))];
+    message.extend(lobby_fields(lobby));
+    message.push(current_players);
+    message.extend(token);
+    message
+}
+
+pub fn destroy(version: u8, host_ip: IpAddress, port: u16, token: &[u8; TOKEN_SIZE]) -> Vec<u8> {
+    let mut message = vec![type_byte(Types::Destroy, version.max(1))];
+    message.extend(host(host_ip, port));
+    message.extend(token);
+    message
+}
+
+/// The search is sent whenever `request.search` is set, `Filter::Search`
+/// cannot be sent. Version 0 only has room for pages below 256, version 2
+/// sends `page_size` and `cursor` instead of the page number.
+pub fn get(version: u8, request: &GetRequest) -> Vec<u8> {
+    let search_bit = if request.search.is_some() { 0x80 } else { 0 };
+    let regions = request
+        .regions
+        .iter()
+        .fold(0, |regions, region| regions | region.clone() as u8);
+
+    let mut message = vec![
+        type_byte(Types::Get, version),
+        (request.filter as u8 & 0x7F) | search_bit,
+        regions,
+    ];
+    match version {
+        0 => message.push(request.page_num as u8),
+        1 => message.extend(request.page_num.serialise()),
+        _ => {
+            message.push(request.page_size.unwrap_or(0));
+            message.extend(request.predicates.serialise());
+            let cursor = request.cursor.as_ref().map(Serialise::serialise);
+            let cursor = cursor.unwrap_or_default();
+            message.extend((cursor.len() as u16).serialise());
+            message.extend(cursor);
+        }
+    }
+    if let Some(search) = &request.search {
+        message.extend(search.clone().serialise());
+    }
+    message
+}
@@ -0,0 +1,293 @@
+//! The command line: global options, then a command and its options, in any
+//! order.
+
+use omicron_protocol::{
+    unhex, Cursor, Filter, GetRequest, IpAddress, LobbySettings, Predicates, Region, TOKEN_SIZE,
+};
+use std::{fmt, net::SocketAddr};
+
+pub const USAGE: &str = "\
+Usage: omicron [OPTIONS] <COMMAND>
+
+Commands:
+  list                     List public lobbies
+  create                   Create a lobby, printing its host token and join code
+  modify                   Replace every setting of a lobby
+  destroy                  Remove a lobby
+
+Options:
+  -s, --server <ADDRESS>   The lobby server [default: 127.0.0.1:5475]
+      --protocol <VERSION> The protocol version to speak, 0 to 2 [default: 2]
+      --json               Print JSON rather than a table
+  -h, --help               Print this help
+
+List options:
+  -r, --region <REGION>    africa, asia, europe, north-america, south-america or
+                           oceania, may be repeated [default: every region]
+      --sort <ORDER>       name-asc, name-desc, players-asc or players-desc
+                           [default: name-asc]
+      --search <TEXT>      Only lobbies whose name contains TEXT
+      --hide-full          Leave out lobbies with no room left
+      --hide-password      Leave out lobbies that need a password
+      --fewest-players <N> Only lobbies with at least N players
+      --most-players <N>   Only lobbies with at most N players
+      --family <FAMILY>    Only hosts on ipv4 or ipv6 [default: either]
+      --page-size <N>      Lobbies per page, within the server's limit [default:
+                           the server's page size]
+      --cursor <HEX>       Start after this `next` of an earlier list, which
+                           also picks its sort [default: the first page]
+      --page <PAGE>        The page, from 0, instead of --page-size and --cursor
+                           for protocol 0 and 1 [default: 0]
+
+Lobby options:
+      --port <PORT>        The game's port, which with the host picks the lobby
+      --host <IP>          The host's ip, as the server sees it [default: this
+                           machine's address towards the server]
+  -r, --region <REGION>    Where the lobby is, for create and modify
+      --max-players <N>    For create and modify
+      --name <NAME>        For create and modify
+      --password <TEXT>    Players need it to join [default: none]
+      --private            Leave the lobby out of lists, it is found by join code
+      --players <N>        Players in the lobby, for modify [default: 1]
+      --token <HEX>        The host token create printed, for modify and destroy
+";
+
+#[derive(Debug, PartialEq)]
+pub struct UsageError(pub String);
+
+impl fmt::Display for UsageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}\n\nRun with --help for usage.", self.0)
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    List(GetRequest),
+    Create(LobbySettings),
+    Modify {
+        lobby: LobbySettings,
+        players: u8,
+        token: [u8; TOKEN_SIZE],
+    },
+    Destroy {
+        port: u16,
+        token: [u8; TOKEN_SIZE],
+    },
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Args {
+    pub server: SocketAddr,
+    pub version: u8,
+    pub json: bool,
+    pub help: bool,
+    /// The lobby host, found from the connection to the server if `None`.
+    /// Lobbies in `command` are given `IpAddress::default()` until it is.
+    pub host: Option<IpAddress>,
+    /// `None` only with `help`.
+    pub command: Option<Command>,
+}
+
+/// Everything that came after a flag, before it is checked against the command.
+#[derive(Default)]
+struct Options {
+    command: Option<String>,
+    regions: Vec<Region>,
+    sort: Option<Filter>,
+    search: Option<String>,
+    page: Option<u16>,
+    page_size: Option<u8>,
+    cursor: Option<Cursor>,
+    predicates: Predicates,
+    port: Option<u16>,
+    max_players: Option<u8>,
+    name: Option<String>,
+    password: Option<String>,
+    private: bool,
+    players: Option<u8>,
+    token: Option<[u8; TOKEN_SIZE]>,
+}
+
+impl Args {
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Self, UsageError> {
+        let mut parsed = Self {
+            server: SocketAddr::from(([127, 0, 0, 1], 5475)),
+            version: omicron_protocol::client::LATEST_VERSION,
+            json: false,
+            help: false,
+            host: None,
+            command: None,
+        };
+        let mut options = Options::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| UsageError(format!("{arg} needs a value")))
+            };
+            match arg.as_str() {
+                "-s" | "--server" => parsed.server = parse(&value()?, "an address and port")?,
+                "--protocol" => {
+                    parsed.version = match value()?.as_str() {
+                        "0" => 0,
+                        "1" => 1,
+                        "2" => 2,
+                        version => return Err(UsageError(format!("{version:?} is not 0 to 2"))),
+                    }
+                }
+                "--json" => parsed.json = true,
+                "-h" | "--help" => parsed.help = true,
+                "-r" | "--region" => options.regions.push(region(&value()?)?),
+                "--sort" => options.sort = Some(sort(&value()?)?),
+                "--search" => options.search = Some(value()?),
+                "--page" => options.page = Some(parse(&value()?, "a page number")?),
+                "--page-size" => options.page_size = Some(parse(&value()?, "a page size")?),
+                "--cursor" => options.cursor = Some(cursor(&value()?)?),
+                "--hide-full" => options.predicates.hide_full = true,
+                "--hide-password" => options.predicates.hide_password = true,
+                "--fewest-players" => {
+                    let fewest = parse(&value()?, "a player count")?;
+                    let most = *options.predicates.players.end();
+                    options.predicates.players = fewest..=most;
+                }
+                "--most-players" => {
+                    let most = parse(&value()?, "a player count")?;
+                    let fewest = *options.predicates.players.start();
+                    options.predicates.players = fewest..=most;
+                }
+                "--family" => options.predicates.ipv6 = Some(family(&value()?)?),
+                "--port" => options.port = Some(parse(&value()?, "a port")?),
+                "--host" => {
+                    let host: std::net::IpAddr = parse(&value()?, "an ip address")?;
+                    parsed.host = Some(host.into());
+                }
+                "--max-players" => options.max_players = Some(parse(&value()?, "a player count")?),
+                "--name" => options.name = Some(value()?),
+                "--password" => options.password = Some(value()?),
+                "--private" => options.private = true,
+                "--players" => options.players = Some(parse(&value()?, "a player count")?),
+                "--token" => options.token = Some(token(&value()?)?),
+                _ if arg.starts_with('-') => {
+                    return Err(UsageError(format!("unknown option {arg:?}")));
+                }
+                _ if options.command.is_some() => {
+                    return Err(UsageError(format!("unexpected argument {arg:?}")));
+                }
+                _ => options.command = Some(arg),
+            }
+        }
+
+        if !parsed.help {
+            parsed.command = Some(options.command()?);
+        }
+        Ok(parsed)
+    }
+}
+
+impl Options {
+    fn command(mut self) -> Result<Command, UsageError> {
+        let command = match self.command.take().as_deref() {
+            Some("list") => Command::List(GetRequest {
+                filter: self
+                    .sort
+                    .or(self.cursor.as_ref().map(|cursor| cursor.filter))
+                    .unwrap_or(Filter::NameAscending),
+                regions: match self.regions.is_empty() {
+                    true => Region::get_regions(0),
+                    false => self.regions,
+                },
+                page_num: self.page.unwrap_or(0),
+                search: self.search,
+                page_size: self.page_size,
+                cursor: self.cursor,
+                predicates: self.predicates,
+            }),
+            Some("create") => Command::Create(self.lobby()?),
+            Some("modify") => Command::Modify {
+                players: self.players.unwrap_or(1),
+                token: required(self.token, "--token")?,
+                lobby: self.lobby()?,
+            },
+            Some("destroy") => Command::Destroy {
+                port: required(self.port, "--port")?,
+                token: required(self.token, "--token")?,
+            },
+            Some(command) => return Err(UsageError(format!("unknown command {command:?}"))),
+            None => return Err(UsageError(String::from("a command is needed"))),
+        };
+        Ok(command)
+    }
+
+    fn lobby(self) -> Result<LobbySettings, UsageError> {
+        let region = match self.regions[..] {
+            [ref region] => region.clone(),
+            [] => return Err(UsageError(String::from("--region is needed"))),
+            _ => return Err(UsageError(String::from("a lobby has one --region"))),
+        };
+        Ok(LobbySettings {
+            host_ip: IpAddress::default(),
+            host_port: required(self.port, "--port")?,
+            region,
+            max_players: required(self.max_players, "--max-players")?,
+            name: required(self.name, "--name")?,
+            public: !self.private,
+            password: self.password.unwrap_or_default(),
+        })
+    }
+}
+
+fn required<T>(value: Option<T>, option: &str) -> Result<T, UsageError> {
+    value.ok_or_else(|| UsageError(format!("{option} is needed")))
+}
+
+fn parse<T: std::str::FromStr>(value: &str, what: &str) -> Result<T, UsageError> {
+    value
+        .parse()
+        .map_err(|_| UsageError(format!("{value:?} is not {what}")))
+}
+
+/// Names as the server logs them, with dashes or underscores.
+fn region(name: &str) -> Result<Region, UsageError> {
+    name.to_lowercase()
+        .replace('-', "_")
+        .parse()
+        .map_err(|_| UsageError(format!("{name:?} is not a region")))
+}
+
+fn sort(order: &str) -> Result<Filter, UsageError> {
+    match order {
+        "name-asc" => Ok(Filter::NameAscending),
+        "name-desc" => Ok(Filter::NameDescending),
+        "players-asc" => Ok(Filter::PlayerCountAscending),
+        "players-desc" => Ok(Filter::PlayerCountDescending),
+        _ => Err(UsageError(format!("{order:?} is not a sort order"))),
+    }
+}
+
+/// Whether the family is IPv6.
+fn family(name: &str) -> Result<bool, UsageError> {
+    match name.to_lowercase().as_str() {
+        "ipv4" => Ok(false),
+        "ipv6" => Ok(true),
+        _ => Err(UsageError(format!("{name:?} is not ipv4 or ipv6"))),
+    }
+}
+
+fn token(hex: &str) -> Result<[u8; TOKEN_SIZE], UsageError> {
+    unhex(hex)
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| {
+            UsageError(format!(
+                "{hex:?} is not a token of {} hex digits",
+                TOKEN_SIZE * 2
+            ))
+        })
+}
+
+fn cursor(hex: &str) -> Result<Cursor, UsageError> {
+    unhex(hex)
+        .and_then(|bytes| Cursor::try_from(&bytes[..]).ok())
+        .ok_or_else(|| UsageError(format!("{hex:?} is not a cursor a list printed")))
+}
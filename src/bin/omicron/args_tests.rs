@@ -0,0 +1,197 @@
+use super::args::{Args, Command, UsageError};
+use omicron_protocol::{Cursor, Filter, GetRequest, IpAddress, LobbySettings, Predicates, Region};
+
+fn args(args: &[&str]) -> Result<Args, UsageError> {
+    Args::parse(args.iter().map(|arg| arg.to_string()))
+}
+
+fn command(arguments: &[&str]) -> Command {
+    args(arguments).unwrap().command.unwrap()
+}
+
+fn error(arguments: &[&str]) -> String {
+    args(arguments).unwrap_err().0
+}
+
+#[test]
+fn list() {
+    assert_eq!(
+        command(&["list"]),
+        Command::List(GetRequest {
+            filter: Filter::NameAscending,
+            regions: Region::get_regions(0),
+            page_num: 0,
+            search: None,
+            page_size: None,
+            cursor: None,
+            predicates: Predicates::default(),
+        })
+    );
+    assert_eq!(
+        command(&[
+            "--region",
+            "europe",
+            "list",
+            "-r",
+            "North-America",
+            "--sort",
+            "players-desc",
+            "--search",
+            "foo",
+            "--page",
+            "2",
+        ]),
+        Command::List(GetRequest {
+            filter: Filter::PlayerCountDescending,
+            regions: vec![Region::Europe, Region::NorthAmerica],
+            page_num: 2,
+            search: Some(String::from("foo")),
+            page_size: None,
+            cursor: None,
+            predicates: Predicates::default(),
+        })
+    );
+
+    // The cursor brings its sort along.
+    let Command::List(request) =
+        command(&["list", "--page-size", "50", "--cursor", "030102616277"])
+    else {
+        panic!("not a list");
+    };
+    let cursor = Cursor {
+        filter: Filter::PlayerCountDescending,
+        lobby_name: String::from("ab"),
+        current_players: 1,
+        key: String::from("w"),
+    };
+    assert_eq!(request.filter, Filter::PlayerCountDescending);
+    assert_eq!(
+        (request.page_size, request.cursor),
+        (Some(50), Some(cursor))
+    );
+    let Command::List(request) = command(&[
+        "list",
+        "--hide-full",
+        "--most-players",
+        "6",
+        "--fewest-players",
+        "2",
+        "--family",
+        "IPv6",
+    ]) else {
+        panic!("not a list");
+    };
+    assert_eq!(
+        request.predicates,
+        Predicates {
+            hide_full: true,
+            hide_password: false,
+            players: 2..=6,
+            ipv6: Some(true),
+        }
+    );
+    assert_eq!(
+        error(&["list", "--family", "ipx"]),
+        "\"ipx\" is not ipv4 or ipv6"
+    );
+    assert_eq!(
+        error(&["list", "--cursor", "ff00"]),
+        "\"ff00\" is not a cursor a list printed"
+    );
+    assert_eq!(
+        error(&["list", "--sort", "random"]),
+        "\"random\" is not a sort order"
+    );
+    assert_eq!(error(&["list", "-r", "mars"]), "\"mars\" is not a region");
+}
+
+#[test]
+fn lobbies() {
+    let parsed = args(&[
+        "create",
+        "-s",
+        "[::1]:6000",
+        "--host",
+        "::1",
+        "--port",
+        "7777",
+        "--region",
+        "oceania",
+        "--max-players",
+        "8",
+        "--name",
+        "My lobby",
+        "--private",
+    ])
+    .unwrap();
+    assert_eq!(parsed.server, "[::1]:6000".parse().unwrap());
+    assert_eq!(parsed.host, Some(IpAddress::IpV6([0, 0, 0, 0, 0, 0, 0, 1])));
+    let lobby = LobbySettings {
+        host_ip: IpAddress::default(),
+        host_port: 7777,
+        region: Region::Oceania,
+        max_players: 8,
+        name: String::from("My lobby"),
+        public: false,
+        password: String::new(),
+    };
+    assert_eq!(parsed.command, Some(Command::Create(lobby.clone())));
+
+    let token = "000102030405060708090a0b0c0d0e0f";
+    let modify = [
+        "modify",
+        "--port",
+        "7777",
+        "-r",
+        "oceania",
+        "--max-players",
+        "8",
+        "--name",
+        "My lobby",
+        "--private",
+        "--players",
+        "3",
+        "--token",
+        token,
+    ];
+    let expected_token: [u8; 16] = std::array::from_fn(|index| index as u8);
+    assert_eq!(
+        command(&modify),
+        Command::Modify {
+            lobby,
+            players: 3,
+            token: expected_token,
+        }
+    );
+    assert_eq!(
+        command(&["destroy", "--port", "7777", "--token", token]),
+        Command::Destroy {
+            port: 7777,
+            token: expected_token,
+        }
+    );
+
+    assert_eq!(error(&["create", "--port", "1"]), "--region is needed");
+    assert_eq!(error(&["destroy", "--port", "1"]), "--token is needed");
+    assert_eq!(
+        error(&["destroy", "--port", "1", "--token", "abc"]),
+        "\"abc\" is not a token of 32 hex digits"
+    );
+}
+
+#[test]
+fn options() {
+    let parsed = args(&["--protocol", "0", "--json", "list"]).unwrap();
+    assert_eq!((parsed.version, parsed.json), (0, true));
+    assert!(args(&["--help"]).unwrap().help);
+
+    assert_eq!(error(&[]), "a command is needed");
+    assert_eq!(error(&["join"]), "unknown command \"join\"");
+    assert_eq!(error(&["list", "extra"]), "unexpected argument \"extra\"");
+    assert_eq!(
+        error(&["list", "--verbose"]),
+        "unknown option \"--verbose\""
+    );
+    assert_eq!(error(&["list", "--page"]), "--page needs a value");
+    assert_eq!(error(&["--protocol", "3", "list"]), "\"3\" is not 0 to 2");
+}
@@ -0,0 +1,100 @@
+//! A command line client for the lobby server, for debugging and scripts.
+
+use args::{Args, Command};
+use omicron_protocol::{hex, Client, Created, Error, Page};
+use serde_json::json;
+use std::{
+    env,
+    io::{self, Write},
+    process::ExitCode,
+};
+
+mod args;
+#[cfg(test)]
+mod args_tests;
+mod output;
+#[cfg(test)]
+mod output_tests;
+
+fn main() -> ExitCode {
+    let args = match Args::parse(env::args().skip(1)) {
+        Ok(args) if args.help => {
+            print!("{}", args::USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("{err}");
+            return ExitCode::from(2);
+        }
+    };
+
+    match run(args) {
+        Ok(output) => {
+            let mut stdout = io::stdout().lock();
+            // A closed pipe, such as `| head`, is not worth reporting.
+            let _ = stdout.write_all(output.as_bytes());
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            eprintln!("error: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Sends the command, returning what to print.
+fn run(args: Args) -> Result<String, Error> {
+    let client = Client::new(args.server).with_version(args.version);
+    let host = match args.host {
+        Some(host) => host,
+        None => client.local_address()?,
+    };
+
+    let output = match args.command.expect("parsed without --help") {
+        Command::List(request) => {
+            let page: Page = client.get(&request)?;
+            match args.json {
+                true => output::page_json(&page).to_string(),
+                false => output::page_table(&page),
+            }
+        }
+        Command::Create(mut lobby) => {
+            lobby.host_ip = host;
+            let created: Created = client.create(&lobby)?;
+            match args.json {
+                true => json!({
+                    "token": hex(&created.token),
+                    "join_code": created.join_code,
+                })
+                .to_string(),
+                false => format!(
+                    "token: {}\njoin code: {}",
+                    hex(&created.token),
+                    created.join_code
+                ),
+            }
+        }
+        Command::Modify {
+            mut lobby,
+            players,
+            token,
+        } => {
+            lobby.host_ip = host;
+            client.modify(&lobby, players, &token)?;
+            done(args.json, "Lobby modified")
+        }
+        Command::Destroy { port, token } => {
+            client.destroy(host, port, &token)?;
+            done(args.json, "Lobby destroyed")
+        }
+    };
+    Ok(output + "\n")
+}
+
+fn done(json: bool, message: &str) -> String {
+    match json {
+        true => json!({}).to_string(),
+        false => message.to_string(),
+    }
+}
@@ -0,0 +1,97 @@
+//! Pages and tokens as printed, a table for people or JSON for scripts.
+
+use omicron_protocol::{hex, Listing, Page, Serialise};
+use serde_json::{json, Value};
+use std::{fmt::Write, net::IpAddr};
+
+fn host(listing: &Listing) -> String {
+    match IpAddr::from(listing.host_ip) {
+        IpAddr::V4(ip) => format!("{ip}:{}", listing.host_port),
+        IpAddr::V6(ip) => format!("[{ip}]:{}", listing.host_port),
+    }
+}
+
+/// The fields the HTTP gateway lists lobbies with.
+pub fn page_json(page: &Page) -> Value {
+    let lobbies: Vec<Value> = page
+        .lobbies
+        .iter()
+        .map(|listing| {
+            json!({
+                "name": listing.name,
+                "region": listing.region.name(),
+                "host_ip": IpAddr::from(listing.host_ip),
+                "port": listing.host_port,
+                "max_players": listing.max_players,
+                "current_players": listing.current_players,
+                "public": listing.flags.is_public(),
+                "has_password": listing.flags.has_password(),
+            })
+        })
+        .collect();
+    match page.total_lobbies {
+        Some(total_lobbies) => json!({
+            "lobbies": lobbies,
+            "total_lobbies": total_lobbies,
+            "next": page.next.as_ref().map(|cursor| hex(&cursor.serialise())),
+        }),
+        None => json!({
+            "lobbies": lobbies,
+            "page": page.page_number,
+            "total_pages": page.total_pages,
+        }),
+    }
+}
+
+pub fn page_table(page: &Page) -> String {
+    let rows: Vec<[String; 5]> = page
+        .lobbies
+        .iter()
+        .map(|listing| {
+            [
+                listing.name.clone(),
+                listing.region.name().to_string(),
+                host(listing),
+                format!("{}/{}", listing.current_players, listing.max_players),
+                String::from(if listing.flags.has_password() {
+                    "yes"
+                } else {
+                    "no"
+                }),
+            ]
+        })
+        .collect();
+
+    let header = ["NAME", "REGION", "HOST", "PLAYERS", "PASSWORD"].map(String::from);
+    let mut widths = header.clone().map(|title| title.chars().count());
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let mut table = String::new();
+    for row in std::iter::once(&header).chain(&rows) {
+        let line: Vec<String> = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{cell:width$}"))
+            .collect();
+        let _ = writeln!(table, "{}", line.join("  ").trim_end());
+    }
+    // As the server counts them, see the README's Get response.
+    let _ = match (page.total_lobbies, &page.next) {
+        (Some(total), Some(next)) => write!(
+            table,
+            "total lobbies: {total}, next: {}",
+            hex(&next.serialise())
+        ),
+        (Some(total), None) => write!(table, "total lobbies: {total}, last page"),
+        (None, _) => write!(
+            table,
+            "page: {}, total pages: {}",
+            page.page_number, page.total_pages
+        ),
+    };
+    table
+}
@@ -0,0 +1,99 @@
+use super::output::{page_json, page_table};
+use omicron_protocol::{hex, unhex, Cursor, Filter, Flags, IpAddress, Listing, Page, Region};
+use serde_json::json;
+
+fn page() -> Page {
+    let listing = Listing {
+        flags: Flags::new(false, true, true),
+        region: Region::NorthAmerica,
+        host_ip: IpAddress::IpV4([203, 0, 113, 7]),
+        host_port: 7777,
+        max_players: 8,
+        name: String::from("Café"),
+        current_players: 3,
+    };
+    let ipv6 = Listing {
+        flags: Flags::new(true, true, false),
+        region: Region::Asia,
+        host_ip: IpAddress::IpV6([0xfd00, 0, 0, 0, 0, 0, 0, 1]),
+        name: String::from("Second lobby"),
+        ..listing.clone()
+    };
+    Page {
+        lobbies: vec![listing, ipv6],
+        page_number: 0,
+        total_pages: 1,
+        total_lobbies: None,
+        next: None,
+    }
+}
+
+/// As version 2 sends it.
+fn cursor_page() -> Page {
+    Page {
+        total_lobbies: Some(40),
+        next: Some(Cursor {
+            filter: Filter::NameAscending,
+            lobby_name: String::from("ab"),
+            current_players: 1,
+            key: String::from("k"),
+        }),
+        ..page()
+    }
+}
+
+#[test]
+fn table() {
+    assert_eq!(
+        page_table(&page()),
+        "\
+NAME          REGION         HOST              PLAYERS  PASSWORD
+Café          north_america  203.0.113.7:7777  3/8      yes
+Second lobby  asia           [fd00::1]:7777    3/8      no
+page: 0, total pages: 1"
+    );
+
+    let table = page_table(&cursor_page());
+    assert!(table.ends_with("\ntotal lobbies: 40, next: 00010261626b"));
+    let last = Page {
+        next: None,
+        ..cursor_page()
+    };
+    assert!(page_table(&last).ends_with("\ntotal lobbies: 40, last page"));
+}
+
+#[test]
+fn json() {
+    let json = page_json(&page());
+    assert_eq!(
+        json["lobbies"][0],
+        json!({
+            "name": "Café",
+            "region": "north_america",
+            "host_ip": "203.0.113.7",
+            "port": 7777,
+            "max_players": 8,
+            "current_players": 3,
+            "public": true,
+            "has_password": true,
+        })
+    );
+    assert_eq!(json["lobbies"][1]["host_ip"], "fd00::1");
+    assert_eq!(
+        (json["page"].clone(), json["total_pages"].clone()),
+        (json!(0), json!(1))
+    );
+
+    let json = page_json(&cursor_page());
+    assert_eq!(json["total_lobbies"], 40);
+    assert_eq!(json["next"], "00010261626b");
+    assert!(json.get("page").is_none());
+}
+
+#[test]
+fn tokens() {
+    assert_eq!(hex(&[0, 1, 0xab, 0xff]), "0001abff");
+    assert_eq!(unhex("0001ABff"), Some(vec![0, 1, 0xab, 0xff]));
+    assert_eq!(unhex("abc"), None);
+    assert_eq!(unhex("zz"), None);
+}
@@ -0,0 +1,111 @@
+use std::{net::SocketAddr, time::Duration};
+
+pub const USAGE: &str = "\
+Usage: omicron_load [OPTIONS]
+
+Simulates hosts and browsing players against a lobby server, then reports
+throughput, latency percentiles and response codes for each message type.
+Every simulated client shares this machine's ip, so give the server rate
+limits and a lobbies-per-ip cap above what the run needs.
+
+Options:
+  -s, --server <ADDRESS>        The lobby server [default: 127.0.0.1:5475]
+      --hosts <N>               Hosts, each creating one lobby [default: 50]
+      --browsers <N>            Players sending Gets [default: 200]
+      --duration <SECONDS>      How long to run for [default: 30]
+      --modify-interval <MS>    Between each host's Modifies [default: 5000]
+      --get-interval <MS>       Between each browser's Gets [default: 0]
+      --first-port <PORT>       The first host's port, each next host takes the
+                                next one [default: 20000]
+      --protocol <VERSION>      The protocol version to speak, 0 to 2 [default: 2]
+      --seed <N>                For the random filters [default: random]
+      --json                    Print the report as JSON
+  -h, --help                    Print this help
+";
+
+#[derive(Debug, PartialEq)]
+pub struct Args {
+    pub server: SocketAddr,
+    pub hosts: u16,
+    pub browsers: usize,
+    pub duration: Duration,
+    pub modify_interval: Duration,
+    pub get_interval: Duration,
+    pub first_port: u16,
+    pub version: u8,
+    pub seed: Option<u64>,
+    pub json: bool,
+    pub help: bool,
+}
+
+impl Default for Args {
+    fn default() -> Self {
+        Self {
+            server: SocketAddr::from(([127, 0, 0, 1], 5475)),
+            hosts: 50,
+            browsers: 200,
+            duration: Duration::from_secs(30),
+            modify_interval: Duration::from_millis(5000),
+            get_interval: Duration::ZERO,
+            first_port: 20000,
+            version: omicron_protocol::client::LATEST_VERSION,
+            seed: None,
+            json: false,
+            help: false,
+        }
+    }
+}
+
+impl Args {
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Self, String> {
+        let mut parsed = Self::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| format!("{arg} needs a value"));
+            match arg.as_str() {
+                "-s" | "--server" => parsed.server = parse(&value()?, "an address and port")?,
+                "--hosts" => parsed.hosts = parse(&value()?, "a number of hosts")?,
+                "--browsers" => parsed.browsers = parse(&value()?, "a number of browsers")?,
+                "--duration" => {
+                    parsed.duration = Duration::from_secs(parse(&value()?, "a number of seconds")?)
+                }
+                "--modify-interval" => {
+                    parsed.modify_interval = milliseconds(&value()?)?;
+                }
+                "--get-interval" => parsed.get_interval = milliseconds(&value()?)?,
+                "--first-port" => parsed.first_port = parse(&value()?, "a port")?,
+                "--protocol" => {
+                    parsed.version = match value()?.as_str() {
+                        "0" => 0,
+                        "1" => 1,
+                        "2" => 2,
+                        version => return Err(format!("{version:?} is not 0 to 2")),
+                    }
+                }
+                "--seed" => parsed.seed = Some(parse(&value()?, "a number")?),
+                "--json" => parsed.json = true,
+                "-h" | "--help" => parsed.help = true,
+                _ => return Err(format!("unknown option {arg:?}")),
+            }
+        }
+
+        if parsed.first_port.checked_add(parsed.hosts).is_none() {
+            return Err(format!(
+                "{} hosts do not fit in the ports from {}",
+                parsed.hosts, parsed.first_port
+            ));
+        }
+        Ok(parsed)
+    }
+}
+
+fn parse<T: std::str::FromStr>(value: &str, what: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{value:?} is not {what}"))
+}
+
+fn milliseconds(value: &str) -> Result<Duration, String> {
+    parse(value, "a number of milliseconds").map(Duration::from_millis)
+}
@@ -0,0 +1,55 @@
+use super::args::Args;
+use std::time::Duration;
+
+fn args(args: &[&str]) -> Result<Args, String> {
+    Args::parse(args.iter().map(|arg| arg.to_string()))
+}
+
+#[test]
+fn parse_args() {
+    assert_eq!(args(&[]).unwrap(), Args::default());
+
+    let parsed = args(&[
+        "-s",
+        "127.0.0.1:6000",
+        "--hosts",
+        "10",
+        "--browsers",
+        "0",
+        "--duration",
+        "5",
+        "--modify-interval",
+        "250",
+        "--get-interval",
+        "10",
+        "--protocol",
+        "0",
+        "--seed",
+        "42",
+        "--json",
+    ])
+    .unwrap();
+    assert_eq!(parsed.server, "127.0.0.1:6000".parse().unwrap());
+    assert_eq!((parsed.hosts, parsed.browsers), (10, 0));
+    assert_eq!(parsed.duration, Duration::from_secs(5));
+    assert_eq!(parsed.modify_interval, Duration::from_millis(250));
+    assert_eq!(parsed.get_interval, Duration::from_millis(10));
+    assert_eq!(
+        (parsed.version, parsed.seed, parsed.json),
+        (0, Some(42), true)
+    );
+
+    assert_eq!(args(&["--hosts"]).unwrap_err(), "--hosts needs a value");
+    assert_eq!(
+        args(&["--hosts", "many"]).unwrap_err(),
+        "\"many\" is not a number of hosts"
+    );
+    assert_eq!(
+        args(&["--first-port", "65000", "--hosts", "1000"]).unwrap_err(),
+        "1000 hosts do not fit in the ports from 65000"
+    );
+    assert_eq!(
+        args(&["--verbose"]).unwrap_err(),
+        "unknown option \"--verbose\""
+    );
+}
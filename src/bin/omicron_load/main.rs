@@ -0,0 +1,242 @@
+//! A load generator: simulated hosts Create a lobby, Modify it now and then
+//! and Destroy it at the end, while simulated players browse with Gets of
+//! random filters, regions and searches. Every client has a thread of its
+//! own and waits for each answer, like a game would.
+
+use args::Args;
+use omicron_protocol::{
+    Client, Error, Filter, GetRequest, IpAddress, LobbySettings, Predicates, Region,
+};
+use stats::Stats;
+use std::{
+    env,
+    process::ExitCode,
+    thread,
+    time::{Duration, Instant},
+};
+
+mod args;
+#[cfg(test)]
+mod args_tests;
+mod stats;
+#[cfg(test)]
+mod stats_tests;
+
+/// Warn when more than this share of responses are refusals, the run then
+/// measures the server's limits rather than the server.
+const REFUSED_WARNING: f64 = 0.5;
+
+/// Lobby names are made of these, and searches pick one.
+const WORDS: [&str; 8] = [
+    "alpha", "bravo", "castle", "dune", "ember", "frost", "grove", "harbor",
+];
+
+fn main() -> ExitCode {
+    let args = match Args::parse(env::args().skip(1)) {
+        Ok(args) if args.help => {
+            print!("{}", args::USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("{err}\n\nRun with --help for usage.");
+            return ExitCode::from(2);
+        }
+    };
+    let client = Client::new(args.server).with_version(args.version);
+    let host_ip = match client.local_address() {
+        Ok(host_ip) => host_ip,
+        Err(err) => {
+            eprintln!("error: cannot reach {}: {err}", args.server);
+            return ExitCode::FAILURE;
+        }
+    };
+    let seed = args.seed.unwrap_or_else(random_seed);
+    eprintln!(
+        "Running {} hosts and {} browsers against {} for {}s, seed {seed}",
+        args.hosts,
+        args.browsers,
+        args.server,
+        args.duration.as_secs()
+    );
+
+    let start = Instant::now();
+    let deadline = start + args.duration;
+    let mut clients = Vec::new();
+    for index in 0..args.hosts {
+        let client = client.clone();
+        let lobby = lobby(host_ip, args.first_port + index, index);
+        let interval = args.modify_interval;
+        let mut random = Random::new(seed ^ u64::from(index));
+        clients.push(thread::spawn(move || {
+            host(&client, lobby, deadline, interval, &mut random)
+        }));
+    }
+    for index in 0..args.browsers {
+        let client = client.clone();
+        let interval = args.get_interval;
+        let mut random = Random::new(seed ^ (index as u64) << 16);
+        clients.push(thread::spawn(move || {
+            browse(&client, deadline, interval, &mut random)
+        }));
+    }
+
+    let mut stats = Stats::default();
+    for client in clients {
+        match client.join() {
+            Ok(client_stats) => stats.merge(client_stats),
+            Err(_) => eprintln!("A simulated client panicked, its requests are left out"),
+        }
+    }
+    let elapsed = start.elapsed();
+    stats.finish();
+
+    match args.json {
+        true => println!("{}", stats.json(elapsed)),
+        false => print!("{}", stats.table(elapsed)),
+    }
+    let refused = stats.refused();
+    if refused > REFUSED_WARNING {
+        eprintln!(
+            "warning: {:.0}% of responses were refusals (61, 62 or 63), raise the \
+            server's rate_limits, e.g. with omicron.load.toml",
+            refused * 100.0
+        );
+    }
+    ExitCode::SUCCESS
+}
+
+fn random_seed() -> u64 {
+    let mut bytes = [0; 8];
+    // A fixed seed only makes the run less varied.
+    let _ = getrandom::getrandom(&mut bytes);
+    u64::from_le_bytes(bytes)
+}
+
+/// Hosts spread over every region, half of them with a password.
+fn lobby(host_ip: IpAddress, port: u16, index: u16) -> LobbySettings {
+    let index = usize::from(index);
+    LobbySettings {
+        host_ip,
+        host_port: port,
+        region: Region::get_regions(1 << (index % 6)).remove(0),
+        max_players: 8,
+        name: format!("{} {index}", WORDS[index % WORDS.len()]),
+        public: true,
+        password: match index % 2 {
+            0 => String::new(),
+            _ => String::from("password"),
+        },
+    }
+}
+
+/// Sends one request, recording how long it took and its response code.
+fn timed<T>(
+    stats: &mut Stats,
+    message_type: &'static str,
+    request: impl FnOnce() -> Result<T, Error>,
+) -> Result<T, Error> {
+    let start = Instant::now();
+    let result = request();
+    stats.record(message_type, start.elapsed(), stats::code(&result));
+    result
+}
+
+/// Sleeps until `wake`, or only until `deadline` and returns false if that
+/// comes first.
+fn sleep_until(wake: Instant, deadline: Instant) -> bool {
+    let now = Instant::now();
+    thread::sleep(wake.min(deadline).saturating_duration_since(now));
+    wake < deadline
+}
+
+fn host(
+    client: &Client,
+    lobby: LobbySettings,
+    deadline: Instant,
+    interval: Duration,
+    random: &mut Random,
+) -> Stats {
+    let mut stats = Stats::default();
+    let Ok(created) = timed(&mut stats, "create", || client.create(&lobby)) else {
+        return stats;
+    };
+
+    // Starting at a random point of the interval keeps hosts from all
+    // sending their Modify at once.
+    let mut wake = Instant::now() + interval.mul_f64(random.fraction());
+    while sleep_until(wake, deadline) {
+        let players = random.below(u64::from(lobby.max_players)) as u8 + 1;
+        let _ = timed(&mut stats, "modify", || {
+            client.modify(&lobby, players, &created.token)
+        });
+        wake += interval;
+    }
+
+    let _ = timed(&mut stats, "destroy", || {
+        client.destroy(lobby.host_ip, lobby.host_port, &created.token)
+    });
+    stats
+}
+
+fn browse(client: &Client, deadline: Instant, interval: Duration, random: &mut Random) -> Stats {
+    let mut stats = Stats::default();
+    while Instant::now() < deadline {
+        let request = random_request(random);
+        let _ = timed(&mut stats, "get", || client.get(&request));
+        if !interval.is_zero() && !sleep_until(Instant::now() + interval, deadline) {
+            break;
+        }
+    }
+    stats
+}
+
+/// Any sort, any regions, a search one time in four and one of the first
+/// three pages.
+fn random_request(random: &mut Random) -> GetRequest {
+    const FILTERS: [Filter; 4] = [
+        Filter::NameAscending,
+        Filter::NameDescending,
+        Filter::PlayerCountAscending,
+        Filter::PlayerCountDescending,
+    ];
+    GetRequest {
+        filter: FILTERS[random.below(4) as usize],
+        regions: Region::get_regions(random.below(64) as u8),
+        page_num: random.below(3) as u16,
+        search: (random.below(4) == 0)
+            .then(|| WORDS[random.below(WORDS.len() as u64) as usize].to_string()),
+        page_size: None,
+        cursor: None,
+        predicates: Predicates::default(),
+    }
+}
+
+/// xorshift64*, enough to spread requests over filters and regions.
+pub struct Random(u64);
+
+impl Random {
+    pub fn new(seed: u64) -> Self {
+        // splitmix64, so that nearby seeds start far apart.
+        let mut mixed = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        mixed = (mixed ^ (mixed >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        mixed = (mixed ^ (mixed >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        Self((mixed ^ (mixed >> 31)) | 1)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    pub fn below(&mut self, bound: u64) -> u64 {
+        self.next_u64() % bound
+    }
+
+    /// In `0.0..1.0`.
+    pub fn fraction(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
@@ -0,0 +1,143 @@
+//! Requests as each simulated client saw them, merged into one report once
+//! the run is over.
+
+use omicron_protocol::{Error, OK};
+use serde_json::{json, Value};
+use std::{collections::BTreeMap, fmt::Write, time::Duration};
+
+/// The response code of a result, `None` if the server never sent one.
+pub fn code<T>(result: &Result<T, Error>) -> Option<u8> {
+    match result {
+        Ok(_) => Some(OK),
+        Err(err) => err.code(),
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct TypeStats {
+    /// Of every request, answered or not.
+    pub latencies: Vec<Duration>,
+    /// Requests by response code, `None` for those that got none.
+    pub codes: BTreeMap<Option<u8>, usize>,
+}
+
+impl TypeStats {
+    /// The latency that `percent` of requests took no longer than, by
+    /// nearest rank. `latencies` must be sorted.
+    pub fn percentile(&self, percent: f64) -> Duration {
+        if self.latencies.is_empty() {
+            return Duration::ZERO;
+        }
+        let rank = (percent / 100.0 * self.latencies.len() as f64).ceil() as usize;
+        self.latencies[rank.clamp(1, self.latencies.len()) - 1]
+    }
+}
+
+/// By message type.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Stats(pub BTreeMap<&'static str, TypeStats>);
+
+impl Stats {
+    pub fn record(&mut self, message_type: &'static str, latency: Duration, code: Option<u8>) {
+        let stats = self.0.entry(message_type).or_default();
+        stats.latencies.push(latency);
+        *stats.codes.entry(code).or_default() += 1;
+    }
+
+    pub fn merge(&mut self, other: Stats) {
+        for (message_type, other) in other.0 {
+            let stats = self.0.entry(message_type).or_default();
+            stats.latencies.extend(other.latencies);
+            for (code, count) in other.codes {
+                *stats.codes.entry(code).or_default() += count;
+            }
+        }
+    }
+
+    /// The share of answered requests that the server turned away for its
+    /// limits, codes 61, 62 and 63.
+    pub fn refused(&self) -> f64 {
+        let (mut answered, mut refused) = (0, 0);
+        for (code, count) in self.0.values().flat_map(|stats| &stats.codes) {
+            match code {
+                Some(61..=63) => refused += count,
+                Some(_) => {}
+                None => continue,
+            }
+            answered += count;
+        }
+        match answered {
+            0 => 0.0,
+            _ => refused as f64 / answered as f64,
+        }
+    }
+
+    /// Sorts the latencies, for `TypeStats::percentile`.
+    pub fn finish(&mut self) {
+        for stats in self.0.values_mut() {
+            stats.latencies.sort_unstable();
+        }
+    }
+
+    pub fn table(&self, elapsed: Duration) -> String {
+        let mut table = format!(
+            "{:<10}{:>10}{:>12}{:>10}{:>10}{:>10}{:>10}  CODES\n",
+            "TYPE", "REQUESTS", "PER SECOND", "P50 MS", "P90 MS", "P99 MS", "MAX MS"
+        );
+        for (message_type, stats) in &self.0 {
+            let codes: Vec<String> = stats
+                .codes
+                .iter()
+                .map(|(code, count)| format!("{}: {count}", code_label(*code)))
+                .collect();
+            let _ = writeln!(
+                table,
+                "{message_type:<10}{:>10}{:>12.1}{:>10.2}{:>10.2}{:>10.2}{:>10.2}  {}",
+                stats.latencies.len(),
+                stats.latencies.len() as f64 / elapsed.as_secs_f64(),
+                milliseconds(stats.percentile(50.0)),
+                milliseconds(stats.percentile(90.0)),
+                milliseconds(stats.percentile(99.0)),
+                milliseconds(stats.percentile(100.0)),
+                codes.join(", ")
+            );
+        }
+        table
+    }
+
+    pub fn json(&self, elapsed: Duration) -> Value {
+        let types: serde_json::Map<String, Value> = self
+            .0
+            .iter()
+            .map(|(message_type, stats)| {
+                let codes: serde_json::Map<String, Value> = stats
+                    .codes
+                    .iter()
+                    .map(|(code, count)| (code_label(*code), json!(count)))
+                    .collect();
+                let stats = json!({
+                    "requests": stats.latencies.len(),
+                    "per_second": stats.latencies.len() as f64 / elapsed.as_secs_f64(),
+                    "latency_ms": {
+                        "p50": milliseconds(stats.percentile(50.0)),
+                        "p90": milliseconds(stats.percentile(90.0)),
+                        "p99": milliseconds(stats.percentile(99.0)),
+                        "max": milliseconds(stats.percentile(100.0)),
+                    },
+                    "codes": codes,
+                });
+                (message_type.to_string(), stats)
+            })
+            .collect();
+        json!({ "seconds": elapsed.as_secs_f64(), "types": types })
+    }
+}
+
+/// As the server's metrics label codes.
+fn code_label(code: Option<u8>) -> String {
+    code.map_or_else(|| String::from("none"), |code| code.to_string())
+}
+
+fn milliseconds(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
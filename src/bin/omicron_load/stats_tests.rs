@@ -0,0 +1,80 @@
+use super::{
+    random_request,
+    stats::{code, Stats},
+    Random,
+};
+use omicron_protocol::{DatabaseError, Error};
+use std::time::Duration;
+
+fn ms(milliseconds: u64) -> Duration {
+    Duration::from_millis(milliseconds)
+}
+
+#[test]
+fn percentiles_and_codes() {
+    let mut first = Stats::default();
+    let mut second = Stats::default();
+    for latency in 1..=60 {
+        first.record("get", ms(latency), Some(10));
+    }
+    for latency in 61..=100 {
+        second.record("get", ms(latency), Some(10));
+    }
+    second.record("create", ms(5), Some(61));
+    second.record("create", ms(7), None);
+
+    // Merged out of order, `finish` sorts them.
+    second.merge(first);
+    second.finish();
+    let get = &second.0["get"];
+    assert_eq!(get.latencies.len(), 100);
+    assert_eq!(get.percentile(50.0), ms(50));
+    assert_eq!(get.percentile(99.0), ms(99));
+    assert_eq!(get.percentile(100.0), ms(100));
+    assert_eq!(second.0["create"].codes.get(&Some(61)), Some(&1));
+
+    let json = second.json(Duration::from_secs(10));
+    assert_eq!(json["types"]["get"]["per_second"], 10.0);
+    assert_eq!(json["types"]["get"]["latency_ms"]["p90"], 90.0);
+    assert_eq!(json["types"]["create"]["codes"]["none"], 1);
+
+    let table = second.table(Duration::from_secs(10));
+    assert!(table.starts_with("TYPE"));
+    assert!(table.contains("none: 1, 61: 1"));
+}
+
+#[test]
+fn refusals() {
+    let mut stats = Stats::default();
+    assert_eq!(stats.refused(), 0.0);
+    stats.record("get", ms(1), Some(10));
+    stats.record("get", ms(1), Some(61));
+    stats.record("create", ms(1), Some(63));
+    stats.record("create", ms(1), Some(52));
+    // Unanswered requests are left out.
+    stats.record("create", ms(1), None);
+    assert_eq!(stats.refused(), 0.5);
+}
+
+#[test]
+fn result_codes() {
+    assert_eq!(code(&Ok::<(), Error>(())), Some(10));
+    let refused: Result<(), Error> = Err(Error::Database(DatabaseError::LobbyFull));
+    assert_eq!(code(&refused), Some(60));
+    assert_eq!(code(&Err::<(), Error>(Error::Malformed)), None);
+}
+
+#[test]
+fn random_requests_vary() {
+    let mut random = Random::new(7);
+    let requests: Vec<_> = (0..200).map(|_| random_request(&mut random)).collect();
+    assert!(requests.iter().any(|request| request.search.is_some()));
+    assert!(requests.iter().any(|request| request.search.is_none()));
+    assert!(requests.iter().any(|request| request.regions.len() == 6));
+    assert!(requests.iter().any(|request| request.regions.len() == 1));
+    assert!(requests.iter().all(|request| request.page_num < 3));
+
+    // The same seed gives the same run.
+    let mut again = Random::new(7);
+    assert_eq!(random_request(&mut again), requests[0]);
+}
@@ -0,0 +1,727 @@
+//! Server settings. Built in defaults are overridden by a TOML file, then by
+//! `OMICRON_*` environment variables, then by the command line. See
+//! `omicron.example.toml` for every setting.
+
+use crate::{database::ENTRY_OVERHEAD, protocol};
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    env, fmt, fs, io,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::OnceLock,
+};
+use tracing_subscriber::EnvFilter;
+
+pub const USAGE: &str = "\
+Usage: project_omicron_lobbies [OPTIONS]
+
+Options:
+  -c, --config <FILE>          Read settings from a TOML file [env: OMICRON_CONFIG]
+  -b, --bind <ADDRESS>         Listen on ADDRESS, may be repeated [env: OMICRON_BIND]
+  -s, --storage <BACKEND>      memory, log or sqlite [env: OMICRON_STORAGE]
+      --log-format <FORMAT>    pretty or json [env: OMICRON_LOG_FORMAT]
+      --metrics <ADDRESS>      Serve /metrics over HTTP on ADDRESS [env: OMICRON_METRICS_BIND]
+      --http <ADDRESS>         Serve the HTTP/JSON gateway on ADDRESS [env: OMICRON_HTTP_BIND]
+      --websocket <ADDRESS>    Accept WebSocket clients on ADDRESS [env: OMICRON_WEBSOCKET_BIND]
+      --udp <ADDRESS>          Answer Get and Modify over UDP on ADDRESS, may be repeated
+                               [env: OMICRON_UDP_BIND]
+  -h, --help                   Print this help
+";
+
+/// The bcrypt crate's limits, which it keeps private.
+pub const MIN_BCRYPT_COST: u32 = 4;
+const MAX_BCRYPT_COST: u32 = 31;
+
+/// The most bytes a character takes in UTF-8. Names and passwords are
+/// limited in characters but sent with a single byte length.
+const MAX_CHAR_BYTES: usize = 4;
+/// The longest password whose bytes always fit its length.
+pub const MAX_PASSWORD_LENGTH: usize = u8::MAX as usize / MAX_CHAR_BYTES;
+/// The longest name whose Get entry always fits version 0's single byte
+/// length.
+pub const MAX_NAME_LENGTH: usize = (u8::MAX as usize - ENTRY_OVERHEAD) / MAX_CHAR_BYTES;
+/// The longest lobby key, `[ipv6]:port`.
+const MAX_KEY_LENGTH: usize = 47;
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// The running server's settings, the defaults unless `install` was called first.
+pub fn get() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
+
+/// Makes `config` the running server's settings, call it before anything
+/// reads them.
+pub fn install(config: Config) {
+    let _ = CONFIG.set(config);
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Usage(String),
+    Read(PathBuf, io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Environment(&'static str, String),
+    Invalid(&'static str, &'static str),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Usage(message) => write!(f, "{message}\n\n{USAGE}"),
+            Self::Read(path, err) => write!(f, "failed to read {}: {err}", path.display()),
+            Self::Parse(path, err) => write!(f, "failed to parse {}: {err}", path.display()),
+            Self::Environment(variable, value) => {
+                write!(f, "{variable}: {value:?} is not a valid value")
+            }
+            Self::Invalid(setting, reason) => write!(f, "{setting}: {reason}"),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    /// Nothing is kept when the server stops.
+    Memory,
+    /// In memory, with a write-ahead log in `data_directory`.
+    Log,
+    /// An SQLite database at `sqlite_path`, needs the sqlite feature.
+    Sqlite,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// One line per event, for people.
+    Pretty,
+    /// One JSON object per event, for log collectors.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "pretty" => Ok(Self::Pretty),
+            "json" => Ok(Self::Json),
+            _ => Err(()),
+        }
+    }
+}
+
+impl FromStr for Backend {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "memory" => Ok(Self::Memory),
+            "log" => Ok(Self::Log),
+            "sqlite" => Ok(Self::Sqlite),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub bind: Vec<SocketAddr>,
+    pub worker_threads: usize,
+    pub timeouts: Timeouts,
+    pub limits: Limits,
+    pub bcrypt_cost: u32,
+    pub storage: Storage,
+    pub logging: Logging,
+    pub metrics: Metrics,
+    pub http: Http,
+    pub websocket: WebSocket,
+    pub udp: Udp,
+    pub rate_limits: RateLimits,
+}
+
+/// All in seconds.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Timeouts {
+    /// For a client to send its whole message.
+    pub receive: u64,
+    /// Without a Create, Modify or Heartbeat before a lobby is removed.
+    pub lobby: u64,
+    pub reap_interval: u64,
+    /// For a player to reach the host with their join ticket.
+    pub join_ticket: u64,
+    /// Between requests before a session is closed.
+    pub session_idle: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    pub page_size: u8,
+    /// The most lobbies a version 2 Get may ask for in a page.
+    pub max_page_size: u8,
+    pub max_name_length: usize,
+    pub max_password_length: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Storage {
+    pub backend: Backend,
+    pub data_directory: PathBuf,
+    pub sqlite_path: PathBuf,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Logging {
+    pub format: LogFormat,
+    /// Which events to show, in `tracing_subscriber::EnvFilter` syntax.
+    pub filter: String,
+    /// Reads debug commands such as `dump` from stdin.
+    pub debug_commands: bool,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Metrics {
+    /// Where to serve `/metrics`, off if `None`.
+    pub bind: Option<SocketAddr>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Http {
+    /// Where to serve the JSON gateway, off if `None`.
+    pub bind: Option<SocketAddr>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebSocket {
+    /// Where to accept WebSocket clients, off if `None`.
+    pub bind: Option<SocketAddr>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Udp {
+    /// Where to answer Get and Modify over UDP, off if empty.
+    pub bind: Vec<SocketAddr>,
+    /// The largest datagram to send, longer responses are split.
+    pub max_datagram: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimits {
+    /// Connections being handled or waiting for a worker, from every client.
+    pub max_connections: usize,
+    /// Lobbies one ip, or IPv6 /64, may have at once.
+    pub max_lobbies_per_ip: usize,
+    /// For message types without a rate of their own.
+    pub default: Rate,
+    /// By message type name (`create`, `get`, ...). Types left out keep
+    /// their built in rate, see `RateLimits::rate`.
+    pub types: BTreeMap<String, Rate>,
+}
+
+/// A token bucket per client ip and message type.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rate {
+    /// Requests a client gets back each second.
+    pub per_second: f64,
+    /// The most requests a client can save up.
+    pub burst: u32,
+}
+
+impl RateLimits {
+    /// The rate for `message_type`. Create, Modify and Join hash or check a
+    /// bcrypt password, so unless configured they get a slower rate than
+    /// `default`.
+    pub fn rate(&self, message_type: &str) -> Rate {
+        const BCRYPT_RATE: Rate = Rate {
+            per_second: 1.0,
+            burst: 5,
+        };
+
+        match self.types.get(message_type) {
+            Some(rate) => *rate,
+            None if matches!(message_type, "create" | "modify" | "join") => BCRYPT_RATE,
+            None => self.default,
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            bind: vec![SocketAddr::from(([0, 0, 0, 0], 5475))],
+            worker_threads: 16,
+            timeouts: Timeouts::default(),
+            limits: Limits::default(),
+            bcrypt_cost: bcrypt::DEFAULT_COST,
+            storage: Storage::default(),
+            logging: Logging::default(),
+            metrics: Metrics::default(),
+            http: Http::default(),
+            websocket: WebSocket::default(),
+            udp: Udp::default(),
+            rate_limits: RateLimits::default(),
+        }
+    }
+}
+
+impl Default for Udp {
+    fn default() -> Self {
+        Self {
+            bind: Vec::new(),
+            // Fits in one packet on any path IPv6 can use.
+            max_datagram: 1200,
+        }
+    }
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        Self {
+            max_connections: 512,
+            max_lobbies_per_ip: 8,
+            default: Rate {
+                per_second: 10.0,
+                burst: 30,
+            },
+            types: BTreeMap::new(),
+        }
+    }
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            receive: 5,
+            lobby: 60,
+            reap_interval: 10,
+            join_ticket: 30,
+            session_idle: 60,
+        }
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            page_size: 15,
+            max_page_size: 100,
+            max_name_length: 32,
+            max_password_length: 32,
+        }
+    }
+}
+
+impl Default for Logging {
+    fn default() -> Self {
+        Self {
+            format: LogFormat::Pretty,
+            filter: String::from("info"),
+            debug_commands: false,
+        }
+    }
+}
+
+impl Default for Storage {
+    fn default() -> Self {
+        Self {
+            backend: Backend::Log,
+            data_directory: PathBuf::from("lobby_data"),
+            sqlite_path: PathBuf::from("lobbies.sqlite3"),
+        }
+    }
+}
+
+/// The command line, each option overrides the file and the environment.
+#[derive(Debug, Default, PartialEq)]
+pub struct Args {
+    pub config: Option<PathBuf>,
+    pub bind: Vec<SocketAddr>,
+    pub storage: Option<Backend>,
+    pub log_format: Option<LogFormat>,
+    pub metrics: Option<SocketAddr>,
+    pub http: Option<SocketAddr>,
+    pub websocket: Option<SocketAddr>,
+    pub udp: Vec<SocketAddr>,
+    pub help: bool,
+}
+
+impl Args {
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Self, ConfigError> {
+        let mut parsed = Self::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| ConfigError::Usage(format!("{arg} needs a value")))
+            };
+            match arg.as_str() {
+                "-c" | "--config" => parsed.config = Some(PathBuf::from(value()?)),
+                "-b" | "--bind" => {
+                    let address = value()?;
+                    let address = address.parse().map_err(|_| {
+                        ConfigError::Usage(format!("{address:?} is not an address and port"))
+                    })?;
+                    parsed.bind.push(address);
+                }
+                "-s" | "--storage" => {
+                    let backend = value()?;
+                    parsed.storage = Some(backend.parse().map_err(|_| {
+                        ConfigError::Usage(format!("{backend:?} is not a storage backend"))
+                    })?);
+                }
+                "--log-format" => {
+                    let format = value()?;
+                    parsed.log_format = Some(format.parse().map_err(|_| {
+                        ConfigError::Usage(format!("{format:?} is not a log format"))
+                    })?);
+                }
+                "--metrics" => {
+                    let address = value()?;
+                    parsed.metrics = Some(address.parse().map_err(|_| {
+                        ConfigError::Usage(format!("{address:?} is not an address and port"))
+                    })?);
+                }
+                "--http" => {
+                    let address = value()?;
+                    parsed.http = Some(address.parse().map_err(|_| {
+                        ConfigError::Usage(format!("{address:?} is not an address and port"))
+                    })?);
+                }
+                "--websocket" => {
+                    let address = value()?;
+                    parsed.websocket = Some(address.parse().map_err(|_| {
+                        ConfigError::Usage(format!("{address:?} is not an address and port"))
+                    })?);
+                }
+                "--udp" => {
+                    let address = value()?;
+                    let address = address.parse().map_err(|_| {
+                        ConfigError::Usage(format!("{address:?} is not an address and port"))
+                    })?;
+                    parsed.udp.push(address);
+                }
+                "-h" | "--help" => parsed.help = true,
+                _ => return Err(ConfigError::Usage(format!("unknown option {arg:?}"))),
+            }
+        }
+
+        Ok(parsed)
+    }
+}
+
+impl Config {
+    /// Reads and checks the settings for `args`, from the file it names (or
+    /// `OMICRON_CONFIG`) and the environment.
+    pub fn load(args: &Args) -> Result<Self, ConfigError> {
+        let path = args
+            .config
+            .clone()
+            .or_else(|| env::var_os("OMICRON_CONFIG").map(PathBuf::from));
+        let mut config = match path {
+            Some(path) => Self::from_file(&path)?,
+            None => Self::default(),
+        };
+
+        config.apply_environment(|variable| env::var(variable).ok())?;
+        config.apply_args(args);
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let contents =
+            fs::read_to_string(path).map_err(|err| ConfigError::Read(path.to_path_buf(), err))?;
+        toml::from_str(&contents).map_err(|err| ConfigError::Parse(path.to_path_buf(), err))
+    }
+
+    /// Overrides settings with the variables `lookup` finds.
+    pub fn apply_environment<F>(&mut self, lookup: F) -> Result<(), ConfigError>
+    where
+        F: Fn(&'static str) -> Option<String>,
+    {
+        fn set<T: FromStr>(
+            lookup: &impl Fn(&'static str) -> Option<String>,
+            variable: &'static str,
+            setting: &mut T,
+        ) -> Result<(), ConfigError> {
+            if let Some(value) = lookup(variable) {
+                *setting = value
+                    .trim()
+                    .parse()
+                    .map_err(|_| ConfigError::Environment(variable, value))?;
+            }
+            Ok(())
+        }
+
+        fn set_addresses(
+            lookup: &impl Fn(&'static str) -> Option<String>,
+            variable: &'static str,
+            setting: &mut Vec<SocketAddr>,
+        ) -> Result<(), ConfigError> {
+            if let Some(value) = lookup(variable) {
+                *setting = value
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|address| !address.is_empty())
+                    .map(str::parse)
+                    .collect::<Result<_, _>>()
+                    .map_err(|_| ConfigError::Environment(variable, value))?;
+            }
+            Ok(())
+        }
+
+        set_addresses(&lookup, "OMICRON_BIND", &mut self.bind)?;
+        set(&lookup, "OMICRON_WORKER_THREADS", &mut self.worker_threads)?;
+        set(
+            &lookup,
+            "OMICRON_RECEIVE_TIMEOUT",
+            &mut self.timeouts.receive,
+        )?;
+        set(&lookup, "OMICRON_LOBBY_TIMEOUT", &mut self.timeouts.lobby)?;
+        set(
+            &lookup,
+            "OMICRON_REAP_INTERVAL",
+            &mut self.timeouts.reap_interval,
+        )?;
+        set(
+            &lookup,
+            "OMICRON_JOIN_TICKET_TIMEOUT",
+            &mut self.timeouts.join_ticket,
+        )?;
+        set(
+            &lookup,
+            "OMICRON_SESSION_IDLE_TIMEOUT",
+            &mut self.timeouts.session_idle,
+        )?;
+        set(&lookup, "OMICRON_PAGE_SIZE", &mut self.limits.page_size)?;
+        set(
+            &lookup,
+            "OMICRON_MAX_PAGE_SIZE",
+            &mut self.limits.max_page_size,
+        )?;
+        set(
+            &lookup,
+            "OMICRON_MAX_NAME_LENGTH",
+            &mut self.limits.max_name_length,
+        )?;
+        set(
+            &lookup,
+            "OMICRON_MAX_PASSWORD_LENGTH",
+            &mut self.limits.max_password_length,
+        )?;
+        set(&lookup, "OMICRON_BCRYPT_COST", &mut self.bcrypt_cost)?;
+        set(&lookup, "OMICRON_STORAGE", &mut self.storage.backend)?;
+        set(
+            &lookup,
+            "OMICRON_DATA_DIRECTORY",
+            &mut self.storage.data_directory,
+        )?;
+        set(
+            &lookup,
+            "OMICRON_SQLITE_PATH",
+            &mut self.storage.sqlite_path,
+        )?;
+        set(&lookup, "OMICRON_LOG_FORMAT", &mut self.logging.format)?;
+        set(&lookup, "OMICRON_LOG_FILTER", &mut self.logging.filter)?;
+        set(
+            &lookup,
+            "OMICRON_DEBUG_COMMANDS",
+            &mut self.logging.debug_commands,
+        )?;
+        set(
+            &lookup,
+            "OMICRON_MAX_CONNECTIONS",
+            &mut self.rate_limits.max_connections,
+        )?;
+        set(
+            &lookup,
+            "OMICRON_MAX_LOBBIES_PER_IP",
+            &mut self.rate_limits.max_lobbies_per_ip,
+        )?;
+        if let Some(value) = lookup("OMICRON_METRICS_BIND") {
+            self.metrics.bind = Some(
+                value
+                    .trim()
+                    .parse()
+                    .map_err(|_| ConfigError::Environment("OMICRON_METRICS_BIND", value))?,
+            );
+        }
+        if let Some(value) = lookup("OMICRON_HTTP_BIND") {
+            self.http.bind = Some(
+                value
+                    .trim()
+                    .parse()
+                    .map_err(|_| ConfigError::Environment("OMICRON_HTTP_BIND", value))?,
+            );
+        }
+        if let Some(value) = lookup("OMICRON_WEBSOCKET_BIND") {
+            self.websocket.bind = Some(
+                value
+                    .trim()
+                    .parse()
+                    .map_err(|_| ConfigError::Environment("OMICRON_WEBSOCKET_BIND", value))?,
+            );
+        }
+        set_addresses(&lookup, "OMICRON_UDP_BIND", &mut self.udp.bind)?;
+        set(
+            &lookup,
+            "OMICRON_UDP_MAX_DATAGRAM",
+            &mut self.udp.max_datagram,
+        )?;
+        Ok(())
+    }
+
+    pub fn apply_args(&mut self, args: &Args) {
+        if !args.bind.is_empty() {
+            self.bind = args.bind.clone();
+        }
+        if let Some(backend) = args.storage {
+            self.storage.backend = backend;
+        }
+        if let Some(format) = args.log_format {
+            self.logging.format = format;
+        }
+        if let Some(address) = args.metrics {
+            self.metrics.bind = Some(address);
+        }
+        if let Some(address) = args.http {
+            self.http.bind = Some(address);
+        }
+        if let Some(address) = args.websocket {
+            self.websocket.bind = Some(address);
+        }
+        if !args.udp.is_empty() {
+            self.udp.bind = args.udp.clone();
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        use ConfigError::Invalid;
+
+        if self.bind.is_empty() {
+            return Err(Invalid("bind", "at least one address is needed"));
+        }
+        if self.worker_threads == 0 {
+            return Err(Invalid("worker_threads", "must be at least 1"));
+        }
+
+        let timeouts = [
+            ("timeouts.receive", self.timeouts.receive),
+            ("timeouts.lobby", self.timeouts.lobby),
+            ("timeouts.reap_interval", self.timeouts.reap_interval),
+            ("timeouts.join_ticket", self.timeouts.join_ticket),
+            ("timeouts.session_idle", self.timeouts.session_idle),
+        ];
+        if let Some((setting, _)) = timeouts.iter().find(|(_, seconds)| *seconds == 0) {
+            return Err(Invalid(setting, "must be at least 1 second"));
+        }
+
+        if self.limits.page_size == 0 {
+            return Err(Invalid("limits.page_size", "must be between 1 and 255"));
+        }
+        if !(1..=MAX_NAME_LENGTH).contains(&self.limits.max_name_length) {
+            return Err(Invalid(
+                "limits.max_name_length",
+                "must be between 1 and 58",
+            ));
+        }
+        if !(1..=MAX_PASSWORD_LENGTH).contains(&self.limits.max_password_length) {
+            return Err(Invalid(
+                "limits.max_password_length",
+                "must be between 1 and 63",
+            ));
+        }
+        // Version 1 pages have a u16 count, page number and total pages, and a
+        // u16 length before each entry. The whole body is sent with a u16
+        // length.
+        let longest_entry = 2 + ENTRY_OVERHEAD + MAX_CHAR_BYTES * self.limits.max_name_length;
+        if 6 + self.limits.page_size as usize * longest_entry > u16::MAX as usize {
+            return Err(Invalid(
+                "limits.page_size",
+                "a page of the longest names would not fit a response",
+            ));
+        }
+        if self.limits.max_page_size < self.limits.page_size {
+            return Err(Invalid(
+                "limits.max_page_size",
+                "must be at least limits.page_size",
+            ));
+        }
+        // Version 2 pages add a u32 total and the cursor, which holds the
+        // last name and key.
+        let longest_cursor = 2 + 3 + MAX_CHAR_BYTES * self.limits.max_name_length + MAX_KEY_LENGTH;
+        if 8 + self.limits.max_page_size as usize * longest_entry + longest_cursor
+            > u16::MAX as usize
+        {
+            return Err(Invalid(
+                "limits.max_page_size",
+                "a page of the longest names would not fit a response",
+            ));
+        }
+
+        if !(MIN_BCRYPT_COST..=MAX_BCRYPT_COST).contains(&self.bcrypt_cost) {
+            return Err(Invalid("bcrypt_cost", "must be between 4 and 31"));
+        }
+
+        // The largest UDP payload, and enough that a u16 long body fits in
+        // the 255 datagrams a response can be split into.
+        if !(512..=65507).contains(&self.udp.max_datagram) {
+            return Err(Invalid("udp.max_datagram", "must be between 512 and 65507"));
+        }
+
+        if self.rate_limits.max_connections == 0 {
+            return Err(Invalid("rate_limits.max_connections", "must be at least 1"));
+        }
+        if self.rate_limits.max_lobbies_per_ip == 0 {
+            return Err(Invalid(
+                "rate_limits.max_lobbies_per_ip",
+                "must be at least 1",
+            ));
+        }
+        let valid_rate =
+            |rate: &Rate| rate.per_second > 0.0 && rate.per_second.is_finite() && rate.burst > 0;
+        if !valid_rate(&self.rate_limits.default) {
+            return Err(Invalid(
+                "rate_limits.default",
+                "per_second and burst must be more than 0",
+            ));
+        }
+        for (message_type, rate) in &self.rate_limits.types {
+            if !protocol::MESSAGE_TYPES.contains(&message_type.as_str()) {
+                return Err(Invalid("rate_limits.types", "is not a message type"));
+            }
+            if !valid_rate(rate) {
+                return Err(Invalid(
+                    "rate_limits.types",
+                    "per_second and burst must be more than 0",
+                ));
+            }
+        }
+
+        if EnvFilter::try_new(&self.logging.filter).is_err() {
+            return Err(Invalid("logging.filter", "is not a valid filter"));
+        }
+
+        if self.storage.backend == Backend::Sqlite && !cfg!(feature = "sqlite") {
+            return Err(Invalid(
+                "storage.backend",
+                "this server was built without the sqlite feature",
+            ));
+        }
+        Ok(())
+    }
+}
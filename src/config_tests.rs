@@ -0,0 +1,323 @@
+use super::{
+    config::*,
+    database::{Lobby, ENTRY_OVERHEAD},
+    protocol::{Flags, IpAddress, Region},
+};
+use std::{collections::HashMap, net::SocketAddr, path::PathBuf};
+
+fn args(args: &[&str]) -> Result<Args, ConfigError> {
+    Args::parse(args.iter().map(|arg| arg.to_string()))
+}
+
+fn environment(variables: &[(&'static str, &str)]) -> impl Fn(&'static str) -> Option<String> {
+    let variables: HashMap<_, _> = variables
+        .iter()
+        .map(|(variable, value)| (*variable, value.to_string()))
+        .collect();
+    move |variable| variables.get(variable).cloned()
+}
+
+#[test]
+fn defaults_are_valid() {
+    Config::default().validate().unwrap();
+}
+
+#[test]
+fn parse_file() {
+    let config: Config = toml::from_str(
+        r#"
+        bind = ["127.0.0.1:6000", "[::1]:6000"]
+        bcrypt_cost = 10
+
+        [timeouts]
+        receive = 2
+
+        [limits]
+        page_size = 20
+
+        [storage]
+        backend = "memory"
+        "#,
+    )
+    .unwrap();
+
+    assert_eq!(
+        config.bind,
+        [
+            "127.0.0.1:6000".parse::<SocketAddr>().unwrap(),
+            "[::1]:6000".parse().unwrap()
+        ]
+    );
+    assert_eq!(config.bcrypt_cost, 10);
+    assert_eq!(config.timeouts.receive, 2);
+    assert_eq!(config.timeouts.lobby, Timeouts::default().lobby);
+    assert_eq!(config.limits.page_size, 20);
+    assert_eq!(config.storage.backend, Backend::Memory);
+    assert_eq!(
+        config.storage.data_directory,
+        Storage::default().data_directory
+    );
+    config.validate().unwrap();
+}
+
+#[test]
+fn example_file_is_valid() {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("omicron.example.toml");
+    let config = Config::from_file(&path).unwrap();
+    assert_eq!(config, Config::default());
+}
+
+#[test]
+fn load_test_file_is_valid() {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("omicron.load.toml");
+    let config = Config::from_file(&path).unwrap();
+    config.validate().unwrap();
+    assert_eq!(config.storage.backend, Backend::Memory);
+    assert!(config.rate_limits.rate("create").per_second >= 1000.0);
+}
+
+#[test]
+fn unknown_setting() {
+    let err = toml::from_str::<Config>("[limits]\npage_sise = 20").unwrap_err();
+    assert!(err.to_string().contains("page_sise"));
+}
+
+#[test]
+fn missing_file() {
+    let err = Config::from_file("does/not/exist.toml".as_ref()).unwrap_err();
+    assert!(matches!(err, ConfigError::Read(..)));
+}
+
+#[test]
+fn environment_overrides_file() {
+    let mut config: Config = toml::from_str("worker_threads = 4").unwrap();
+    config
+        .apply_environment(environment(&[
+            ("OMICRON_WORKER_THREADS", "8"),
+            ("OMICRON_BIND", "127.0.0.1:1, [::]:2"),
+            ("OMICRON_STORAGE", "memory"),
+            ("OMICRON_DEBUG_COMMANDS", "true"),
+            ("OMICRON_UDP_BIND", "127.0.0.1:3"),
+        ]))
+        .unwrap();
+
+    assert_eq!(config.worker_threads, 8);
+    assert_eq!(config.bind.len(), 2);
+    assert_eq!(config.storage.backend, Backend::Memory);
+    assert!(config.logging.debug_commands);
+    assert_eq!(config.udp.bind, ["127.0.0.1:3".parse().unwrap()]);
+
+    // An empty list turns UDP back off.
+    config
+        .apply_environment(environment(&[("OMICRON_UDP_BIND", "")]))
+        .unwrap();
+    assert!(config.udp.bind.is_empty());
+}
+
+#[test]
+fn bad_environment_variable() {
+    let err = Config::default()
+        .apply_environment(environment(&[("OMICRON_PAGE_SIZE", "many")]))
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        ConfigError::Environment("OMICRON_PAGE_SIZE", _)
+    ));
+}
+
+#[test]
+fn args_override_environment() {
+    let mut config = Config::default();
+    config
+        .apply_environment(environment(&[("OMICRON_STORAGE", "memory")]))
+        .unwrap();
+    config.apply_args(&args(&["--storage", "log", "-b", "127.0.0.1:7000"]).unwrap());
+
+    assert_eq!(config.storage.backend, Backend::Log);
+    assert_eq!(config.bind, ["127.0.0.1:7000".parse().unwrap()]);
+}
+
+#[test]
+fn parse_args() {
+    let parsed = args(&[
+        "-c",
+        "lobbies.toml",
+        "--bind",
+        "[::]:5475",
+        "-b",
+        "0.0.0.0:5475",
+    ])
+    .unwrap();
+    assert_eq!(parsed.config, Some(PathBuf::from("lobbies.toml")));
+    assert_eq!(parsed.bind.len(), 2);
+    assert!(!parsed.help);
+
+    assert!(args(&["--help"]).unwrap().help);
+    assert_eq!(
+        args(&["--log-format", "json"]).unwrap().log_format,
+        Some(LogFormat::Json)
+    );
+    assert_eq!(args(&["--udp", "[::]:5475"]).unwrap().udp.len(), 1);
+    assert_eq!(
+        args(&["--http", "127.0.0.1:8080"]).unwrap().http,
+        Some("127.0.0.1:8080".parse().unwrap())
+    );
+    assert_eq!(
+        args(&["--websocket", "[::]:5476"]).unwrap().websocket,
+        Some("[::]:5476".parse().unwrap())
+    );
+    assert!(matches!(args(&["--bind"]), Err(ConfigError::Usage(_))));
+    assert!(matches!(
+        args(&["-b", "localhost"]),
+        Err(ConfigError::Usage(_))
+    ));
+    assert!(matches!(
+        args(&["-s", "postgres"]),
+        Err(ConfigError::Usage(_))
+    ));
+    assert!(matches!(args(&["--verbose"]), Err(ConfigError::Usage(_))));
+}
+
+#[test]
+fn invalid_settings() {
+    let invalid = |change: fn(&mut Config)| {
+        let mut config = Config::default();
+        change(&mut config);
+        match config.validate() {
+            Err(ConfigError::Invalid(setting, _)) => setting,
+            other => panic!("expected an invalid setting, got {other:?}"),
+        }
+    };
+
+    assert_eq!(invalid(|config| config.bind.clear()), "bind");
+    assert_eq!(
+        invalid(|config| config.worker_threads = 0),
+        "worker_threads"
+    );
+    assert_eq!(
+        invalid(|config| config.timeouts.receive = 0),
+        "timeouts.receive"
+    );
+    assert_eq!(
+        invalid(|config| config.limits.page_size = 0),
+        "limits.page_size"
+    );
+    assert_eq!(
+        invalid(|config| config.limits.max_page_size = 10),
+        "limits.max_page_size"
+    );
+    assert_eq!(
+        invalid(|config| {
+            config.limits.max_page_size = 255;
+            config.limits.max_name_length = 58;
+        }),
+        "limits.max_page_size"
+    );
+    assert_eq!(
+        invalid(|config| config.limits.max_name_length = 59),
+        "limits.max_name_length"
+    );
+    assert_eq!(
+        invalid(|config| config.limits.max_password_length = 64),
+        "limits.max_password_length"
+    );
+    assert_eq!(
+        invalid(|config| {
+            config.limits.page_size = 255;
+            config.limits.max_name_length = 58;
+        }),
+        "limits.page_size"
+    );
+    assert_eq!(invalid(|config| config.bcrypt_cost = 3), "bcrypt_cost");
+    assert_eq!(
+        invalid(|config| config.udp.max_datagram = 70000),
+        "udp.max_datagram"
+    );
+    assert_eq!(
+        invalid(|config| config.rate_limits.max_connections = 0),
+        "rate_limits.max_connections"
+    );
+    assert_eq!(
+        invalid(|config| config.rate_limits.default.burst = 0),
+        "rate_limits.default"
+    );
+    assert_eq!(
+        invalid(|config| config.logging.filter = String::from("info,=")),
+        "logging.filter"
+    );
+}
+
+#[test]
+fn longest_names_fit_entries() {
+    let longest = Lobby::new(
+        Flags::new(true, true, true),
+        Region::Europe,
+        IpAddress::IpV6([0xFFFF; 8]),
+        25565,
+        8,
+        "𝄞".repeat(MAX_NAME_LENGTH),
+        String::new(),
+    )
+    .unwrap();
+
+    let fields = longest.serialise_fields();
+    assert_eq!(fields.len(), ENTRY_OVERHEAD + 4 * MAX_NAME_LENGTH);
+    assert!(fields.len() <= u8::MAX as usize);
+
+    // The largest page allowed with them fits a response.
+    let mut config = Config::default();
+    config.limits.max_name_length = MAX_NAME_LENGTH;
+    config.limits.page_size = 253;
+    config.limits.max_page_size = 253;
+    config.validate().unwrap();
+}
+
+#[cfg(not(feature = "sqlite"))]
+#[test]
+fn sqlite_needs_feature() {
+    let mut config = Config::default();
+    config.storage.backend = Backend::Sqlite;
+    assert!(matches!(
+        config.validate(),
+        Err(ConfigError::Invalid("storage.backend", _))
+    ));
+}
+
+#[test]
+fn rate_limits() {
+    let config: Config = toml::from_str(
+        r#"
+        [rate_limits]
+        max_lobbies_per_ip = 2
+
+        [rate_limits.types]
+        get = { per_second = 2.5, burst = 5 }
+        "#,
+    )
+    .unwrap();
+    config.validate().unwrap();
+
+    let rate_limits = &config.rate_limits;
+    assert_eq!(rate_limits.max_lobbies_per_ip, 2);
+    assert_eq!(
+        rate_limits.rate("get"),
+        Rate {
+            per_second: 2.5,
+            burst: 5
+        }
+    );
+    assert_eq!(rate_limits.rate("heartbeat"), rate_limits.default);
+
+    let mut config = Config::default();
+    config.rate_limits.types.insert(
+        String::from("teleport"),
+        Rate {
+            per_second: 1.0,
+            burst: 1,
+        },
+    );
+    assert!(matches!(
+        config.validate(),
+        Err(ConfigError::Invalid("rate_limits.types", _))
+    ));
+}
@@ -1,152 +1,328 @@
-use super::{DatabaseError, Lobby, Page};
+use super::{
+    check_host_password, check_lobby_password, index::Lobbies, join_code, make_key, now, token,
+    wal::WriteAheadLog, Created, DatabaseError, HostAuth, HostToken, Lobby, LobbyStore, Page,
+};
 use crate::{
-    database::PAGE_SIZE,
-    protocol::{Filter, GetRequest, IpAddress},
+    database::{page_limit, page_offset},
+    protocol::{GetRequest, IpAddress},
+};
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{Mutex, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard},
 };
-use bcrypt::verify;
-use std::collections::HashMap;
+use tracing::{error, info, warn};
 
-static mut DATABASE: Option<HashMap<String, Lobby>> = None;
+/// How many logged changes to allow before compacting them into a snapshot.
+const SNAPSHOT_EVERY: u32 = 1000;
 
-fn make_key(ip: IpAddress, port: u16) -> String {
-    format!("{ip}:{port}")
+#[derive(Debug, Default)]
+pub struct InMemory {
+    lobbies: RwLock<Lobbies>,
+    log: Option<Mutex<WriteAheadLog>>,
+    /// How many lobbies one ip, or IPv6 /64, may have at once, any number if
+    /// `None`.
+    lobbies_per_host: Option<usize>,
 }
 
-pub fn dbg_database() {
-    dbg!(unsafe { &DATABASE });
-}
+impl InMemory {
+    /// A store that forgets every lobby when the server stops.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A store that logs every change to `directory` and replays the
+    /// snapshot and log found there.
+    pub fn open<P: AsRef<Path>>(directory: P) -> Result<Self, DatabaseError> {
+        Self::open_with(directory, SNAPSHOT_EVERY)
+    }
+
+    /// Limits how many lobbies one host ip, or IPv6 /64, may have at once.
+    pub fn with_lobbies_per_host(mut self, limit: usize) -> Self {
+        self.lobbies_per_host = Some(limit);
+        self
+    }
+
+    pub(super) fn open_with<P: AsRef<Path>>(
+        directory: P,
+        snapshot_every: u32,
+    ) -> Result<Self, DatabaseError> {
+        let (log, lobbies) = WriteAheadLog::open(directory, snapshot_every).map_err(|err| {
+            error!(%err, "Failed to replay the write-ahead log");
+            DatabaseError::NotInitialised
+        })?;
+        info!(lobbies = lobbies.len(), "Replayed the write-ahead log");
+        Ok(Self {
+            lobbies: RwLock::new(Lobbies::from(lobbies)),
+            log: Some(Mutex::new(log)),
+            lobbies_per_host: None,
+        })
+    }
 
-pub fn init() {
-    let db = unsafe { &mut DATABASE };
-    *db = Some(HashMap::new());
+    // A panic while holding the lock can only happen between whole-lobby
+    // inserts/removes, so the map is still consistent and safe to keep using.
+    fn read(&self) -> RwLockReadGuard<'_, Lobbies> {
+        self.lobbies.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, Lobbies> {
+        self.lobbies.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Logs a change before it is applied. Callers hold the write lock, so
+    /// the log sees changes in the same order as the map.
+    fn log<F>(&self, append: F) -> Result<(), DatabaseError>
+    where
+        F: FnOnce(&mut WriteAheadLog) -> std::io::Result<()>,
+    {
+        match &self.log {
+            Some(log) => {
+                append(&mut log.lock().unwrap_or_else(PoisonError::into_inner)).map_err(|err| {
+                    error!(%err, "Failed to append to the write-ahead log");
+                    DatabaseError::StorageFailure
+                })
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn snapshot_if_due(&self, db: &HashMap<String, Lobby>) {
+        if let Some(log) = &self.log {
+            let mut log = log.lock().unwrap_or_else(PoisonError::into_inner);
+            if log.snapshot_due() {
+                // The changes are safe in the log, a failed snapshot is
+                // simply retried after the next change.
+                if let Err(err) = log.snapshot(db) {
+                    warn!(%err, "Failed to snapshot the write-ahead log");
+                }
+            }
+        }
+    }
+
+    /// Checks a password sent as `auth` against a copy of the host's lobby,
+    /// bcrypt is slow on purpose so this is done without holding the lock.
+    /// Returns the hash it was checked against for `authorised`.
+    fn check_password(&self, key: &str, auth: &HostAuth) -> Result<Option<String>, DatabaseError> {
+        let HostAuth::Password(password) = auth else {
+            return Ok(None);
+        };
+        let lobby = self
+            .read()
+            .get(key)
+            .cloned()
+            .ok_or(DatabaseError::LobbyDoesNotExist)?;
+        check_host_password(&lobby, password.clone())?;
+        Ok(Some(lobby.password))
+    }
 }
 
-pub fn create(lobby: Option<Lobby>) -> Result<(), DatabaseError> {
-    if lobby.is_none() {
-        return Err(DatabaseError::FailedToHashPassword);
+/// The host's lobby, if `auth` is the token it was issued, or a password
+/// that was `checked` against the hash it still has.
+fn authorised<'a>(
+    db: &'a HashMap<String, Lobby>,
+    key: &str,
+    auth: &HostAuth,
+    checked: Option<&str>,
+) -> Result<&'a Lobby, DatabaseError> {
+    let lobby = db.get(key).ok_or(DatabaseError::LobbyDoesNotExist)?;
+    let proven = match auth {
+        HostAuth::Token(token) => token.matches(&lobby.token_hash),
+        // Fails if the lobby was replaced while the password was checked.
+        HostAuth::Password(_) => checked == Some(lobby.password.as_str()),
+    };
+    if !proven {
+        return Err(DatabaseError::InvalidCredentials);
     }
+    Ok(lobby)
+}
 
-    if let Some(db) = unsafe { &mut DATABASE } {
-        let lobby = lobby.unwrap();
+impl LobbyStore for InMemory {
+    fn create(&self, mut lobby: Lobby) -> Result<Created, DatabaseError> {
         let key = make_key(lobby.host_ip, lobby.host_port);
 
+        let mut db = self.write();
         if db.contains_key(&key) {
             return Err(DatabaseError::LobbyAlreadyExists);
         }
+        if self
+            .lobbies_per_host
+            .is_some_and(|limit| db.hosted(lobby.host_ip) >= limit)
+        {
+            return Err(DatabaseError::TooManyLobbies);
+        }
 
-        db.insert(key, lobby);
-        Ok(())
-    } else {
-        Err(DatabaseError::NotInitialised)
-    }
-}
+        let join_code = loop {
+            let join_code = join_code::generate()?;
+            if db.by_code(&join_code).is_none() {
+                break join_code;
+            }
+        };
+        lobby.join_code = Some(join_code.clone());
+        let token = token::issue(&mut lobby)?;
 
-pub fn modify(lobby: Option<Lobby>) -> Result<(), DatabaseError> {
-    if lobby.is_none() {
-        return Err(DatabaseError::FailedToHashPassword);
+        self.log(|log| log.put(&lobby))?;
+        db.insert(key, lobby);
+        self.snapshot_if_due(&db);
+        Ok(Created { token, join_code })
     }
 
-    if let Some(db) = unsafe { &mut DATABASE } {
-        let lobby = lobby.unwrap();
+    fn modify(&self, mut lobby: Lobby, auth: HostAuth) -> Result<(), DatabaseError> {
         let key = make_key(lobby.host_ip, lobby.host_port);
+        let checked = self.check_password(&key, &auth)?;
 
-        if !db.contains_key(&key) {
-            return Err(DatabaseError::LobbyDoesNotExist);
+        let mut db = self.write();
+        let stored = authorised(&db, &key, &auth, checked.as_deref())?;
+        lobby.token_hash = stored.token_hash;
+        lobby.password_auth = stored.password_auth;
+        lobby.join_code = stored.join_code.clone();
+        if let Some(hash) = checked {
+            lobby.password = hash;
         }
+        lobby.reservations = stored.reservations.clone();
 
+        self.log(|log| log.put(&lobby))?;
         db.insert(key, lobby);
+        self.snapshot_if_due(&db);
         Ok(())
-    } else {
-        Err(DatabaseError::NotInitialised)
     }
-}
 
-pub fn delete(
-    host_ip: IpAddress,
-    port: u16,
-    password: Option<String>,
-) -> Result<(), DatabaseError> {
-    if let Some(db) = unsafe { &mut DATABASE } {
+    fn update(
+        &self,
+        host_ip: IpAddress,
+        port: u16,
+        token: HostToken,
+        change: &dyn Fn(&mut Lobby),
+    ) -> Result<Lobby, DatabaseError> {
         let key = make_key(host_ip, port);
-        let lobby = db.get(&key).ok_or(DatabaseError::LobbyDoesNotExist)?;
 
-        if let Some(password) = password {
-            let password_check = verify(password, &lobby.password)
-                .map_err(|_| DatabaseError::FailedToVerifyPassword)?;
-            if !password_check {
-                return Err(DatabaseError::InvalidCredentials);
+        let mut db = self.write();
+        let mut lobby = authorised(&db, &key, &HostAuth::Token(token), None)?.clone();
+        change(&mut lobby);
+
+        self.log(|log| log.put(&lobby))?;
+        db.insert(key, lobby.clone());
+        self.snapshot_if_due(&db);
+        Ok(lobby)
+    }
+
+    fn delete(&self, host_ip: IpAddress, port: u16, auth: HostAuth) -> Result<(), DatabaseError> {
+        let key = make_key(host_ip, port);
+        let checked = self.check_password(&key, &auth)?;
+
+        let mut db = self.write();
+        authorised(&db, &key, &auth, checked.as_deref())?;
+
+        self.log(|log| log.remove(&key))?;
+        db.remove(&key);
+        self.snapshot_if_due(&db);
+        Ok(())
+    }
+
+    fn rotate_token(
+        &self,
+        host_ip: IpAddress,
+        port: u16,
+        token: HostToken,
+    ) -> Result<HostToken, DatabaseError> {
+        let key = make_key(host_ip, port);
+
+        let mut db = self.write();
+        let mut lobby = authorised(&db, &key, &HostAuth::Token(token), None)?.clone();
+
+        let token = token::issue(&mut lobby)?;
+        self.log(|log| log.put(&lobby))?;
+        db.insert(key, lobby);
+        self.snapshot_if_due(&db);
+        Ok(token)
+    }
+
+    fn get(&self, request: GetRequest) -> Result<Page, DatabaseError> {
+        let db = self.read();
+
+        let limit = page_limit(&request);
+        let offset = page_offset(&request, limit)?;
+        // One lobby more tells whether there is a next page.
+        let (lobbies, num_lobbies) = db.find(&request, offset, limit + 1)?;
+        let lobbies = lobbies.into_iter().cloned().collect();
+
+        Ok(Page::new(lobbies, &request, limit, num_lobbies))
+    }
+
+    fn lookup(&self, join_code: &str) -> Result<Lobby, DatabaseError> {
+        self.read()
+            .by_code(&join_code::normalise(join_code))
+            .cloned()
+            .ok_or(DatabaseError::LobbyDoesNotExist)
+    }
+
+    fn join(
+        &self,
+        host_ip: IpAddress,
+        port: u16,
+        password: Option<String>,
+        expires: u64,
+    ) -> Result<Lobby, DatabaseError> {
+        let key = make_key(host_ip, port);
+
+        // bcrypt is slow on purpose, so verify without holding the lock.
+        let lobby = self
+            .read()
+            .get(&key)
+            .cloned()
+            .ok_or(DatabaseError::LobbyDoesNotExist)?;
+        check_lobby_password(&lobby, password)?;
+
+        // Reservations are let go of when the ticket runs out, they are not
+        // worth logging.
+        let mut db = self.write();
+        match db.get(&key) {
+            None => return Err(DatabaseError::LobbyDoesNotExist),
+            // The password was changed while we were verifying.
+            Some(stored) if stored.password != lobby.password => {
+                return Err(DatabaseError::InvalidCredentials)
             }
+            Some(stored) if stored.is_full(now()) => return Err(DatabaseError::LobbyFull),
+            Some(_) => {}
         }
+        db.reserve(&key, now(), expires)
+            .cloned()
+            .ok_or(DatabaseError::LobbyDoesNotExist)
+    }
 
-        if db.remove(&key).is_some() {
-            Ok(())
-        } else {
-            Err(DatabaseError::LobbyDoesNotExist)
-        }
-    } else {
-        Err(DatabaseError::NotInitialised)
+    fn list(&self) -> Result<Vec<Lobby>, DatabaseError> {
+        Ok(self.read().values().cloned().collect())
     }
-}
 
-pub fn get(request: GetRequest) -> Result<Page, DatabaseError> {
-    if let Some(db) = unsafe { &mut DATABASE } {
-        // Filter by regions and search?
-        let mut lobbies = if let Some(search) = request.search {
-            db.iter()
-                .filter(|&(_, lobby)| request.regions.contains(&lobby.region))
-                .filter(|&(_, lobby)| {
-                    lobby
-                        .lobby_name
-                        .to_lowercase()
-                        .contains(&search.to_lowercase())
-                })
-                .map(|(_, lobby)| lobby)
-                .collect::<Vec<_>>()
-        } else {
-            db.iter()
-                .filter(|&(_, lobby)| request.regions.contains(&lobby.region))
-                .map(|(_, lobby)| lobby)
-                .collect::<Vec<_>>()
-        };
+    fn heartbeat(&self, host_ip: IpAddress, port: u16) -> Result<(), DatabaseError> {
+        let key = make_key(host_ip, port);
+        let last_seen = now();
 
-        // Sort by filter
-        match request.filter {
-            Filter::NameAscending => lobbies.sort_by(|&left, &right| {
-                left.lobby_name
-                    .to_lowercase()
-                    .cmp(&right.lobby_name.to_lowercase())
-            }),
-            Filter::NameDescending => lobbies.sort_by(|&left, &right| {
-                right
-                    .lobby_name
-                    .to_lowercase()
-                    .cmp(&left.lobby_name.to_lowercase())
-            }),
-            Filter::PlayerCountAscending => {
-                lobbies.sort_by(|&left, &right| left.current_players.cmp(&right.current_players))
-            }
-            Filter::PlayerCountDescending => {
-                lobbies.sort_by(|&left, &right| right.current_players.cmp(&left.current_players))
-            }
-            Filter::Search => Err(DatabaseError::InvalidFilter)?,
+        let mut db = self.write();
+        if !db.contains_key(&key) {
+            return Err(DatabaseError::LobbyDoesNotExist);
         }
 
-        let num_lobbies = lobbies.len() as u8;
-        let lobbies: Vec<_> = lobbies
+        self.log(|log| log.touch(&key, last_seen))?;
+        db.touch(&key, last_seen);
+        self.snapshot_if_due(&db);
+        Ok(())
+    }
+
+    fn expire(&self, cutoff: u64) -> Result<usize, DatabaseError> {
+        let mut db = self.write();
+        let stale: Vec<String> = db
             .iter()
-            .skip(
-                (request.page_num as usize)
-                    .checked_mul(PAGE_SIZE as usize)
-                    .ok_or(DatabaseError::BadMessage)?,
-            )
-            .take(PAGE_SIZE as usize)
-            .map(|&lobby| lobby.clone())
+            .filter(|(_, lobby)| lobby.last_seen < cutoff)
+            .map(|(key, _)| key.clone())
             .collect();
 
-        let response = Page::new(lobbies, request.page_num, num_lobbies / PAGE_SIZE);
-
-        Ok(response)
-    } else {
-        Err(DatabaseError::NotInitialised)
+        for key in &stale {
+            self.log(|log| log.remove(key))?;
+            db.remove(key);
+        }
+        if !stale.is_empty() {
+            self.snapshot_if_due(&db);
+        }
+        Ok(stale.len())
     }
 }
@@ -0,0 +1,498 @@
+//! The in-memory store's lobbies, with the indexes that answer a Get without
+//! looking at every lobby: public lobbies are bucketed by region, each bucket
+//! ordered by name and by player count, and their names are split into
+//! trigrams for search. Private lobbies are never listed, so only the map
+//! holds them. Predicates are checked on each lobby the other indexes leave,
+//! and each bucket tallies its lobbies by what predicates look at, so a Get
+//! without a search is counted without walking every lobby. Every lobby,
+//! public or private, has its join code indexed so a Lookup does not look at
+//! every lobby, and is counted towards its host's group.
+
+use super::{host_group, DatabaseError, Lobby};
+use crate::protocol::{Filter, Flags, GetRequest, IpAddress, Predicates};
+use std::{
+    cmp::Ordering,
+    collections::{btree_set, BTreeMap, BTreeSet, HashMap, HashSet},
+    iter,
+    ops::{
+        Bound::{Excluded, Unbounded},
+        Deref,
+    },
+};
+
+type Trigram = [char; 3];
+
+/// A cursor as the indexes compare it.
+struct Position {
+    name: String,
+    players: u8,
+    key: String,
+}
+
+/// Lobbies by key. Reads go through `Deref`, changes through `insert` and
+/// `remove` so that the indexes follow.
+#[derive(Debug, Default)]
+pub struct Lobbies {
+    lobbies: HashMap<String, Lobby>,
+    /// One per region, by the region's bit.
+    buckets: [Bucket; 6],
+    /// The keys of the public lobbies whose lowercase name has the trigram.
+    trigrams: HashMap<Trigram, HashSet<String>>,
+    /// The key of the lobby with each join code.
+    codes: HashMap<String, String>,
+    /// How many lobbies each `host_group` has.
+    hosted: HashMap<IpAddress, usize>,
+}
+
+#[derive(Debug, Default)]
+struct Bucket {
+    /// Keys by lowercase name, ties in key order like the sqlite backend.
+    by_name: BTreeMap<String, BTreeSet<String>>,
+    by_players: BTreeMap<u8, BTreeSet<String>>,
+    /// How many lobbies have each tally.
+    tallies: BTreeMap<Tally, usize>,
+}
+
+impl Bucket {
+    /// How many of the bucket's lobbies `predicates` allow.
+    fn allowed(&self, predicates: &Predicates) -> usize {
+        if predicates.players.is_empty() {
+            return 0;
+        }
+        let lowest = Tally {
+            players: *predicates.players.start(),
+            full: false,
+            password: false,
+            ipv6: false,
+        };
+        let highest = Tally {
+            players: *predicates.players.end(),
+            full: true,
+            password: true,
+            ipv6: true,
+        };
+        self.tallies
+            .range(lowest..=highest)
+            .filter(|(tally, _)| tally.allowed(predicates))
+            .map(|(_, count)| count)
+            .sum()
+    }
+}
+
+/// What predicates look at in a lobby.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct Tally {
+    players: u8,
+    full: bool,
+    password: bool,
+    ipv6: bool,
+}
+
+impl Tally {
+    fn of(lobby: &Lobby) -> Self {
+        Self {
+            players: lobby.current_players,
+            full: lobby.current_players >= lobby.max_players,
+            password: lobby.flags.has_password(),
+            ipv6: lobby.flags.is_ipv6(),
+        }
+    }
+
+    fn allowed(self, predicates: &Predicates) -> bool {
+        // Any room left passes `hide_full` alike, so one more than the
+        // players stands in for the maximum.
+        let max_players = match self.full {
+            true => self.players,
+            false => self.players + 1,
+        };
+        let flags = Flags::new(self.ipv6, true, self.password);
+        predicates.allow(&flags, self.players, max_players)
+    }
+}
+
+impl Deref for Lobbies {
+    type Target = HashMap<String, Lobby>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.lobbies
+    }
+}
+
+impl From<HashMap<String, Lobby>> for Lobbies {
+    fn from(lobbies: HashMap<String, Lobby>) -> Self {
+        let mut indexed = Self::default();
+        for (key, lobby) in lobbies {
+            indexed.insert(key, lobby);
+        }
+        indexed
+    }
+}
+
+impl Lobbies {
+    pub fn insert(&mut self, key: String, lobby: Lobby) {
+        self.remove(&key);
+        self.index(&key, &lobby);
+        if let Some(join_code) = &lobby.join_code {
+            self.codes.insert(join_code.clone(), key.clone());
+        }
+        *self.hosted.entry(host_group(lobby.host_ip)).or_default() += 1;
+        self.lobbies.insert(key, lobby);
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<Lobby> {
+        let lobby = self.lobbies.remove(key)?;
+        self.unindex(key, &lobby);
+        if let Some(join_code) = &lobby.join_code {
+            self.codes.remove(join_code);
+        }
+        let group = host_group(lobby.host_ip);
+        if let Some(hosted) = self.hosted.get_mut(&group) {
+            *hosted -= 1;
+            if *hosted == 0 {
+                self.hosted.remove(&group);
+            }
+        }
+        Some(lobby)
+    }
+
+    /// How many lobbies the hosts in `host_ip`'s group have.
+    pub fn hosted(&self, host_ip: IpAddress) -> usize {
+        self.hosted
+            .get(&host_group(host_ip))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Sets when the lobby was last heard from, which no index uses.
+    pub fn touch(&mut self, key: &str, last_seen: u64) {
+        if let Some(lobby) = self.lobbies.get_mut(key) {
+            lobby.last_seen = last_seen;
+        }
+    }
+
+    /// Reserves a player slot until `expires`, letting go of the ones that
+    /// ran out before `now`. No index uses reservations.
+    pub fn reserve(&mut self, key: &str, now: u64, expires: u64) -> Option<&Lobby> {
+        let lobby = self.lobbies.get_mut(key)?;
+        lobby.reservations.retain(|&reserved| reserved >= now);
+        lobby.reservations.push(expires);
+        Some(lobby)
+    }
+
+    /// The lobby with `join_code`, which must already be normalised.
+    pub fn by_code(&self, join_code: &str) -> Option<&Lobby> {
+        self.codes
+            .get(join_code)
+            .and_then(|key| self.lobbies.get(key))
+    }
+
+    /// The lobbies `request` lists from `offset`, or from right after its
+    /// cursor, at most `limit` of them, and how many it lists on every page.
+    pub fn find(
+        &self,
+        request: &GetRequest,
+        offset: usize,
+        limit: usize,
+    ) -> Result<(Vec<&Lobby>, usize), DatabaseError> {
+        let regions = request
+            .regions
+            .iter()
+            .fold(0, |mask, region| mask | region.clone() as u8);
+        let after = request.cursor.as_ref().map(|cursor| Position {
+            name: cursor.lobby_name.to_lowercase(),
+            players: cursor.current_players,
+            key: cursor.key.clone(),
+        });
+        let allowed = |lobby: &Lobby| {
+            let predicates = &request.predicates;
+            predicates.allow(&lobby.flags, lobby.current_players, lobby.max_players)
+        };
+        let Some(search) = request.search.as_ref().map(|search| search.to_lowercase()) else {
+            // The walk stops once the page is full, the tallies count the rest.
+            let total = self
+                .selected(regions)
+                .map(|bucket| bucket.allowed(&request.predicates))
+                .sum();
+            let keys = self
+                .ordered(regions, request.filter, after.as_ref())?
+                .filter(|&key| allowed(&self.lobbies[key]));
+            return Ok((self.page(keys, offset, limit), total));
+        };
+
+        let descending = descending(request.filter);
+        let keys: Vec<&String> = match self.candidates(&search) {
+            Some(candidates) => {
+                let mut found: Vec<_> = candidates
+                    .into_iter()
+                    .map(|key| (key, &self.lobbies[key]))
+                    .filter(|(_, lobby)| regions & lobby.region.clone() as u8 != 0)
+                    .filter(|(_, lobby)| allowed(lobby))
+                    .map(|(key, lobby)| (key, lobby.lobby_name.to_lowercase(), lobby))
+                    .filter(|(_, name, _)| name.contains(&search))
+                    .collect();
+                match request.filter {
+                    Filter::NameAscending | Filter::NameDescending => {
+                        found.sort_by(|(left, left_name, _), (right, right_name, _)| {
+                            order((left_name, *left), (right_name, *right), descending)
+                        })
+                    }
+                    Filter::PlayerCountAscending | Filter::PlayerCountDescending => {
+                        found.sort_by(|(left, _, left_lobby), (right, _, right_lobby)| {
+                            let players =
+                                (&left_lobby.current_players, &right_lobby.current_players);
+                            order((players.0, *left), (players.1, *right), descending)
+                        })
+                    }
+                    Filter::Search => Err(DatabaseError::InvalidFilter)?,
+                }
+                found.into_iter().map(|(key, _, _)| key).collect()
+            }
+            // Too short for a trigram, so every listed lobby is checked.
+            None => self
+                .ordered(regions, request.filter, None)?
+                .filter(|&key| {
+                    let lobby = &self.lobbies[key];
+                    allowed(lobby) && lobby.lobby_name.to_lowercase().contains(&search)
+                })
+                .collect(),
+        };
+
+        let total = keys.len();
+        let start = match &after {
+            Some(after) => keys.partition_point(|key| {
+                let lobby = &self.lobbies[*key];
+                let sorted = match request.filter {
+                    Filter::NameAscending | Filter::NameDescending => order(
+                        (&lobby.lobby_name.to_lowercase(), key),
+                        (&after.name, &after.key),
+                        descending,
+                    ),
+                    _ => order(
+                        (&lobby.current_players, key),
+                        (&after.players, &after.key),
+                        descending,
+                    ),
+                };
+                sorted != Ordering::Greater
+            }),
+            None => 0,
+        };
+        Ok((
+            self.page(keys[start..].iter().copied(), offset, limit),
+            total,
+        ))
+    }
+
+    fn page<'k>(
+        &self,
+        keys: impl Iterator<Item = &'k String>,
+        offset: usize,
+        limit: usize,
+    ) -> Vec<&Lobby> {
+        keys.skip(offset)
+            .take(limit)
+            .map(|key| &self.lobbies[key])
+            .collect()
+    }
+
+    fn selected(&self, regions: u8) -> impl Iterator<Item = &Bucket> {
+        self.buckets
+            .iter()
+            .enumerate()
+            .filter(move |(bit, _)| regions & 1 << bit != 0)
+            .map(|(_, bucket)| bucket)
+    }
+
+    /// The keys of every public lobby in `regions` sorted by `filter`, from
+    /// right after `after`.
+    fn ordered<'a>(
+        &'a self,
+        regions: u8,
+        filter: Filter,
+        after: Option<&'a Position>,
+    ) -> Result<Box<dyn Iterator<Item = &'a String> + 'a>, DatabaseError> {
+        let descending = descending(filter);
+        let buckets = self.selected(regions);
+        Ok(match filter {
+            Filter::NameAscending | Filter::NameDescending => {
+                let after = after.map(|after| (&after.name, after.key.as_str()));
+                Box::new(merge(
+                    buckets
+                        .map(|bucket| walk(&bucket.by_name, descending, after))
+                        .collect(),
+                    descending,
+                ))
+            }
+            Filter::PlayerCountAscending | Filter::PlayerCountDescending => {
+                let after = after.map(|after| (&after.players, after.key.as_str()));
+                Box::new(merge(
+                    buckets
+                        .map(|bucket| walk(&bucket.by_players, descending, after))
+                        .collect(),
+                    descending,
+                ))
+            }
+            Filter::Search => Err(DatabaseError::InvalidFilter)?,
+        })
+    }
+
+    /// The public lobbies whose name has every trigram of `search`, `None`
+    /// if it is shorter than a trigram.
+    fn candidates(&self, search: &str) -> Option<Vec<&String>> {
+        let mut lists = Vec::new();
+        for trigram in trigrams(search) {
+            match self.trigrams.get(&trigram) {
+                Some(keys) => lists.push(keys),
+                None => return Some(Vec::new()),
+            }
+        }
+        lists.sort_by_key(|keys| keys.len());
+        let (shortest, rest) = lists.split_first()?;
+        Some(
+            shortest
+                .iter()
+                .filter(|key| rest.iter().all(|keys| keys.contains(*key)))
+                .collect(),
+        )
+    }
+
+    fn index(&mut self, key: &str, lobby: &Lobby) {
+        if !lobby.flags.is_public() {
+            return;
+        }
+        let name = lobby.lobby_name.to_lowercase();
+        for trigram in trigrams(&name) {
+            self.trigrams
+                .entry(trigram)
+                .or_default()
+                .insert(key.to_string());
+        }
+        let bucket = &mut self.buckets[bucket(lobby)];
+        bucket
+            .by_name
+            .entry(name)
+            .or_default()
+            .insert(key.to_string());
+        bucket
+            .by_players
+            .entry(lobby.current_players)
+            .or_default()
+            .insert(key.to_string());
+        *bucket.tallies.entry(Tally::of(lobby)).or_default() += 1;
+    }
+
+    fn unindex(&mut self, key: &str, lobby: &Lobby) {
+        if !lobby.flags.is_public() {
+            return;
+        }
+        let name = lobby.lobby_name.to_lowercase();
+        for trigram in trigrams(&name) {
+            if let Some(keys) = self.trigrams.get_mut(&trigram) {
+                keys.remove(key);
+                if keys.is_empty() {
+                    self.trigrams.remove(&trigram);
+                }
+            }
+        }
+        let bucket = &mut self.buckets[bucket(lobby)];
+        unlist(&mut bucket.by_name, name, key);
+        unlist(&mut bucket.by_players, lobby.current_players, key);
+        let tally = Tally::of(lobby);
+        if let Some(count) = bucket.tallies.get_mut(&tally) {
+            *count -= 1;
+            if *count == 0 {
+                bucket.tallies.remove(&tally);
+            }
+        }
+    }
+}
+
+fn bucket(lobby: &Lobby) -> usize {
+    (lobby.region.clone() as u8).trailing_zeros() as usize
+}
+
+fn descending(filter: Filter) -> bool {
+    matches!(
+        filter,
+        Filter::NameDescending | Filter::PlayerCountDescending
+    )
+}
+
+fn trigrams(name: &str) -> HashSet<Trigram> {
+    let chars: Vec<char> = name.chars().collect();
+    chars
+        .windows(3)
+        .map(|window| [window[0], window[1], window[2]])
+        .collect()
+}
+
+/// Takes `key` out of the set at `value`, dropping the set once empty.
+fn unlist<K: Ord>(index: &mut BTreeMap<K, BTreeSet<String>>, value: K, key: &str) {
+    if let Some(keys) = index.get_mut(&value) {
+        keys.remove(key);
+        if keys.is_empty() {
+            index.remove(&value);
+        }
+    }
+}
+
+/// Sorts by the indexed value, then by key in either direction, as the
+/// sqlite backend's `ORDER BY value, key` does.
+fn order<K: Ord>(left: (&K, &String), right: (&K, &String), descending: bool) -> Ordering {
+    let by_value = left.0.cmp(right.0);
+    match descending {
+        false => by_value,
+        true => by_value.reverse(),
+    }
+    .then_with(|| left.1.cmp(right.1))
+}
+
+type Walk<'a, K> = Box<dyn Iterator<Item = (&'a K, &'a String)> + 'a>;
+
+/// One bucket's index in order, from right after `after`'s value and key.
+fn walk<'a, K: Ord>(
+    index: &'a BTreeMap<K, BTreeSet<String>>,
+    descending: bool,
+    after: Option<(&'a K, &'a str)>,
+) -> Walk<'a, K> {
+    fn group<'a, K>(
+        (value, keys): (&'a K, &'a BTreeSet<String>),
+    ) -> iter::Zip<iter::Repeat<&'a K>, btree_set::Iter<'a, String>> {
+        iter::repeat(value).zip(keys)
+    }
+    let Some((value, key)) = after else {
+        return match descending {
+            false => Box::new(index.iter().flat_map(group)),
+            true => Box::new(index.iter().rev().flat_map(group)),
+        };
+    };
+
+    // Ties are in key order either way, so the rest of the value's keys
+    // come first, then the values past it.
+    let rest = index
+        .get_key_value(value)
+        .into_iter()
+        .flat_map(move |(value, keys)| {
+            iter::repeat(value).zip(keys.range::<str, _>((Excluded(key), Unbounded)))
+        });
+    match descending {
+        false => Box::new(rest.chain(index.range((Excluded(value), Unbounded)).flat_map(group))),
+        true => Box::new(rest.chain(index.range(..value).rev().flat_map(group))),
+    }
+}
+
+/// Merges the buckets' walks into one, like the last step of a merge sort.
+fn merge<'a, K: Ord + 'a>(
+    walks: Vec<Walk<'a, K>>,
+    descending: bool,
+) -> impl Iterator<Item = &'a String> + 'a {
+    let mut walks: Vec<_> = walks.into_iter().map(Iterator::peekable).collect();
+    iter::from_fn(move || {
+        let (next, _) = walks
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(index, walk)| walk.peek().map(|&entry| (index, entry)))
+            .min_by(|(_, left), (_, right)| order(*left, *right, descending))?;
+        walks[next].next().map(|(_, key)| key)
+    })
+}
@@ -0,0 +1,323 @@
+use super::{index::Lobbies, Lobby, NO_TOKEN};
+use crate::protocol::{Cursor, Filter, Flags, GetRequest, IpAddress, Predicates, Region};
+use std::{
+    cmp::Reverse,
+    time::{Duration, Instant},
+};
+
+const WORDS: [&str; 6] = ["Alpha", "bravo", "CASTLE", "dune", "Émber", "frost"];
+const FILTERS: [Filter; 4] = [
+    Filter::NameAscending,
+    Filter::NameDescending,
+    Filter::PlayerCountAscending,
+    Filter::PlayerCountDescending,
+];
+
+/// xorshift, so that every run builds the same lobbies.
+struct Random(u64);
+
+impl Random {
+    fn below(&mut self, bound: u64) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 % bound
+    }
+}
+
+fn random_lobby(random: &mut Random, port: u16) -> (String, Lobby) {
+    let word = WORDS[random.below(WORDS.len() as u64) as usize];
+    let lobby = Lobby {
+        flags: Flags::new(
+            random.below(3) == 0,
+            random.below(4) != 0,
+            random.below(2) == 0,
+        ),
+        region: Region::get_regions(1 << random.below(6)).remove(0),
+        host_ip: IpAddress::IpV4([10, 0, 0, 1]),
+        host_port: port,
+        max_players: 16,
+        lobby_name: format!("{word} {}", random.below(50)),
+        password: String::new(),
+        current_players: random.below(17) as u8,
+        last_seen: 0,
+        token_hash: NO_TOKEN,
+        password_auth: false,
+        join_code: None,
+        reservations: Vec::new(),
+    };
+    (format!("10.0.0.1:{port}"), lobby)
+}
+
+fn random_lobbies(count: u16, seed: u64) -> Lobbies {
+    let mut random = Random(seed);
+    let mut lobbies = Lobbies::default();
+    for port in 0..count {
+        let (key, lobby) = random_lobby(&mut random, port);
+        lobbies.insert(key, lobby);
+    }
+    lobbies
+}
+
+/// What Get did before the indexes: check every lobby, then sort them all.
+fn full_scan<'a>(
+    lobbies: &'a Lobbies,
+    request: &GetRequest,
+    offset: usize,
+    limit: usize,
+) -> (Vec<&'a Lobby>, usize) {
+    let mut found: Vec<(&String, &Lobby)> = lobbies
+        .iter()
+        .filter(|(_, lobby)| {
+            request.matches(
+                &lobby.flags,
+                &lobby.region,
+                &lobby.lobby_name,
+                lobby.current_players,
+                lobby.max_players,
+            )
+        })
+        .collect();
+    match request.filter {
+        Filter::NameAscending => {
+            found.sort_by_key(|(key, lobby)| (lobby.lobby_name.to_lowercase(), *key))
+        }
+        Filter::NameDescending => {
+            found.sort_by_key(|(key, lobby)| (Reverse(lobby.lobby_name.to_lowercase()), *key))
+        }
+        Filter::PlayerCountAscending => {
+            found.sort_by_key(|(key, lobby)| (lobby.current_players, *key))
+        }
+        Filter::PlayerCountDescending => {
+            found.sort_by_key(|(key, lobby)| (Reverse(lobby.current_players), *key))
+        }
+        Filter::Search => unreachable!(),
+    }
+    let total = found.len();
+    let page = found
+        .into_iter()
+        .skip(offset)
+        .take(limit)
+        .map(|(_, lobby)| lobby)
+        .collect();
+    (page, total)
+}
+
+fn predicates() -> [Predicates; 4] {
+    [
+        Predicates::default(),
+        Predicates {
+            hide_full: true,
+            hide_password: true,
+            ..Predicates::default()
+        },
+        Predicates {
+            players: 4..=12,
+            ipv6: Some(false),
+            ..Predicates::default()
+        },
+        // The fewest above the most, as a client can send.
+        Predicates::try_from([0, 9, 3]).unwrap(),
+    ]
+}
+
+fn requests() -> Vec<GetRequest> {
+    let mut requests = Vec::new();
+    for filter in FILTERS {
+        for mask in [0b111111, 0b000100, 0b101001, 0] {
+            for search in [None, Some("a"), Some("RAV"), Some("ember 1"), Some("zzz")] {
+                for predicates in predicates() {
+                    requests.push(GetRequest {
+                        filter,
+                        regions: Region::get_regions(mask),
+                        page_num: 0,
+                        search: search.map(String::from),
+                        page_size: None,
+                        cursor: None,
+                        predicates,
+                    });
+                }
+            }
+        }
+    }
+    requests
+}
+
+#[test]
+fn find_matches_a_full_scan() {
+    let mut lobbies = random_lobbies(400, 1);
+
+    // Renames, new player counts, lobbies made private and removed lobbies
+    // must all leave the indexes behind.
+    let mut random = Random(2);
+    for port in (0..400).step_by(3) {
+        let (key, lobby) = random_lobby(&mut random, port);
+        lobbies.insert(key, lobby);
+    }
+    for port in (0..400).step_by(7) {
+        assert!(lobbies.remove(&format!("10.0.0.1:{port}")).is_some());
+    }
+    assert!(lobbies.remove("10.0.0.1:0").is_none());
+
+    for request in requests() {
+        for (offset, limit) in [(0, 15), (15, 15), (30, 100), (1000, 15)] {
+            let indexed = lobbies.find(&request, offset, limit).unwrap();
+            assert_eq!(
+                indexed,
+                full_scan(&lobbies, &request, offset, limit),
+                "{request:?} from {offset}"
+            );
+        }
+    }
+}
+
+#[test]
+fn cursors_walk_a_full_scan() {
+    let lobbies = random_lobbies(400, 5);
+    for mut request in requests() {
+        let (everything, total) = full_scan(&lobbies, &request, 0, usize::MAX);
+        let mut walked = Vec::new();
+        loop {
+            let (page, page_total) = lobbies.find(&request, 0, 7).unwrap();
+            assert_eq!(page_total, total, "{request:?}");
+            let Some(last) = page.last() else { break };
+            let key = lobbies
+                .iter()
+                .find(|(_, lobby)| std::ptr::eq(*lobby, *last))
+                .map(|(key, _)| key.clone())
+                .unwrap();
+            request.cursor = Some(Cursor {
+                filter: request.filter,
+                lobby_name: last.lobby_name.clone(),
+                current_players: last.current_players,
+                key,
+            });
+            walked.extend(page);
+        }
+        assert_eq!(walked, everything, "{request:?}");
+    }
+}
+
+#[test]
+fn find_after_clearing() {
+    let mut lobbies = random_lobbies(50, 3);
+    for port in 0..50 {
+        lobbies.remove(&format!("10.0.0.1:{port}"));
+    }
+    for request in requests() {
+        assert_eq!(lobbies.find(&request, 0, 15).unwrap(), (vec![], 0));
+    }
+
+    let request = GetRequest {
+        filter: Filter::Search,
+        regions: vec![Region::Europe],
+        page_num: 0,
+        search: None,
+        page_size: None,
+        cursor: None,
+        predicates: Predicates::default(),
+    };
+    assert!(lobbies.find(&request, 0, 15).is_err());
+}
+
+/// Run with `cargo test --release -- --ignored --nocapture get_latency`.
+/// Each line is the mean time of one kind of Get. Indexed, a Get without a
+/// search, with or without predicates, takes about as long at any lobby
+/// count, while the full scan grows with it. A search still grows with the
+/// lobbies it matches, which it has to count, and a search shorter than a
+/// trigram checks every listed lobby.
+#[test]
+#[ignore = "benchmark"]
+fn get_latency() {
+    let open = Predicates {
+        hide_full: true,
+        hide_password: true,
+        ..Predicates::default()
+    };
+    let kinds: [Kind; 6] = [
+        (
+            "every region",
+            Filter::NameAscending,
+            0b111111,
+            None,
+            0,
+            Predicates::default(),
+        ),
+        (
+            "one region",
+            Filter::PlayerCountDescending,
+            0b000100,
+            None,
+            0,
+            Predicates::default(),
+        ),
+        (
+            "page 10",
+            Filter::NameDescending,
+            0b111111,
+            None,
+            10,
+            Predicates::default(),
+        ),
+        (
+            "search",
+            Filter::NameAscending,
+            0b111111,
+            Some("castle 42"),
+            0,
+            Predicates::default(),
+        ),
+        (
+            "short search",
+            Filter::NameAscending,
+            0b111111,
+            Some("a"),
+            0,
+            Predicates::default(),
+        ),
+        ("predicates", Filter::NameAscending, 0b111111, None, 0, open),
+    ];
+
+    println!(
+        "{:>8}  {:<14}{:>12}{:>12}",
+        "LOBBIES", "GET", "INDEXED", "FULL SCAN"
+    );
+    for count in [1_000, 10_000, 60_000] {
+        let lobbies = random_lobbies(count, 4);
+        for (kind, filter, mask, search, page_num, predicates) in kinds.clone() {
+            let request = GetRequest {
+                filter,
+                regions: Region::get_regions(mask),
+                page_num,
+                search: search.map(String::from),
+                page_size: None,
+                cursor: None,
+                predicates,
+            };
+            let offset = page_num as usize * 15;
+            let indexed = mean(|| drop(lobbies.find(&request, offset, 15)));
+            let scanned = mean(|| drop(full_scan(&lobbies, &request, offset, 15)));
+            println!("{count:>8}  {kind:<14}{indexed:>12.1?}{scanned:>12.1?}");
+        }
+    }
+}
+
+/// A Get's name, filter, regions, search, page and predicates.
+type Kind = (
+    &'static str,
+    Filter,
+    u8,
+    Option<&'static str>,
+    u16,
+    Predicates,
+);
+
+fn mean(mut get: impl FnMut()) -> Duration {
+    const RUNS: u32 = 50;
+    get();
+    let start = Instant::now();
+    for _ in 0..RUNS {
+        get();
+    }
+    start.elapsed() / RUNS
+}
@@ -0,0 +1,24 @@
+//! Short codes that players type in to find a lobby, the only way to find a
+//! private one since Get leaves them out.
+
+use super::DatabaseError;
+
+/// No `0`/`O` or `1`/`I`, they are too easy to mix up when read out.
+const ALPHABET: &[u8; 32] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+
+pub const JOIN_CODE_LENGTH: usize = 6;
+
+pub fn generate() -> Result<String, DatabaseError> {
+    let mut bytes = [0; JOIN_CODE_LENGTH];
+    getrandom::getrandom(&mut bytes).map_err(|_| DatabaseError::FailedToGenerateToken)?;
+    // 256 is a multiple of 32, so every character is equally likely.
+    Ok(bytes
+        .iter()
+        .map(|byte| ALPHABET[*byte as usize % ALPHABET.len()] as char)
+        .collect())
+}
+
+/// Codes are typed in by people, so any case is accepted.
+pub fn normalise(join_code: &str) -> String {
+    join_code.to_uppercase()
+}
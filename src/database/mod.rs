@@ -1,57 +1,340 @@
 #![allow(dead_code)]
 
 use crate::{
-    protocol::{Flags, IpAddress, Region},
+    config::{self, Backend, Storage},
+    limiter,
+    protocol::{Cursor, Flags, GetRequest, IpAddress, Region},
     Serialise,
 };
-use bcrypt::{hash, DEFAULT_COST};
-pub use in_memory::{create, dbg_database, delete, get, init, modify};
+use bcrypt::{hash, verify};
+pub use in_memory::InMemory;
+pub use join_code::JOIN_CODE_LENGTH;
+pub use notify::{Change, Hub, Notifying};
+pub use omicron_protocol::DatabaseError;
+pub use omicron_protocol::TOKEN_SIZE;
+#[cfg(feature = "sqlite")]
+pub use sqlite::Sqlite;
+use std::{
+    sync::Arc,
+    thread::{self, JoinHandle},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+pub use ticket::JoinTicket;
+pub use token::{HostToken, TokenHash, NO_TOKEN};
+use tracing::{error, info, warn};
 
-#[repr(u8)]
-#[derive(Debug)]
-pub enum DatabaseError {
-    NotInitialised = 50,
-    LobbyAlreadyExists = 51,
-    LobbyDoesNotExist = 52,
-    FailedToHashPassword = 53,
-    FailedToVerifyPassword = 54,
-    InvalidCredentials = 55,
-    InvalidFilter = 56,
-    BadMessage = 57,
+/// Lobbies in one page of a Get.
+pub fn page_size() -> u8 {
+    config::get().limits.page_size
+}
+
+/// Lobbies in a page of `request`: the size it asks for, within
+/// `limits.max_page_size`, or `page_size()`.
+pub fn page_limit(request: &GetRequest) -> usize {
+    let max_page_size = config::get().limits.max_page_size;
+    request
+        .page_size
+        .map_or(page_size(), |size| size.clamp(1, max_page_size)) as usize
+}
+
+/// How many matching lobbies come before the page. A page after a cursor
+/// starts at the cursor instead.
+fn page_offset(request: &GetRequest, limit: usize) -> Result<usize, DatabaseError> {
+    match request.cursor {
+        Some(_) => Ok(0),
+        None => (request.page_num as usize)
+            .checked_mul(limit)
+            .ok_or(DatabaseError::BadMessage),
+    }
 }
 
-pub const PAGE_SIZE: u8 = 15;
+#[cfg(not(test))]
+fn hash_cost() -> u32 {
+    config::get().bcrypt_cost
+}
+
+#[cfg(test)]
+fn hash_cost() -> u32 {
+    config::MIN_BCRYPT_COST // Keeps the tests fast.
+}
+
+/// Opens the configured backend. `Config::validate` has already turned away
+/// sqlite when the feature is off.
+pub fn init(
+    storage: &Storage,
+    lobbies_per_host: usize,
+) -> Result<Arc<dyn LobbyStore>, DatabaseError> {
+    match storage.backend {
+        Backend::Memory => Ok(Arc::new(
+            InMemory::new().with_lobbies_per_host(lobbies_per_host),
+        )),
+        Backend::Log => Ok(Arc::new(
+            InMemory::open(&storage.data_directory)?.with_lobbies_per_host(lobbies_per_host),
+        )),
+        #[cfg(feature = "sqlite")]
+        Backend::Sqlite => Ok(Arc::new(
+            Sqlite::open(&storage.sqlite_path)?.with_lobbies_per_host(lobbies_per_host),
+        )),
+        #[cfg(not(feature = "sqlite"))]
+        Backend::Sqlite => Err(DatabaseError::StorageFailure),
+    }
+}
+
+/// The primary key of a lobby in every backend.
+fn make_key(ip: IpAddress, port: u16) -> String {
+    format!("{ip}:{port}")
+}
+
+/// The hosts whose lobbies count towards one limit, grouped as the limiter
+/// groups clients so that a host cannot dodge it by moving around its /64.
+fn host_group(ip: IpAddress) -> IpAddress {
+    limiter::client_key(ip.into()).into()
+}
+
+/// A storage backend for lobbies, keyed by the host's ip and port.
+///
+/// Backends must report failures with the same `DatabaseError` codes, the
+/// codes are sent straight to the client.
+///
+/// Modify, delete and rotate fail with `InvalidCredentials` unless the host
+/// proves the lobby is theirs, see `HostAuth`.
+pub trait LobbyStore: Send + Sync {
+    /// Stores the lobby with a new host token and a join code no other lobby
+    /// has. Fails with `LobbyAlreadyExists` if the host already has a lobby
+    /// on that port, or `TooManyLobbies` if their ip, or their IPv6 /64, has
+    /// used up its limit.
+    fn create(&self, lobby: Lobby) -> Result<Created, DatabaseError>;
+
+    /// Replaces the host's lobby, keeping its token and join code. A host
+    /// sending its password keeps the stored hash too, the password cannot be
+    /// changed that way. Fails with `LobbyDoesNotExist` if there is none.
+    fn modify(&self, lobby: Lobby, auth: HostAuth) -> Result<(), DatabaseError>;
+
+    /// Changes the host's lobby with `change`, with no other write to it in
+    /// between, and returns it changed. `change` must keep the host ip and
+    /// port, and should be quick: the store is held while it runs.
+    fn update(
+        &self,
+        host_ip: IpAddress,
+        port: u16,
+        token: HostToken,
+        change: &dyn Fn(&mut Lobby),
+    ) -> Result<Lobby, DatabaseError>;
+
+    /// Removes the host's lobby.
+    fn delete(&self, host_ip: IpAddress, port: u16, auth: HostAuth) -> Result<(), DatabaseError>;
+
+    /// Replaces the host's token with a new one, returning it. Only the
+    /// current token will do.
+    fn rotate_token(
+        &self,
+        host_ip: IpAddress,
+        port: u16,
+        token: HostToken,
+    ) -> Result<HostToken, DatabaseError>;
+
+    /// One page of public lobbies in the requested regions, sorted by the
+    /// request's filter.
+    fn get(&self, request: GetRequest) -> Result<Page, DatabaseError>;
 
+    /// The lobby with `join_code` in any case, public or private. Fails with
+    /// `LobbyDoesNotExist` if there is none.
+    fn lookup(&self, join_code: &str) -> Result<Lobby, DatabaseError>;
+
+    /// Reserves a player slot in the host's lobby until `expires` (unix
+    /// seconds), when the player's ticket runs out, once `password` has been
+    /// checked with `check_lobby_password`. Returns the lobby, whose token
+    /// hash signs the ticket. Fails with `LobbyFull` if the players the host
+    /// reported and the reservations that have not run out take every slot.
+    fn join(
+        &self,
+        host_ip: IpAddress,
+        port: u16,
+        password: Option<String>,
+        expires: u64,
+    ) -> Result<Lobby, DatabaseError>;
+
+    /// Every stored lobby, in no particular order.
+    fn list(&self) -> Result<Vec<Lobby>, DatabaseError>;
+
+    /// Marks the host's lobby as seen now, fails with `LobbyDoesNotExist` if there is none.
+    fn heartbeat(&self, host_ip: IpAddress, port: u16) -> Result<(), DatabaseError>;
+
+    /// Removes every lobby last seen before `cutoff` (unix seconds), returning how many.
+    fn expire(&self, cutoff: u64) -> Result<usize, DatabaseError>;
+
+    /// Logs every stored lobby, leaving out the password and token hashes.
+    fn dump(&self) {
+        let lobbies = match self.list() {
+            Ok(lobbies) => lobbies,
+            Err(err) => return error!(?err, "Failed to list the lobbies"),
+        };
+        info!(lobbies = lobbies.len(), "Lobby table");
+        for lobby in lobbies {
+            info!(
+                host = %make_key(lobby.host_ip, lobby.host_port),
+                name = %lobby.lobby_name,
+                region = ?lobby.region,
+                players = lobby.current_players,
+                max_players = lobby.max_players,
+                public = lobby.flags.is_public(),
+                has_password = lobby.flags.has_password(),
+                join_code = lobby.join_code.as_deref().unwrap_or(""),
+                last_seen = lobby.last_seen,
+                "Lobby"
+            );
+        }
+    }
+}
+
+/// Seconds since the unix epoch, what `Lobby::last_seen` is measured in.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+}
+
+/// Evicts lobbies that have not been created, modified or sent a heartbeat
+/// for `ttl`, checking every `interval`.
+pub fn spawn_reaper(
+    store: Arc<dyn LobbyStore>,
+    ttl: Duration,
+    interval: Duration,
+) -> JoinHandle<()> {
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        let cutoff = now().saturating_sub(ttl.as_secs());
+        // A failed sweep is retried on the next interval.
+        match store.expire(cutoff) {
+            Ok(0) => {}
+            Ok(expired) => info!(expired, "Removed expired lobbies"),
+            Err(err) => warn!(?err, "Failed to remove expired lobbies"),
+        }
+    })
+}
+
+/// Hashes a lobby password for storing.
+pub fn hash_password(password: String) -> Result<String, DatabaseError> {
+    hash(password, hash_cost()).map_err(|_| DatabaseError::FailedToHashPassword)
+}
+
+/// Checks a password supplied by a client against a lobby's stored hash.
+pub fn check_password(password: Option<String>, hash: &str) -> Result<(), DatabaseError> {
+    if let Some(password) = password {
+        let password_check =
+            verify(password, hash).map_err(|_| DatabaseError::FailedToVerifyPassword)?;
+        if !password_check {
+            return Err(DatabaseError::InvalidCredentials);
+        }
+    }
+    Ok(())
+}
+
+/// What the host is sent back on Create.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Created {
+    pub token: HostToken,
+    pub join_code: String,
+}
+
+impl Serialise for Created {
+    fn serialise(self) -> Vec<u8> {
+        let mut output = self.token.serialise();
+        output.extend(self.join_code.serialise());
+        output
+    }
+}
+
+/// How a host proves a lobby is theirs on Modify and Destroy.
+#[derive(Debug, PartialEq)]
+pub enum HostAuth {
+    /// The token the lobby was last issued.
+    Token(HostToken),
+    /// The lobby's password, as version 0 hosts sent it before there were
+    /// tokens. Only lobbies with `password_auth` take it.
+    Password(String),
+}
+
+/// Checks a host's password against their lobby's stored hash, for lobbies
+/// that take one.
+pub fn check_host_password(lobby: &Lobby, password: String) -> Result<(), DatabaseError> {
+    if !lobby.password_auth {
+        return Err(DatabaseError::InvalidCredentials);
+    }
+    check_password(Some(password), &lobby.password)
+}
+
+/// Checks a joining player's password against the lobby's stored hash. Open
+/// lobbies let anyone in, a missing password counts as empty. Lobbies without
+/// a host token let nobody in, anyone could sign their tickets.
+pub fn check_lobby_password(lobby: &Lobby, password: Option<String>) -> Result<(), DatabaseError> {
+    if lobby.token_hash == NO_TOKEN {
+        return Err(DatabaseError::InvalidCredentials);
+    }
+    if !lobby.flags.has_password() {
+        return Ok(());
+    }
+    check_password(Some(password.unwrap_or_default()), &lobby.password)
+}
+
+#[derive(Debug)]
 pub struct Page {
-    lobbies: Vec<Lobby>,
-    page_number: u8,
-    total_pages: u8,
+    pub lobbies: Vec<Lobby>,
+    pub page_number: u16,
+    /// Every lobby matching the request, not just the ones on this page.
+    pub total_lobbies: usize,
+    /// Where this page ended, `None` if no lobby comes after it.
+    pub next: Option<Cursor>,
 }
 
 impl Page {
-    pub fn new(lobbies: Vec<Lobby>, page_number: u8, total_pages: u8) -> Self {
+    /// `found` holds the page's lobbies and, if there is a next page, the
+    /// first lobby of it, which only tells that there is one.
+    pub fn new(
+        mut found: Vec<Lobby>,
+        request: &GetRequest,
+        limit: usize,
+        total_lobbies: usize,
+    ) -> Self {
+        let more = found.len() > limit;
+        found.truncate(limit);
+        let next = match found.last() {
+            Some(last) if more => Some(Cursor {
+                filter: request.filter,
+                lobby_name: last.lobby_name.clone(),
+                current_players: last.current_players,
+                key: make_key(last.host_ip, last.host_port),
+            }),
+            _ => None,
+        };
         Page {
-            lobbies,
-            page_number,
-            total_pages,
+            lobbies: found,
+            page_number: request.page_num,
+            total_lobbies,
+            next,
         }
     }
+
+    /// Pages of the default size, counting the last one however short.
+    pub fn total_pages(&self) -> usize {
+        self.total_lobbies.div_ceil(page_size() as usize)
+    }
 }
 
 impl Serialise for Page {
     fn serialise(self) -> Vec<u8> {
-        let mut output = self
-            .lobbies
-            .iter()
-            .map(|l| l)
-            .collect::<Vec<_>>()
-            .serialise();
-        output.push(self.page_number);
-        output.push(self.total_pages);
+        let total_pages = self.total_pages().min(u8::MAX as usize) as u8;
+        let mut output = self.lobbies.iter().collect::<Vec<_>>().serialise();
+        output.push(self.page_number as u8);
+        output.push(total_pages);
         output
     }
 }
 
+/// The most bytes `Lobby::serialise_fields` adds to the name's: an IPv6
+/// address, the name's length and every other field.
+pub const ENTRY_OVERHEAD: usize = 23;
+
 #[derive(Clone, Debug)]
 pub struct Lobby {
     pub flags: Flags,
@@ -62,17 +345,22 @@ pub struct Lobby {
     pub lobby_name: String,
     pub password: String, // bcrypted!
     pub current_players: u8,
+    pub last_seen: u64, // unix seconds
+    pub token_hash: TokenHash,
+    /// Whether the host may send the password instead of the token, for
+    /// lobbies created over version 0 and ones from before tokens.
+    pub password_auth: bool,
+    /// Given out by the store on Create, `None` for lobbies from before join codes.
+    pub join_code: Option<String>,
+    /// When each player slot reserved by Join is let go, the expiry of the
+    /// player's ticket (unix seconds). Only the in-memory store keeps them
+    /// here, and does not log them.
+    pub reservations: Vec<u64>,
 }
 
 impl Serialise for &Lobby {
     fn serialise(self) -> Vec<u8> {
-        let mut output = self.flags.clone().serialise();
-        output.extend(self.region.clone().serialise());
-        output.extend(self.host_ip.serialise());
-        output.extend(self.host_port.serialise());
-        output.push(self.max_players);
-        output.extend(self.lobby_name.clone().serialise());
-        output.push(self.current_players);
+        let mut output = self.serialise_fields();
         output.insert(0, output.len() as u8);
         output
     }
@@ -100,17 +388,59 @@ impl Lobby {
         lobby_name: String,
         password: String,
     ) -> Option<Self> {
-        let password = hash(password, DEFAULT_COST).ok()?;
-        Some(Self {
+        Self {
             flags,
             region,
             host_ip,
             host_port,
             max_players,
             lobby_name,
-            password,
+            password: String::new(),
             current_players: 1,
-        })
+            last_seen: now(),
+            token_hash: NO_TOKEN,
+            password_auth: false,
+            join_code: None,
+            reservations: Vec::new(),
+        }
+        .with_password(password)
+    }
+
+    /// Replaces the stored hash with a hash of `password`.
+    pub fn with_password(mut self, password: String) -> Option<Self> {
+        self.password = hash_password(password).ok()?;
+        Some(self)
+    }
+
+    /// The lobby as sent in a Page, without the length prefix.
+    pub fn serialise_fields(&self) -> Vec<u8> {
+        let mut output = self.flags.clone().serialise();
+        output.extend(self.region.clone().serialise());
+        output.extend(self.host_ip.serialise());
+        output.extend(self.host_port.serialise());
+        output.push(self.max_players);
+        output.extend(self.lobby_name.clone().serialise());
+        output.push(self.current_players);
+        output
+    }
+
+    /// Where to find the lobby, as sent in a Lookup response.
+    pub fn serialise_host(&self) -> Vec<u8> {
+        let mut output = self.flags.clone().serialise();
+        output.extend(self.host_ip.serialise());
+        output.extend(self.host_port.serialise());
+        output
+    }
+
+    /// Whether the players the host reported and the reservations that have
+    /// not run out by `now` take every slot.
+    pub fn is_full(&self, now: u64) -> bool {
+        let reserved = self
+            .reservations
+            .iter()
+            .filter(|&&expires| expires >= now)
+            .count();
+        self.current_players as usize + reserved >= self.max_players as usize
     }
 
     pub fn set_player_count(&mut self, count: u8) {
@@ -119,3 +449,21 @@ impl Lobby {
 }
 
 mod in_memory;
+mod index;
+#[cfg(test)]
+mod index_tests;
+mod join_code;
+mod notify;
+#[cfg(test)]
+mod notify_tests;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+#[cfg(test)]
+mod store_tests;
+mod ticket;
+#[cfg(test)]
+mod ticket_tests;
+mod token;
+mod wal;
+#[cfg(test)]
+mod wal_tests;
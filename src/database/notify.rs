@@ -0,0 +1,188 @@
+//! Tells subscribers about every change to the stored lobbies, so they can
+//! keep a lobby list up to date without polling Get.
+
+use super::{Created, DatabaseError, HostAuth, HostToken, Lobby, LobbyStore, Page};
+use crate::protocol::{GetRequest, IpAddress};
+use std::{
+    collections::HashSet,
+    sync::{
+        mpsc::{self, Receiver, SyncSender, TrySendError},
+        Arc, Mutex, PoisonError,
+    },
+};
+use tracing::{debug, warn};
+
+/// Changes a subscriber may fall behind by before it is dropped.
+pub(super) const QUEUE_LENGTH: usize = 1024;
+
+#[derive(Clone, Debug)]
+pub enum Change {
+    /// The lobby was created or changed.
+    Put(Lobby),
+    /// The host's lobby was deleted or expired.
+    Remove(IpAddress, u16),
+}
+
+#[derive(Default)]
+pub struct Hub {
+    subscribers: Mutex<Vec<SyncSender<Change>>>,
+}
+
+impl Hub {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Every change from now on. The receiver is disconnected if it falls
+    /// more than `QUEUE_LENGTH` changes behind, rather than holding up the
+    /// store.
+    pub fn subscribe(&self) -> Receiver<Change> {
+        let (sender, receiver) = mpsc::sync_channel(QUEUE_LENGTH);
+        self.subscribers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(sender);
+        receiver
+    }
+
+    pub fn subscribers(&self) -> usize {
+        self.subscribers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .len()
+    }
+
+    pub(super) fn publish(&self, change: Change) {
+        self.subscribers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .retain(|subscriber| match subscriber.try_send(change.clone()) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    debug!("Dropping a subscriber that fell behind");
+                    false
+                }
+                Err(TrySendError::Disconnected(_)) => false,
+            });
+    }
+}
+
+/// Wraps a store, publishing each change it makes to `hub`.
+///
+/// Changes are published after the store has made them, so two changes to
+/// one lobby racing each other may reach subscribers in either order.
+pub struct Notifying {
+    store: Arc<dyn LobbyStore>,
+    hub: Arc<Hub>,
+}
+
+impl Notifying {
+    pub fn new(store: Arc<dyn LobbyStore>, hub: Arc<Hub>) -> Self {
+        Self { store, hub }
+    }
+}
+
+impl LobbyStore for Notifying {
+    fn create(&self, lobby: Lobby) -> Result<Created, DatabaseError> {
+        let created = self.store.create(lobby.clone())?;
+        self.hub.publish(Change::Put(lobby));
+        Ok(created)
+    }
+
+    fn modify(&self, lobby: Lobby, auth: HostAuth) -> Result<(), DatabaseError> {
+        self.store.modify(lobby.clone(), auth)?;
+        self.hub.publish(Change::Put(lobby));
+        Ok(())
+    }
+
+    fn update(
+        &self,
+        host_ip: IpAddress,
+        port: u16,
+        token: HostToken,
+        change: &dyn Fn(&mut Lobby),
+    ) -> Result<Lobby, DatabaseError> {
+        let lobby = self.store.update(host_ip, port, token, change)?;
+        self.hub.publish(Change::Put(lobby.clone()));
+        Ok(lobby)
+    }
+
+    fn delete(&self, host_ip: IpAddress, port: u16, auth: HostAuth) -> Result<(), DatabaseError> {
+        self.store.delete(host_ip, port, auth)?;
+        self.hub.publish(Change::Remove(host_ip, port));
+        Ok(())
+    }
+
+    fn rotate_token(
+        &self,
+        host_ip: IpAddress,
+        port: u16,
+        token: HostToken,
+    ) -> Result<HostToken, DatabaseError> {
+        self.store.rotate_token(host_ip, port, token)
+    }
+
+    fn get(&self, request: GetRequest) -> Result<Page, DatabaseError> {
+        self.store.get(request)
+    }
+
+    fn lookup(&self, join_code: &str) -> Result<Lobby, DatabaseError> {
+        self.store.lookup(join_code)
+    }
+
+    fn join(
+        &self,
+        host_ip: IpAddress,
+        port: u16,
+        password: Option<String>,
+        expires: u64,
+    ) -> Result<Lobby, DatabaseError> {
+        // A reservation changes nothing a subscriber sees.
+        self.store.join(host_ip, port, password, expires)
+    }
+
+    fn list(&self) -> Result<Vec<Lobby>, DatabaseError> {
+        self.store.list()
+    }
+
+    fn heartbeat(&self, host_ip: IpAddress, port: u16) -> Result<(), DatabaseError> {
+        self.store.heartbeat(host_ip, port)
+    }
+
+    fn expire(&self, cutoff: u64) -> Result<usize, DatabaseError> {
+        // Backends only count what they expire, so compare the lobbies
+        // before and after. One that got a heartbeat in between is kept.
+        let stale = match self.store.list() {
+            Ok(lobbies) => lobbies
+                .into_iter()
+                .filter(|lobby| lobby.last_seen < cutoff)
+                .map(|lobby| (lobby.host_ip, lobby.host_port))
+                .collect(),
+            Err(err) => {
+                warn!(
+                    ?err,
+                    "Failed to list the lobbies, expiring without notifying"
+                );
+                Vec::new()
+            }
+        };
+
+        let expired = self.store.expire(cutoff)?;
+        if expired == 0 || stale.is_empty() {
+            return Ok(expired);
+        }
+
+        let remaining: HashSet<_> = self
+            .store
+            .list()?
+            .into_iter()
+            .map(|lobby| (lobby.host_ip, lobby.host_port))
+            .collect();
+        for (host_ip, port) in stale {
+            if !remaining.contains(&(host_ip, port)) {
+                self.hub.publish(Change::Remove(host_ip, port));
+            }
+        }
+        Ok(expired)
+    }
+}
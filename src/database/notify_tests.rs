@@ -0,0 +1,101 @@
+use super::{notify::QUEUE_LENGTH, *};
+use crate::protocol::{Flags, Region};
+use std::sync::mpsc::TryRecvError;
+
+const HOST: IpAddress = IpAddress::IpV4([192, 168, 1, 111]);
+
+fn notifying_store() -> (Notifying, Arc<Hub>) {
+    let hub = Arc::new(Hub::new());
+    let store = Notifying::new(Arc::new(InMemory::new()), Arc::clone(&hub));
+    (store, hub)
+}
+
+fn lobby(port: u16, lobby_name: &str) -> Lobby {
+    Lobby::new(
+        Flags::new(false, true, false),
+        Region::Europe,
+        HOST,
+        port,
+        8,
+        String::from(lobby_name),
+        String::new(),
+    )
+    .unwrap()
+}
+
+fn put_name(change: Change) -> String {
+    match change {
+        Change::Put(lobby) => lobby.lobby_name,
+        Change::Remove(..) => panic!("expected a put, got {change:?}"),
+    }
+}
+
+#[test]
+fn publishes_changes() {
+    let (store, hub) = notifying_store();
+    let changes = hub.subscribe();
+
+    let created = store.create(lobby(1, "Created")).unwrap();
+    assert_eq!(put_name(changes.try_recv().unwrap()), "Created");
+
+    store
+        .modify(lobby(1, "Modified"), HostAuth::Token(created.token))
+        .unwrap();
+    assert_eq!(put_name(changes.try_recv().unwrap()), "Modified");
+
+    let rename = |lobby: &mut Lobby| lobby.lobby_name = String::from("Updated");
+    store.update(HOST, 1, created.token, &rename).unwrap();
+    assert_eq!(put_name(changes.try_recv().unwrap()), "Updated");
+
+    // Nothing a subscriber could see changes.
+    store.join(HOST, 1, None, now() + 30).unwrap();
+    store.heartbeat(HOST, 1).unwrap();
+    let token = store.rotate_token(HOST, 1, created.token).unwrap();
+    assert!(matches!(changes.try_recv(), Err(TryRecvError::Empty)));
+
+    store.delete(HOST, 1, HostAuth::Token(token)).unwrap();
+    assert!(matches!(changes.try_recv(), Ok(Change::Remove(HOST, 1))));
+}
+
+#[test]
+fn failed_changes_are_not_published() {
+    let (store, hub) = notifying_store();
+    let changes = hub.subscribe();
+
+    store.create(lobby(1, "Created")).unwrap();
+    changes.try_recv().unwrap();
+
+    assert!(store.create(lobby(1, "Duplicate")).is_err());
+    assert!(store
+        .delete(HOST, 1, HostAuth::Token(HostToken([0; TOKEN_SIZE])))
+        .is_err());
+    assert!(matches!(changes.try_recv(), Err(TryRecvError::Empty)));
+}
+
+#[test]
+fn expire_publishes_removals() {
+    let (store, hub) = notifying_store();
+    store.create(lobby(1, "Stale")).unwrap();
+    let changes = hub.subscribe();
+
+    assert_eq!(store.expire(now() + 1).unwrap(), 1);
+    assert!(matches!(changes.try_recv(), Ok(Change::Remove(HOST, 1))));
+    assert!(matches!(changes.try_recv(), Err(TryRecvError::Empty)));
+}
+
+#[test]
+fn slow_subscribers_are_dropped() {
+    let hub = Hub::new();
+    let slow = hub.subscribe();
+    let gone = hub.subscribe();
+    drop(gone);
+    assert_eq!(hub.subscribers(), 2);
+
+    for _ in 0..=QUEUE_LENGTH {
+        hub.publish(Change::Remove(HOST, 1));
+    }
+    assert_eq!(hub.subscribers(), 0);
+
+    // It still gets what was queued, then finds out it was dropped.
+    assert_eq!(slow.iter().count(), QUEUE_LENGTH);
+}
@@ -0,0 +1,723 @@
+use super::{
+    check_host_password, check_lobby_password, host_group, join_code, make_key, now, page_limit,
+    page_offset, token, Created, DatabaseError, HostAuth, HostToken, Lobby, LobbyStore, Page,
+    TokenHash, NO_TOKEN,
+};
+use crate::{
+    protocol::{Filter, Flags, GetRequest, IpAddress, Predicates, Region},
+    Serialise,
+};
+use rusqlite::{
+    params,
+    types::{ToSql, ToSqlOutput},
+    Connection, OptionalExtension,
+};
+use std::{
+    path::Path,
+    sync::{Mutex, MutexGuard, PoisonError},
+};
+use tracing::error;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS lobbies (
+    key TEXT PRIMARY KEY NOT NULL,
+    flags INTEGER NOT NULL,
+    region INTEGER NOT NULL,
+    host_ip BLOB NOT NULL,
+    host_port INTEGER NOT NULL,
+    max_players INTEGER NOT NULL,
+    lobby_name TEXT NOT NULL,
+    password TEXT NOT NULL,
+    current_players INTEGER NOT NULL,
+    folded_name TEXT NOT NULL,
+    last_seen INTEGER NOT NULL,
+    token_hash BLOB NOT NULL,
+    password_auth INTEGER NOT NULL,
+    join_code TEXT,
+    host_group BLOB NOT NULL
+);
+CREATE TABLE IF NOT EXISTS reservations (
+    key TEXT NOT NULL,
+    expires INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS reservations_key ON reservations (key);";
+
+const COLUMNS: &str = "flags, region, host_ip, host_port, max_players, lobby_name, password,
+    current_players, last_seen, token_hash, password_auth, join_code";
+
+type Row = (
+    u8,
+    u8,
+    Vec<u8>,
+    u16,
+    u8,
+    String,
+    String,
+    u8,
+    i64,
+    Vec<u8>,
+    bool,
+    Option<String>,
+);
+
+/// What a write to a host's lobby must find in its row: the hash of the
+/// token they sent, or the password hash their password was checked against.
+enum Guard {
+    Token(TokenHash),
+    Password(String),
+}
+
+impl Guard {
+    fn column(&self) -> &'static str {
+        match self {
+            Guard::Token(_) => "token_hash",
+            Guard::Password(_) => "password",
+        }
+    }
+}
+
+impl ToSql for Guard {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        match self {
+            Guard::Token(hash) => hash.to_sql(),
+            Guard::Password(hash) => hash.to_sql(),
+        }
+    }
+}
+
+fn query_failed(err: rusqlite::Error) -> DatabaseError {
+    error!(%err, "SQLite query failed");
+    DatabaseError::StorageFailure
+}
+
+/// Lobbies kept in an SQLite database so they survive a server restart.
+pub struct Sqlite {
+    connection: Mutex<Connection>,
+    /// How many lobbies one ip, or IPv6 /64, may have at once, any number if
+    /// `None`.
+    lobbies_per_host: Option<usize>,
+}
+
+impl Sqlite {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, DatabaseError> {
+        let connection = Connection::open(path).map_err(|err| {
+            error!(%err, "Failed to open the SQLite database");
+            DatabaseError::NotInitialised
+        })?;
+        Self::with_connection(connection)
+    }
+
+    pub fn open_in_memory() -> Result<Self, DatabaseError> {
+        Self::with_connection(
+            Connection::open_in_memory().map_err(|_| DatabaseError::NotInitialised)?,
+        )
+    }
+
+    fn with_connection(connection: Connection) -> Result<Self, DatabaseError> {
+        connection
+            .execute_batch(SCHEMA)
+            .map_err(|_| DatabaseError::NotInitialised)?;
+        add_last_seen(&connection).map_err(|_| DatabaseError::NotInitialised)?;
+        add_token_hash(&connection).map_err(|_| DatabaseError::NotInitialised)?;
+        add_join_code(&connection).map_err(|_| DatabaseError::NotInitialised)?;
+        add_host_group(&connection).map_err(|_| DatabaseError::NotInitialised)?;
+        Ok(Self {
+            connection: Mutex::new(connection),
+            lobbies_per_host: None,
+        })
+    }
+
+    /// Limits how many lobbies one host ip, or IPv6 /64, may have at once.
+    pub fn with_lobbies_per_host(mut self, limit: usize) -> Self {
+        self.lobbies_per_host = Some(limit);
+        self
+    }
+
+    fn hosted(&self, host_ip: IpAddress) -> Result<i64, DatabaseError> {
+        self.connection()
+            .query_row(
+                "SELECT COUNT(*) FROM lobbies WHERE host_group = ?1",
+                params![host_group(host_ip).serialise()],
+                |row| row.get(0),
+            )
+            .map_err(query_failed)
+    }
+
+    /// Checks a password sent as `auth` against the host's lobby, bcrypt is
+    /// slow on purpose so this is done without holding the connection. A
+    /// write guarded by the hash it was checked against fails if the lobby
+    /// changed in the meantime.
+    fn guard(&self, key: &str, auth: HostAuth) -> Result<Guard, DatabaseError> {
+        match auth {
+            HostAuth::Token(token) => Ok(Guard::Token(token.hash())),
+            HostAuth::Password(password) => {
+                let lobby = self.find(key)?.ok_or(DatabaseError::LobbyDoesNotExist)?;
+                check_host_password(&lobby, password)?;
+                Ok(Guard::Password(lobby.password))
+            }
+        }
+    }
+
+    fn find(&self, key: &str) -> Result<Option<Lobby>, DatabaseError> {
+        self.connection()
+            .query_row(
+                &format!("SELECT {COLUMNS} FROM lobbies WHERE key = ?1"),
+                params![key],
+                read_row,
+            )
+            .optional()
+            .map_err(query_failed)?
+            .map(into_lobby)
+            .transpose()
+    }
+
+    /// Why a guarded write touched nothing: either there is no lobby or the
+    /// host could not prove it was theirs.
+    fn unauthorised(&self, key: &str) -> Result<DatabaseError, DatabaseError> {
+        Ok(if self.exists(key)? {
+            DatabaseError::InvalidCredentials
+        } else {
+            DatabaseError::LobbyDoesNotExist
+        })
+    }
+
+    fn exists(&self, key: &str) -> Result<bool, DatabaseError> {
+        Ok(self
+            .connection()
+            .query_row("SELECT 1 FROM lobbies WHERE key = ?1", params![key], |_| {
+                Ok(())
+            })
+            .optional()
+            .map_err(query_failed)?
+            .is_some())
+    }
+
+    pub(super) fn connection(&self) -> MutexGuard<'_, Connection> {
+        self.connection
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Databases from before lobbies expired have no `last_seen` column, their
+/// lobbies count as seen when the server starts.
+fn has_column(connection: &Connection, column: &str) -> rusqlite::Result<bool> {
+    connection.query_row(
+        "SELECT COUNT(*) FROM pragma_table_info('lobbies') WHERE name = ?1",
+        params![column],
+        |row| row.get(0),
+    )
+}
+
+fn add_last_seen(connection: &Connection) -> rusqlite::Result<()> {
+    if !has_column(connection, "last_seen")? {
+        connection.execute(
+            "ALTER TABLE lobbies ADD COLUMN last_seen INTEGER NOT NULL DEFAULT 0",
+            [],
+        )?;
+        connection.execute("UPDATE lobbies SET last_seen = ?1", params![now() as i64])?;
+    }
+    Ok(())
+}
+
+/// Lobbies from before host tokens get `NO_TOKEN`, their hosts keep using
+/// the password.
+fn add_token_hash(connection: &Connection) -> rusqlite::Result<()> {
+    if !has_column(connection, "token_hash")? {
+        connection.execute(
+            "ALTER TABLE lobbies ADD COLUMN token_hash BLOB NOT NULL DEFAULT x''",
+            [],
+        )?;
+        connection.execute("UPDATE lobbies SET token_hash = ?1", params![NO_TOKEN])?;
+        connection.execute(
+            "ALTER TABLE lobbies ADD COLUMN password_auth INTEGER NOT NULL DEFAULT 1",
+            [],
+        )?;
+    }
+    Ok(())
+}
+
+/// Lobbies from before join codes are left without one.
+fn add_join_code(connection: &Connection) -> rusqlite::Result<()> {
+    if !has_column(connection, "join_code")? {
+        connection.execute("ALTER TABLE lobbies ADD COLUMN join_code TEXT", [])?;
+    }
+    connection.execute(
+        "CREATE UNIQUE INDEX IF NOT EXISTS lobbies_join_code ON lobbies (join_code)",
+        [],
+    )?;
+    Ok(())
+}
+
+/// Databases from before IPv6 hosts were limited by their /64 have no
+/// `host_group` column, it is worked out from each lobby's ip.
+fn add_host_group(connection: &Connection) -> rusqlite::Result<()> {
+    if !has_column(connection, "host_group")? {
+        connection.execute(
+            "ALTER TABLE lobbies ADD COLUMN host_group BLOB NOT NULL DEFAULT x''",
+            [],
+        )?;
+        let hosts = connection
+            .prepare("SELECT key, host_ip FROM lobbies")?
+            .query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, Vec<u8>>(1)?))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        for (key, host_ip) in hosts {
+            let Ok(host_ip) = IpAddress::from_message(&mut host_ip.iter(), host_ip.len() == 16)
+            else {
+                continue;
+            };
+            connection.execute(
+                "UPDATE lobbies SET host_group = ?2 WHERE key = ?1",
+                params![key, host_group(host_ip).serialise()],
+            )?;
+        }
+    }
+    connection.execute(
+        "CREATE INDEX IF NOT EXISTS lobbies_host_group ON lobbies (host_group)",
+        [],
+    )?;
+    Ok(())
+}
+
+fn read_row(row: &rusqlite::Row) -> rusqlite::Result<Row> {
+    Ok((
+        row.get(0)?,
+        row.get(1)?,
+        row.get(2)?,
+        row.get(3)?,
+        row.get(4)?,
+        row.get(5)?,
+        row.get(6)?,
+        row.get(7)?,
+        row.get(8)?,
+        row.get(9)?,
+        row.get(10)?,
+        row.get(11)?,
+    ))
+}
+
+fn into_lobby(row: Row) -> Result<Lobby, DatabaseError> {
+    let (
+        flags,
+        region,
+        host_ip,
+        host_port,
+        max_players,
+        lobby_name,
+        password,
+        current_players,
+        last_seen,
+        token_hash,
+        password_auth,
+        join_code,
+    ) = row;
+    let flags: Flags = flags.into();
+    let region: Region = region
+        .try_into()
+        .map_err(|_| DatabaseError::StorageFailure)?;
+    let host_ip = IpAddress::from_message(&mut host_ip.iter(), host_ip.len() == 16)
+        .map_err(|_| DatabaseError::StorageFailure)?;
+
+    Ok(Lobby {
+        flags,
+        region,
+        host_ip,
+        host_port,
+        max_players,
+        lobby_name,
+        password,
+        current_players,
+        last_seen: last_seen as u64,
+        token_hash: token_hash
+            .try_into()
+            .map_err(|_| DatabaseError::StorageFailure)?,
+        password_auth,
+        join_code,
+        reservations: Vec::new(),
+    })
+}
+
+/// Writes everything a host may change, if `guard` still holds. Returns how
+/// many rows changed.
+fn write_settings(
+    connection: &Connection,
+    key: &str,
+    guard: &Guard,
+    lobby: &Lobby,
+) -> Result<usize, DatabaseError> {
+    let column = guard.column();
+    connection
+        .execute(
+            &format!(
+                "UPDATE lobbies SET flags = ?3, region = ?4, max_players = ?5, lobby_name = ?6,
+                password = ?7, current_players = ?8, last_seen = ?9, folded_name = ?10
+            WHERE key = ?1 AND {column} = ?2"
+            ),
+            params![
+                key,
+                guard,
+                lobby.flags.clone().serialise()[0],
+                lobby.region.clone() as u8,
+                lobby.max_players,
+                lobby.lobby_name,
+                lobby.password,
+                lobby.current_players,
+                lobby.last_seen as i64,
+                lobby.lobby_name.to_lowercase(),
+            ],
+        )
+        .map_err(query_failed)
+}
+
+/// The flag bits a lobby must have set or clear to be listed, as a mask and
+/// the values under it: public, and whatever `predicates` ask of the rest.
+fn flag_condition(predicates: &Predicates) -> (u8, u8) {
+    let (mut mask, mut values) = (2, 2);
+    if predicates.hide_password {
+        mask |= 4;
+    }
+    if let Some(ipv6) = predicates.ipv6 {
+        mask |= 1;
+        values |= ipv6 as u8;
+    }
+    (mask, values)
+}
+
+fn region_mask(regions: &[Region]) -> u8 {
+    regions
+        .iter()
+        .fold(0, |mask, region| mask | region.clone() as u8)
+}
+
+impl LobbyStore for Sqlite {
+    fn create(&self, mut lobby: Lobby) -> Result<Created, DatabaseError> {
+        let key = make_key(lobby.host_ip, lobby.host_port);
+        let token = token::issue(&mut lobby)?;
+        let limit = self.lobbies_per_host.map_or(i64::MAX, |limit| limit as i64);
+
+        loop {
+            let join_code = join_code::generate()?;
+            // The limit is checked in the same statement, so two Creates
+            // cannot both squeeze under it.
+            let inserted = self
+                .connection()
+                .execute(
+                    &format!(
+                        "INSERT OR IGNORE INTO lobbies (key, {COLUMNS}, folded_name, host_group)
+                    SELECT ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?16
+                    WHERE (SELECT COUNT(*) FROM lobbies WHERE host_group = ?16) < ?15"
+                    ),
+                    params![
+                        key,
+                        lobby.flags.clone().serialise()[0],
+                        lobby.region.clone() as u8,
+                        lobby.host_ip.serialise(),
+                        lobby.host_port,
+                        lobby.max_players,
+                        lobby.lobby_name,
+                        lobby.password,
+                        lobby.current_players,
+                        lobby.last_seen as i64,
+                        lobby.token_hash,
+                        lobby.password_auth,
+                        join_code,
+                        lobby.lobby_name.to_lowercase(),
+                        limit,
+                        host_group(lobby.host_ip).serialise(),
+                    ],
+                )
+                .map_err(query_failed)?;
+
+            if inserted == 1 {
+                return Ok(Created { token, join_code });
+            }
+            if self.exists(&key)? {
+                return Err(DatabaseError::LobbyAlreadyExists);
+            }
+            if self.hosted(lobby.host_ip)? >= limit {
+                return Err(DatabaseError::TooManyLobbies);
+            }
+            // Another lobby already has the join code, try again.
+        }
+    }
+
+    fn modify(&self, mut lobby: Lobby, auth: HostAuth) -> Result<(), DatabaseError> {
+        let key = make_key(lobby.host_ip, lobby.host_port);
+        let guard = self.guard(&key, auth)?;
+        if let Guard::Password(hash) = &guard {
+            lobby.password = hash.clone();
+        }
+        let updated = write_settings(&self.connection(), &key, &guard, &lobby)?;
+
+        if updated == 0 {
+            return Err(self.unauthorised(&key)?);
+        }
+        Ok(())
+    }
+
+    fn update(
+        &self,
+        host_ip: IpAddress,
+        port: u16,
+        token: HostToken,
+        change: &dyn Fn(&mut Lobby),
+    ) -> Result<Lobby, DatabaseError> {
+        let key = make_key(host_ip, port);
+        let guard = Guard::Token(token.hash());
+
+        // Holding the connection keeps other writes out until this one is done.
+        let connection = self.connection();
+        let row = connection
+            .query_row(
+                &format!("SELECT {COLUMNS} FROM lobbies WHERE key = ?1 AND token_hash = ?2"),
+                params![key, guard],
+                read_row,
+            )
+            .optional()
+            .map_err(query_failed)?;
+        let Some(row) = row else {
+            drop(connection);
+            return Err(self.unauthorised(&key)?);
+        };
+
+        let mut lobby = into_lobby(row)?;
+        change(&mut lobby);
+        write_settings(&connection, &key, &guard, &lobby)?;
+        Ok(lobby)
+    }
+
+    fn delete(&self, host_ip: IpAddress, port: u16, auth: HostAuth) -> Result<(), DatabaseError> {
+        let key = make_key(host_ip, port);
+        let guard = self.guard(&key, auth)?;
+        let column = guard.column();
+        let deleted = self
+            .connection()
+            .execute(
+                &format!("DELETE FROM lobbies WHERE key = ?1 AND {column} = ?2"),
+                params![key, guard],
+            )
+            .map_err(query_failed)?;
+
+        if deleted == 0 {
+            return Err(self.unauthorised(&key)?);
+        }
+        self.connection()
+            .execute("DELETE FROM reservations WHERE key = ?1", params![key])
+            .map_err(query_failed)?;
+        Ok(())
+    }
+
+    fn rotate_token(
+        &self,
+        host_ip: IpAddress,
+        port: u16,
+        token: HostToken,
+    ) -> Result<HostToken, DatabaseError> {
+        let key = make_key(host_ip, port);
+        let new_token = HostToken::generate()?;
+        let updated = self
+            .connection()
+            .execute(
+                "UPDATE lobbies SET token_hash = ?3 WHERE key = ?1 AND token_hash = ?2",
+                params![key, token.hash(), new_token.hash()],
+            )
+            .map_err(query_failed)?;
+
+        if updated == 0 {
+            return Err(self.unauthorised(&key)?);
+        }
+        Ok(new_token)
+    }
+
+    fn get(&self, request: GetRequest) -> Result<Page, DatabaseError> {
+        let (order, past, same) = match request.filter {
+            Filter::NameAscending => ("folded_name ASC", "folded_name > ?10", "folded_name = ?10"),
+            Filter::NameDescending => {
+                ("folded_name DESC", "folded_name < ?10", "folded_name = ?10")
+            }
+            Filter::PlayerCountAscending => (
+                "current_players ASC",
+                "current_players > ?11",
+                "current_players = ?11",
+            ),
+            Filter::PlayerCountDescending => (
+                "current_players DESC",
+                "current_players < ?11",
+                "current_players = ?11",
+            ),
+            Filter::Search => Err(DatabaseError::InvalidFilter)?,
+        };
+
+        let limit = page_limit(&request);
+        let offset = page_offset(&request, limit)?;
+        let regions = region_mask(&request.regions);
+        // SQLite's lower() only folds ASCII, so names are stored folded the
+        // way the in-memory store folds them, and the search and cursor are
+        // folded here.
+        let search = request.search.as_ref().map(|search| search.to_lowercase());
+        let predicates = &request.predicates;
+        let (flag_mask, flag_values) = flag_condition(predicates);
+        let cursor = request.cursor.as_ref();
+        let cursor_name = cursor.map(|cursor| cursor.lobby_name.to_lowercase());
+
+        // Public, in the regions, the search and the predicates?
+        let condition = "flags & ?3 = ?4 AND region & ?1 != 0
+            AND (?2 IS NULL OR instr(folded_name, ?2) > 0)
+            AND current_players BETWEEN ?5 AND ?6
+            AND (NOT ?7 OR current_players < max_players)";
+        let filters = params![
+            regions,
+            search,
+            flag_mask,
+            flag_values,
+            predicates.players.start(),
+            predicates.players.end(),
+            predicates.hide_full,
+        ];
+        // Right after the cursor: past its value, or at it with a later key.
+        let after = format!("(?12 IS NULL OR {past} OR ({same} AND key > ?12))");
+
+        let connection = self.connection();
+        let num_lobbies: u32 = connection
+            .query_row(
+                &format!("SELECT COUNT(*) FROM lobbies WHERE {condition}"),
+                filters,
+                |row| row.get(0),
+            )
+            .map_err(query_failed)?;
+
+        let mut statement = connection
+            .prepare(&format!(
+                "SELECT {COLUMNS} FROM lobbies WHERE {condition} AND {after}
+            ORDER BY {order}, key LIMIT ?8 OFFSET ?9"
+            ))
+            .map_err(query_failed)?;
+        // One lobby more tells whether there is a next page.
+        let lobbies = statement
+            .query_map(
+                params![
+                    regions,
+                    search,
+                    flag_mask,
+                    flag_values,
+                    predicates.players.start(),
+                    predicates.players.end(),
+                    predicates.hide_full,
+                    limit as i64 + 1,
+                    offset as i64,
+                    cursor_name,
+                    cursor.map(|cursor| cursor.current_players),
+                    cursor.map(|cursor| &cursor.key),
+                ],
+                read_row,
+            )
+            .map_err(query_failed)?
+            .map(|row| into_lobby(row.map_err(query_failed)?))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Page::new(lobbies, &request, limit, num_lobbies as usize))
+    }
+
+    fn lookup(&self, join_code: &str) -> Result<Lobby, DatabaseError> {
+        let row = self
+            .connection()
+            .query_row(
+                &format!("SELECT {COLUMNS} FROM lobbies WHERE join_code = ?1"),
+                params![join_code::normalise(join_code)],
+                read_row,
+            )
+            .optional()
+            .map_err(query_failed)?
+            .ok_or(DatabaseError::LobbyDoesNotExist)?;
+        into_lobby(row)
+    }
+
+    fn join(
+        &self,
+        host_ip: IpAddress,
+        port: u16,
+        password: Option<String>,
+        expires: u64,
+    ) -> Result<Lobby, DatabaseError> {
+        let key = make_key(host_ip, port);
+
+        // bcrypt is slow on purpose, so verify without holding the connection.
+        let lobby = self.find(&key)?.ok_or(DatabaseError::LobbyDoesNotExist)?;
+        check_lobby_password(&lobby, password)?;
+
+        let connection = self.connection();
+        connection
+            .execute(
+                "DELETE FROM reservations WHERE expires < ?1",
+                params![now() as i64],
+            )
+            .map_err(query_failed)?;
+        let reserved = connection
+            .execute(
+                "INSERT INTO reservations (key, expires)
+                SELECT key, ?3 FROM lobbies
+                WHERE key = ?1 AND password = ?2 AND current_players
+                    + (SELECT COUNT(*) FROM reservations WHERE key = ?1) < max_players",
+                params![key, lobby.password, expires as i64],
+            )
+            .map_err(query_failed)?;
+        drop(connection);
+
+        match self.find(&key)? {
+            None => Err(DatabaseError::LobbyDoesNotExist),
+            Some(stored) if reserved == 1 => Ok(stored),
+            // The password was changed while we were verifying.
+            Some(stored) if stored.password != lobby.password => {
+                Err(DatabaseError::InvalidCredentials)
+            }
+            Some(_) => Err(DatabaseError::LobbyFull),
+        }
+    }
+
+    fn list(&self) -> Result<Vec<Lobby>, DatabaseError> {
+        let connection = self.connection();
+        let mut statement = connection
+            .prepare(&format!("SELECT {COLUMNS} FROM lobbies"))
+            .map_err(query_failed)?;
+        let lobbies = statement
+            .query_map([], read_row)
+            .map_err(query_failed)?
+            .map(|row| into_lobby(row.map_err(query_failed)?))
+            .collect();
+        lobbies
+    }
+
+    fn heartbeat(&self, host_ip: IpAddress, port: u16) -> Result<(), DatabaseError> {
+        let updated = self
+            .connection()
+            .execute(
+                "UPDATE lobbies SET last_seen = ?2 WHERE key = ?1",
+                params![make_key(host_ip, port), now() as i64],
+            )
+            .map_err(query_failed)?;
+
+        if updated == 0 {
+            return Err(DatabaseError::LobbyDoesNotExist);
+        }
+        Ok(())
+    }
+
+    fn expire(&self, cutoff: u64) -> Result<usize, DatabaseError> {
+        let connection = self.connection();
+        let deleted = connection
+            .execute(
+                "DELETE FROM lobbies WHERE last_seen < ?1",
+                params![cutoff as i64],
+            )
+            .map_err(query_failed)?;
+        connection
+            .execute(
+                "DELETE FROM reservations WHERE key NOT IN (SELECT key FROM lobbies)",
+                [],
+            )
+            .map_err(query_failed)?;
+        Ok(deleted)
+    }
+}
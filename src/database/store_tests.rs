@@ -0,0 +1,812 @@
+use super::*;
+use crate::protocol::{Filter, Predicates};
+use std::{thread, time::Duration};
+
+// Every backend runs the same behavioural tests, see the bottom of the file.
+macro_rules! store_tests {
+    ($backend:ident, $new_store:expr) => {
+        mod $backend {
+            use super::*;
+
+            #[test]
+            fn create() {
+                super::create(&$new_store);
+            }
+
+            #[test]
+            fn create_duplicate() {
+                super::create_duplicate(&$new_store);
+            }
+
+            #[test]
+            fn lobbies_per_host() {
+                super::lobbies_per_host(&$new_store.with_lobbies_per_host(2));
+            }
+
+            #[test]
+            fn modify() {
+                super::modify(&$new_store);
+            }
+
+            #[test]
+            fn modify_wrong_token() {
+                super::modify_wrong_token(&$new_store);
+            }
+
+            #[test]
+            fn modify_missing() {
+                super::modify_missing(&$new_store);
+            }
+
+            #[test]
+            fn update() {
+                super::update(&$new_store);
+            }
+
+            #[test]
+            fn delete() {
+                super::delete(&$new_store);
+            }
+
+            #[test]
+            fn delete_wrong_token() {
+                super::delete_wrong_token(&$new_store);
+            }
+
+            #[test]
+            fn delete_missing() {
+                super::delete_missing(&$new_store);
+            }
+
+            #[test]
+            fn rotate_token() {
+                super::rotate_token(&$new_store);
+            }
+
+            #[test]
+            fn password_auth() {
+                super::password_auth(&$new_store);
+            }
+
+            #[test]
+            fn password_auth_refused() {
+                super::password_auth_refused(&$new_store);
+            }
+
+            #[test]
+            fn get_hides_private() {
+                super::get_hides_private(&$new_store);
+            }
+
+            #[test]
+            fn lookup() {
+                super::lookup(&$new_store);
+            }
+
+            #[test]
+            fn join() {
+                super::join(&$new_store);
+            }
+
+            #[test]
+            fn join_open() {
+                super::join_open(&$new_store);
+            }
+
+            #[test]
+            fn join_reservations_run_out() {
+                super::join_reservations_run_out(&$new_store);
+            }
+
+            #[test]
+            fn get_regions_and_search() {
+                super::get_regions_and_search(&$new_store);
+            }
+
+            #[test]
+            fn get_non_ascii_names() {
+                super::get_non_ascii_names(&$new_store);
+            }
+
+            #[test]
+            fn get_sorted() {
+                super::get_sorted(&$new_store);
+            }
+
+            #[test]
+            fn get_pages() {
+                super::get_pages(&$new_store);
+            }
+
+            #[test]
+            fn get_cursors() {
+                super::get_cursors(&$new_store);
+            }
+
+            #[test]
+            fn get_predicates() {
+                super::get_predicates(&$new_store);
+            }
+
+            #[test]
+            fn get_search_filter() {
+                super::get_search_filter(&$new_store);
+            }
+
+            #[test]
+            fn heartbeat() {
+                super::heartbeat(&$new_store);
+            }
+
+            #[test]
+            fn heartbeat_missing() {
+                super::heartbeat_missing(&$new_store);
+            }
+
+            #[test]
+            fn expire() {
+                super::expire(&$new_store);
+            }
+
+            #[test]
+            fn modify_refreshes() {
+                super::modify_refreshes(&$new_store);
+            }
+        }
+    };
+}
+
+store_tests!(in_memory, InMemory::new());
+#[cfg(feature = "sqlite")]
+store_tests!(sqlite, Sqlite::open_in_memory().unwrap());
+
+const HOST: IpAddress = IpAddress::IpV4([192, 168, 1, 111]);
+const WRONG_TOKEN: HostToken = HostToken([0; TOKEN_SIZE]);
+
+fn lobby(port: u16, region: Region, lobby_name: &str, players: u8) -> Lobby {
+    let mut lobby = Lobby::new(
+        Flags::new(false, true, true),
+        region,
+        HOST,
+        port,
+        32,
+        String::from(lobby_name),
+        String::from("password123"),
+    )
+    .unwrap();
+    lobby.set_player_count(players);
+    lobby
+}
+
+fn stale_lobby(port: u16, lobby_name: &str) -> Lobby {
+    let mut lobby = lobby(port, Region::Europe, lobby_name, 1);
+    lobby.last_seen = now() - 120;
+    lobby
+}
+
+fn get_request(filter: Filter, regions: Vec<Region>, page_num: u16) -> GetRequest {
+    GetRequest {
+        filter,
+        regions,
+        page_num,
+        search: None,
+        page_size: None,
+        cursor: None,
+        predicates: Predicates::default(),
+    }
+}
+
+fn names(page: &Page) -> Vec<&str> {
+    page.lobbies
+        .iter()
+        .map(|lobby| lobby.lobby_name.as_str())
+        .collect()
+}
+
+fn create(store: &impl LobbyStore) {
+    let expected = lobby(25565, Region::Europe, "Test Lobby!", 1);
+    store.create(expected.clone()).unwrap();
+
+    let lobbies = store.list().unwrap();
+    assert_eq!(lobbies, vec![expected]);
+}
+
+fn create_duplicate(store: &impl LobbyStore) {
+    store
+        .create(lobby(25565, Region::Europe, "First", 1))
+        .unwrap();
+
+    let result = store.create(lobby(25565, Region::Asia, "Second", 1));
+    assert_eq!(result, Err(DatabaseError::LobbyAlreadyExists));
+    assert_eq!(store.list().unwrap()[0].lobby_name, "First");
+}
+
+fn lobbies_per_host(store: &impl LobbyStore) {
+    store.create(lobby(1, Region::Europe, "First", 1)).unwrap();
+    store.create(lobby(2, Region::Europe, "Second", 1)).unwrap();
+
+    let result = store.create(lobby(3, Region::Europe, "Third", 1));
+    assert_eq!(result, Err(DatabaseError::TooManyLobbies));
+
+    // Another host has their own limit.
+    let mut other = lobby(3, Region::Europe, "Other", 1);
+    other.host_ip = IpAddress::IpV4([192, 168, 1, 112]);
+    store.create(other).unwrap();
+    assert_eq!(store.list().unwrap().len(), 3);
+
+    // An IPv6 host shares one with the rest of its /64.
+    let ipv6 = |port, last| {
+        let mut lobby = lobby(port, Region::Europe, "IPv6", 1);
+        lobby.host_ip = IpAddress::IpV6([0x2001, 0xdb8, 0, 1, 0, 0, 0, last]);
+        lobby
+    };
+    let first = store.create(ipv6(1, 1)).unwrap();
+    store.create(ipv6(1, 2)).unwrap();
+    let result = store.create(ipv6(1, 3));
+    assert_eq!(result, Err(DatabaseError::TooManyLobbies));
+    let mut elsewhere = ipv6(1, 3);
+    elsewhere.host_ip = IpAddress::IpV6([0x2001, 0xdb8, 0, 2, 0, 0, 0, 3]);
+    store.create(elsewhere).unwrap();
+
+    // Deleting one gives its place back.
+    let first_ip = IpAddress::IpV6([0x2001, 0xdb8, 0, 1, 0, 0, 0, 1]);
+    store
+        .delete(first_ip, 1, HostAuth::Token(first.token))
+        .unwrap();
+    store.create(ipv6(1, 3)).unwrap();
+}
+
+fn modify(store: &impl LobbyStore) {
+    let token = store
+        .create(lobby(25565, Region::Europe, "Before", 1))
+        .unwrap()
+        .token;
+
+    let expected = lobby(25565, Region::Asia, "After", 7);
+    store
+        .modify(expected.clone(), HostAuth::Token(token))
+        .unwrap();
+
+    assert_eq!(store.list().unwrap(), vec![expected]);
+}
+
+fn modify_wrong_token(store: &impl LobbyStore) {
+    let mut open = lobby(25565, Region::Europe, "Mine", 1);
+    open.flags = Flags::new(false, true, false);
+    let token = store.create(open).unwrap().token;
+
+    let hijacked = lobby(25565, Region::Asia, "Not Yours", 32);
+    let result = store.modify(hijacked, HostAuth::Token(WRONG_TOKEN));
+    assert_eq!(result, Err(DatabaseError::InvalidCredentials));
+    assert_eq!(store.list().unwrap()[0].lobby_name, "Mine");
+
+    // The token outlives a Modify.
+    store
+        .modify(
+            lobby(25565, Region::Europe, "Renamed", 1),
+            HostAuth::Token(token),
+        )
+        .unwrap();
+    store.delete(HOST, 25565, HostAuth::Token(token)).unwrap();
+}
+
+fn modify_missing(store: &impl LobbyStore) {
+    let result = store.modify(
+        lobby(25565, Region::Europe, "Nobody", 1),
+        HostAuth::Token(WRONG_TOKEN),
+    );
+    assert_eq!(result, Err(DatabaseError::LobbyDoesNotExist));
+    assert!(store.list().unwrap().is_empty());
+}
+
+fn update(store: &impl LobbyStore) {
+    let token = store
+        .create(lobby(25565, Region::Europe, "Before", 1))
+        .unwrap()
+        .token;
+
+    let rename = |lobby: &mut Lobby| lobby.lobby_name = String::from("Not Yours");
+    let result = store.update(HOST, 25565, WRONG_TOKEN, &rename);
+    assert_eq!(result, Err(DatabaseError::InvalidCredentials));
+    let result = store.update(HOST, 25566, token, &rename);
+    assert_eq!(result, Err(DatabaseError::LobbyDoesNotExist));
+
+    let expected = lobby(25565, Region::Europe, "Before", 5);
+    let updated = store.update(HOST, 25565, token, &|lobby| lobby.set_player_count(5));
+    assert_eq!(updated, Ok(expected.clone()));
+    assert_eq!(store.list().unwrap(), vec![expected]);
+}
+
+fn delete(store: &impl LobbyStore) {
+    let token = store
+        .create(lobby(25565, Region::Europe, "Test Lobby!", 1))
+        .unwrap()
+        .token;
+
+    store.delete(HOST, 25565, HostAuth::Token(token)).unwrap();
+    assert!(store.list().unwrap().is_empty());
+}
+
+fn delete_wrong_token(store: &impl LobbyStore) {
+    store
+        .create(lobby(25565, Region::Europe, "Test Lobby!", 1))
+        .unwrap();
+
+    let result = store.delete(HOST, 25565, HostAuth::Token(WRONG_TOKEN));
+    assert_eq!(result, Err(DatabaseError::InvalidCredentials));
+    assert_eq!(store.list().unwrap().len(), 1);
+}
+
+fn delete_missing(store: &impl LobbyStore) {
+    let result = store.delete(HOST, 25565, HostAuth::Token(WRONG_TOKEN));
+    assert_eq!(result, Err(DatabaseError::LobbyDoesNotExist));
+}
+
+fn rotate_token(store: &impl LobbyStore) {
+    let old_token = store
+        .create(lobby(25565, Region::Europe, "Test Lobby!", 1))
+        .unwrap()
+        .token;
+
+    let result = store.rotate_token(HOST, 25565, WRONG_TOKEN);
+    assert_eq!(result, Err(DatabaseError::InvalidCredentials));
+
+    let new_token = store.rotate_token(HOST, 25565, old_token).unwrap();
+    assert_ne!(new_token, old_token);
+
+    let result = store.delete(HOST, 25565, HostAuth::Token(old_token));
+    assert_eq!(result, Err(DatabaseError::InvalidCredentials));
+    store
+        .delete(HOST, 25565, HostAuth::Token(new_token))
+        .unwrap();
+
+    let result = store.rotate_token(HOST, 25565, new_token);
+    assert_eq!(result, Err(DatabaseError::LobbyDoesNotExist));
+}
+
+fn password_auth(store: &impl LobbyStore) {
+    let mut legacy = lobby(25565, Region::Europe, "Old Client", 1);
+    legacy.password_auth = true;
+    let token = store.create(legacy).unwrap().token;
+
+    let wrong = HostAuth::Password(String::from("password124"));
+    let result = store.modify(lobby(25565, Region::Asia, "Not Yours", 1), wrong);
+    assert_eq!(result, Err(DatabaseError::InvalidCredentials));
+
+    // The password proves it is the host's, but does not replace the hash.
+    let mut renamed = lobby(25565, Region::Europe, "Renamed", 3);
+    renamed = renamed.with_password(String::from("changed")).unwrap();
+    let password = HostAuth::Password(String::from("password123"));
+    store.modify(renamed.clone(), password).unwrap();
+    let lobbies = store.list().unwrap();
+    assert_eq!(lobbies, vec![renamed]);
+    check_password(Some(String::from("password123")), &lobbies[0].password).unwrap();
+
+    // The token it was issued works too.
+    let token = store.rotate_token(HOST, 25565, token).unwrap();
+    let result = store.delete(HOST, 25565, HostAuth::Password(String::new()));
+    assert_eq!(result, Err(DatabaseError::InvalidCredentials));
+    store
+        .delete(HOST, 25565, HostAuth::Password(String::from("password123")))
+        .unwrap();
+    let result = store.delete(HOST, 25565, HostAuth::Token(token));
+    assert_eq!(result, Err(DatabaseError::LobbyDoesNotExist));
+}
+
+fn password_auth_refused(store: &impl LobbyStore) {
+    store
+        .create(lobby(25565, Region::Europe, "New Client", 1))
+        .unwrap();
+
+    // Knowing the password is not enough once the host has a token.
+    let password = HostAuth::Password(String::from("password123"));
+    let result = store.modify(lobby(25565, Region::Asia, "Not Yours", 1), password);
+    assert_eq!(result, Err(DatabaseError::InvalidCredentials));
+    let password = HostAuth::Password(String::from("password123"));
+    let result = store.delete(HOST, 25565, password);
+    assert_eq!(result, Err(DatabaseError::InvalidCredentials));
+    assert_eq!(store.list().unwrap()[0].lobby_name, "New Client");
+}
+
+fn private_lobby(port: u16, lobby_name: &str) -> Lobby {
+    let mut lobby = lobby(port, Region::Europe, lobby_name, 1);
+    lobby.flags = Flags::new(false, false, true);
+    lobby
+}
+
+fn get_hides_private(store: &impl LobbyStore) {
+    store.create(private_lobby(1, "Friends Only")).unwrap();
+    store
+        .create(lobby(2, Region::Europe, "Everyone", 1))
+        .unwrap();
+
+    let request = get_request(Filter::NameAscending, vec![Region::Europe], 0);
+    let page = store.get(request).unwrap();
+    assert_eq!(names(&page), ["Everyone"]);
+    assert_eq!(page.total_lobbies, 1);
+}
+
+fn lookup(store: &impl LobbyStore) {
+    let private = store.create(private_lobby(1, "Friends Only")).unwrap();
+    let public = store
+        .create(lobby(2, Region::Europe, "Everyone", 1))
+        .unwrap();
+
+    assert_eq!(private.join_code.len(), JOIN_CODE_LENGTH);
+    assert_ne!(private.join_code, public.join_code);
+    let found = store.lookup(&private.join_code.to_lowercase()).unwrap();
+    assert_eq!(found, private_lobby(1, "Friends Only"));
+
+    // The code survives a Modify.
+    let renamed = private_lobby(1, "Renamed");
+    store
+        .modify(renamed.clone(), HostAuth::Token(private.token))
+        .unwrap();
+    assert_eq!(store.lookup(&private.join_code).unwrap(), renamed);
+
+    let result = store.lookup("");
+    assert_eq!(result, Err(DatabaseError::LobbyDoesNotExist));
+    store
+        .delete(HOST, 1, HostAuth::Token(private.token))
+        .unwrap();
+    let result = store.lookup(&private.join_code);
+    assert_eq!(result, Err(DatabaseError::LobbyDoesNotExist));
+}
+
+/// A ticket good for the next half a minute.
+fn ticket_expiry() -> u64 {
+    now() + 30
+}
+
+fn join(store: &impl LobbyStore) {
+    let mut small = lobby(25565, Region::Europe, "Small", 1);
+    small.max_players = 2;
+    store.create(small).unwrap();
+
+    let result = store.join(HOST, 25565, Some(String::from("wrong")), ticket_expiry());
+    assert_eq!(result, Err(DatabaseError::InvalidCredentials));
+    let result = store.join(HOST, 25565, None, ticket_expiry());
+    assert_eq!(result, Err(DatabaseError::InvalidCredentials));
+
+    let password = || Some(String::from("password123"));
+    store
+        .join(HOST, 25565, password(), ticket_expiry())
+        .unwrap();
+    // The host still reports the players who have connected.
+    assert_eq!(store.list().unwrap()[0].current_players, 1);
+
+    let result = store.join(HOST, 25565, password(), ticket_expiry());
+    assert_eq!(result, Err(DatabaseError::LobbyFull));
+
+    let result = store.join(HOST, 1, password(), ticket_expiry());
+    assert_eq!(result, Err(DatabaseError::LobbyDoesNotExist));
+}
+
+fn join_open(store: &impl LobbyStore) {
+    let mut open = lobby(25565, Region::Europe, "Open", 1);
+    open.flags = Flags::new(false, true, false);
+    let created = store.create(open).unwrap();
+
+    let joined = store.join(HOST, 25565, None, ticket_expiry()).unwrap();
+
+    // The ticket key is the token hash, which the host can work out.
+    assert_eq!(joined.token_hash, created.token.hash());
+}
+
+fn join_reservations_run_out(store: &impl LobbyStore) {
+    let mut open = lobby(25565, Region::Europe, "Open", 1);
+    open.flags = Flags::new(false, true, false);
+    open.max_players = 3;
+    let token = store.create(open.clone()).unwrap().token;
+
+    // Tickets that ran out a second ago free their slots for the next Join.
+    store.join(HOST, 25565, None, now() - 1).unwrap();
+    store.join(HOST, 25565, None, now() - 1).unwrap();
+    store.join(HOST, 25565, None, ticket_expiry()).unwrap();
+    store.join(HOST, 25565, None, ticket_expiry()).unwrap();
+    let result = store.join(HOST, 25565, None, ticket_expiry());
+    assert_eq!(result, Err(DatabaseError::LobbyFull));
+
+    // A Modify does not let go of them, the players may not have connected yet.
+    store.modify(open, HostAuth::Token(token)).unwrap();
+    let result = store.join(HOST, 25565, None, ticket_expiry());
+    assert_eq!(result, Err(DatabaseError::LobbyFull));
+}
+
+fn get_regions_and_search(store: &impl LobbyStore) {
+    store
+        .create(lobby(1, Region::Europe, "Alpha Squad", 1))
+        .unwrap();
+    store.create(lobby(2, Region::Europe, "Bravo", 1)).unwrap();
+    store
+        .create(lobby(3, Region::Oceania, "alpha centauri", 1))
+        .unwrap();
+
+    let request = get_request(Filter::NameAscending, vec![Region::Europe], 0);
+    let page = store.get(request).unwrap();
+    assert_eq!(names(&page), ["Alpha Squad", "Bravo"]);
+
+    let mut request = get_request(Filter::NameAscending, Region::get_regions(0), 0);
+    request.search = Some(String::from("ALPHA"));
+    let page = store.get(request).unwrap();
+    assert_eq!(names(&page), ["alpha centauri", "Alpha Squad"]);
+}
+
+fn get_non_ascii_names(store: &impl LobbyStore) {
+    store.create(lobby(1, Region::Europe, "École", 1)).unwrap();
+    store.create(lobby(2, Region::Europe, "Zebra", 1)).unwrap();
+    store.create(lobby(3, Region::Europe, "éclair", 1)).unwrap();
+
+    let request = get_request(Filter::NameAscending, vec![Region::Europe], 0);
+    let page = store.get(request).unwrap();
+    assert_eq!(names(&page), ["Zebra", "éclair", "École"]);
+
+    let mut request = get_request(Filter::NameAscending, vec![Region::Europe], 0);
+    request.search = Some(String::from("ÉC"));
+    let page = store.get(request).unwrap();
+    assert_eq!(names(&page), ["éclair", "École"]);
+}
+
+fn get_sorted(store: &impl LobbyStore) {
+    store.create(lobby(1, Region::Asia, "b", 3)).unwrap();
+    store.create(lobby(2, Region::Asia, "C", 1)).unwrap();
+    store.create(lobby(3, Region::Asia, "a", 2)).unwrap();
+
+    let get = |filter| {
+        let page = store.get(get_request(filter, vec![Region::Asia], 0));
+        names(&page.unwrap())
+            .into_iter()
+            .map(String::from)
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(get(Filter::NameAscending), ["a", "b", "C"]);
+    assert_eq!(get(Filter::NameDescending), ["C", "b", "a"]);
+    assert_eq!(get(Filter::PlayerCountAscending), ["C", "a", "b"]);
+    assert_eq!(get(Filter::PlayerCountDescending), ["b", "a", "C"]);
+}
+
+fn get_pages(store: &impl LobbyStore) {
+    let lobby_count = page_size() as u16 + 5;
+    for port in 0..lobby_count {
+        let lobby_name = format!("Lobby {port:02}");
+        store
+            .create(lobby(port, Region::Africa, &lobby_name, 1))
+            .unwrap();
+    }
+
+    let first = store
+        .get(get_request(Filter::NameAscending, vec![Region::Africa], 0))
+        .unwrap();
+    assert_eq!(first.lobbies.len(), page_size() as usize);
+    assert_eq!(first.page_number, 0);
+    assert_eq!(first.lobbies[0].lobby_name, "Lobby 00");
+
+    let second = store
+        .get(get_request(Filter::NameAscending, vec![Region::Africa], 1))
+        .unwrap();
+    assert_eq!(second.lobbies.len(), 5);
+    assert_eq!(second.page_number, 1);
+    assert_eq!(
+        second.lobbies[0].lobby_name,
+        format!("Lobby {}", page_size())
+    );
+
+    let past_the_end = store
+        .get(get_request(Filter::NameAscending, vec![Region::Africa], 2))
+        .unwrap();
+    assert!(past_the_end.lobbies.is_empty());
+}
+
+fn get_cursors(store: &impl LobbyStore) {
+    let mut tokens = Vec::new();
+    for port in 0..10 {
+        let created = store
+            .create(lobby(port, Region::Africa, &format!("Lobby {port}"), 1))
+            .unwrap();
+        tokens.push(created.token);
+    }
+
+    let mut request = get_request(Filter::NameAscending, vec![Region::Africa], 0);
+    request.page_size = Some(4);
+    let first = store.get(request.clone()).unwrap();
+    assert_eq!(names(&first), ["Lobby 0", "Lobby 1", "Lobby 2", "Lobby 3"]);
+    assert_eq!(first.total_lobbies, 10);
+    assert_eq!(first.total_pages(), 10_usize.div_ceil(page_size() as usize));
+
+    // Lobbies coming and going before the cursor move no one onto the next
+    // page twice, or off it.
+    store.delete(HOST, 0, HostAuth::Token(tokens[0])).unwrap();
+    store
+        .create(lobby(10, Region::Africa, "Lobby 00", 1))
+        .unwrap();
+    request.cursor = first.next;
+    let second = store.get(request.clone()).unwrap();
+    assert_eq!(names(&second), ["Lobby 4", "Lobby 5", "Lobby 6", "Lobby 7"]);
+    assert_eq!(second.total_lobbies, 10);
+
+    // The lobby the cursor was made from is gone, which it does not need.
+    store.delete(HOST, 7, HostAuth::Token(tokens[7])).unwrap();
+    request.cursor = second.next;
+    let last = store.get(request.clone()).unwrap();
+    assert_eq!(names(&last), ["Lobby 8", "Lobby 9"]);
+    assert_eq!(last.total_lobbies, 9);
+    assert_eq!(last.next, None);
+
+    // Page sizes stay within the limits.
+    request.cursor = None;
+    request.page_size = Some(0);
+    assert_eq!(store.get(request.clone()).unwrap().lobbies.len(), 1);
+    request.page_size = Some(u8::MAX);
+    assert_eq!(store.get(request).unwrap().lobbies.len(), 9);
+}
+
+fn get_predicates(store: &impl LobbyStore) {
+    let mut full = lobby(1, Region::Asia, "Full", 4);
+    full.max_players = 4;
+    let mut open = lobby(2, Region::Asia, "Open", 2);
+    open.flags = Flags::new(false, true, false);
+    let mut ipv6 = lobby(3, Region::Asia, "IPv6", 6);
+    ipv6.flags = Flags::new(true, true, true);
+    ipv6.host_ip = IpAddress::IpV6([0xfd00, 0, 0, 0, 0, 0, 0, 1]);
+    for lobby in [full, open, ipv6, private_lobby(4, "Private")] {
+        store.create(lobby).unwrap();
+    }
+
+    let get = |predicates| {
+        let mut request = get_request(Filter::NameAscending, Region::get_regions(0), 0);
+        request.predicates = predicates;
+        let page = store.get(request).unwrap();
+        assert_eq!(page.total_lobbies, page.lobbies.len());
+        names(&page)
+            .into_iter()
+            .map(String::from)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(get(Predicates::default()), ["Full", "IPv6", "Open"]);
+    let hide = |hide_full, hide_password| Predicates {
+        hide_full,
+        hide_password,
+        ..Predicates::default()
+    };
+    assert_eq!(get(hide(true, false)), ["IPv6", "Open"]);
+    assert_eq!(get(hide(false, true)), ["Open"]);
+    let players = |players| Predicates {
+        players,
+        ..Predicates::default()
+    };
+    assert_eq!(get(players(3..=u8::MAX)), ["Full", "IPv6"]);
+    assert_eq!(get(players(2..=4)), ["Full", "Open"]);
+    // A client can send the fewest above the most, which lists nothing.
+    let crossed = Predicates::try_from([0, 5, 3]).unwrap();
+    assert_eq!(get(crossed), Vec::<String>::new());
+    let family = |ipv6| Predicates {
+        ipv6: Some(ipv6),
+        ..Predicates::default()
+    };
+    assert_eq!(get(family(true)), ["IPv6"]);
+    assert_eq!(get(family(false)), ["Full", "Open"]);
+
+    // They add up.
+    let predicates = Predicates {
+        hide_full: true,
+        ipv6: Some(false),
+        ..Predicates::default()
+    };
+    assert_eq!(get(predicates), ["Open"]);
+}
+
+fn get_search_filter(store: &impl LobbyStore) {
+    let result = store.get(get_request(Filter::Search, vec![Region::Africa], 0));
+    assert_eq!(result.unwrap_err(), DatabaseError::InvalidFilter);
+}
+
+fn heartbeat(store: &impl LobbyStore) {
+    store.create(stale_lobby(25565, "Test Lobby!")).unwrap();
+
+    store.heartbeat(HOST, 25565).unwrap();
+    assert!(store.list().unwrap()[0].last_seen >= now() - 1);
+}
+
+fn heartbeat_missing(store: &impl LobbyStore) {
+    let result = store.heartbeat(HOST, 25565);
+    assert_eq!(result, Err(DatabaseError::LobbyDoesNotExist));
+}
+
+fn expire(store: &impl LobbyStore) {
+    store.create(stale_lobby(1, "Crashed")).unwrap();
+    store.create(lobby(2, Region::Europe, "Alive", 1)).unwrap();
+    store.create(stale_lobby(3, "Heartbeat")).unwrap();
+    store.heartbeat(HOST, 3).unwrap();
+
+    let expired = store.expire(now() - 60).unwrap();
+    assert_eq!(expired, 1);
+
+    let mut lobbies = store.list().unwrap();
+    lobbies.sort_by_key(|lobby| lobby.host_port);
+    let names: Vec<_> = lobbies
+        .iter()
+        .map(|lobby| lobby.lobby_name.as_str())
+        .collect();
+    assert_eq!(names, ["Alive", "Heartbeat"]);
+}
+
+fn modify_refreshes(store: &impl LobbyStore) {
+    let token = store
+        .create(stale_lobby(25565, "Test Lobby!"))
+        .unwrap()
+        .token;
+    store
+        .modify(
+            lobby(25565, Region::Europe, "Test Lobby!", 3),
+            HostAuth::Token(token),
+        )
+        .unwrap();
+
+    assert_eq!(store.expire(now() - 60).unwrap(), 0);
+    assert_eq!(store.list().unwrap().len(), 1);
+}
+
+#[test]
+fn reaper_evicts_stale_lobbies() {
+    let store: Arc<dyn LobbyStore> = Arc::new(InMemory::new());
+    store.create(stale_lobby(1, "Crashed")).unwrap();
+    store.create(lobby(2, Region::Europe, "Alive", 1)).unwrap();
+
+    spawn_reaper(
+        Arc::clone(&store),
+        Duration::from_secs(60),
+        Duration::from_millis(10),
+    );
+
+    for _ in 0..200 {
+        if store.list().unwrap().len() == 1 {
+            break;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert_eq!(store.list().unwrap()[0].lobby_name, "Alive");
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn sqlite_survives_restart() {
+    let path = std::env::temp_dir().join(format!("omicron-lobbies-{}.sqlite3", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    let expected = lobby(25565, Region::Europe, "Test Lobby!", 4);
+    let token = {
+        let store = Sqlite::open(&path).unwrap();
+        store.create(expected.clone()).unwrap().token
+    };
+
+    let store = Sqlite::open(&path).unwrap();
+    let lobbies = store.list().unwrap();
+    assert_eq!(lobbies, vec![expected.clone()]);
+    assert_eq!(lobbies[0].password, expected.password);
+
+    // The stored token hash still checks out.
+    store.delete(HOST, 25565, HostAuth::Token(token)).unwrap();
+    drop(store);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn sqlite_lobbies_without_tokens_cannot_be_joined() {
+    let store = Sqlite::open_in_memory().unwrap();
+    let mut open = lobby(25565, Region::Europe, "Open", 1);
+    open.flags = Flags::new(false, true, false);
+    store.create(open).unwrap();
+    // As the migration leaves lobbies from before host tokens.
+    store
+        .connection()
+        .execute(
+            "UPDATE lobbies SET token_hash = ?1",
+            rusqlite::params![NO_TOKEN],
+        )
+        .unwrap();
+
+    let result = store.join(HOST, 25565, None, ticket_expiry());
+    assert_eq!(result, Err(DatabaseError::InvalidCredentials));
+}
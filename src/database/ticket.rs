@@ -0,0 +1,108 @@
+//! Join tickets, handed to a player once the lobby server has checked their
+//! password and reserved them a slot. The game host checks the ticket when
+//! the player connects instead of asking the lobby server.
+//!
+//! A ticket is `[IpV][player address][u64 expiry][HMAC-SHA256]`. The MAC is
+//! keyed with the sha256 of the host token, which the server stores and the
+//! host can work out from their token, so tickets can be checked offline.
+
+use super::{now, HostToken, TokenHash};
+use crate::{protocol::IpAddress, Serialise};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+const MAC_SIZE: usize = 32;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TicketError {
+    Malformed,
+    BadSignature,
+    Expired,
+    WrongPlayer,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct JoinTicket {
+    pub player_ip: IpAddress,
+    /// Unix seconds.
+    pub expires: u64,
+    mac: [u8; MAC_SIZE],
+}
+
+impl JoinTicket {
+    pub fn sign(key: &TokenHash, player_ip: IpAddress, expires: u64) -> Self {
+        let mac = mac(key, &signed_part(player_ip, expires))
+            .finalize()
+            .into_bytes()
+            .into();
+        Self {
+            player_ip,
+            expires,
+            mac,
+        }
+    }
+
+    /// What the host does with a ticket from a player connecting from `player_ip`.
+    pub fn verify(
+        ticket: &[u8],
+        token: &HostToken,
+        player_ip: IpAddress,
+    ) -> Result<Self, TicketError> {
+        let mac_start = ticket
+            .len()
+            .checked_sub(MAC_SIZE)
+            .ok_or(TicketError::Malformed)?;
+        let (signed, tag) = ticket.split_at(mac_start);
+
+        mac(&token.hash(), signed)
+            .verify_slice(tag)
+            .map_err(|_| TicketError::BadSignature)?;
+
+        let mut fields = signed.iter();
+        let is_ipv6 = *fields.next().ok_or(TicketError::Malformed)? == 1;
+        let ticket_ip =
+            IpAddress::from_message(&mut fields, is_ipv6).map_err(|_| TicketError::Malformed)?;
+        let expires = u64::from_be_bytes(
+            fields
+                .as_slice()
+                .try_into()
+                .map_err(|_| TicketError::Malformed)?,
+        );
+
+        if expires < now() {
+            return Err(TicketError::Expired);
+        }
+        if ticket_ip != player_ip {
+            return Err(TicketError::WrongPlayer);
+        }
+        Ok(Self {
+            player_ip,
+            expires,
+            mac: tag.try_into().map_err(|_| TicketError::Malformed)?,
+        })
+    }
+}
+
+impl Serialise for JoinTicket {
+    fn serialise(self) -> Vec<u8> {
+        let mut output = signed_part(self.player_ip, self.expires);
+        output.extend(self.mac);
+        output
+    }
+}
+
+fn signed_part(player_ip: IpAddress, expires: u64) -> Vec<u8> {
+    let mut output = match player_ip {
+        IpAddress::IpV4(_) => vec![0],
+        IpAddress::IpV6(_) => vec![1],
+    };
+    output.extend(player_ip.serialise());
+    output.extend(expires.to_be_bytes());
+    output
+}
+
+fn mac(key: &TokenHash, message: &[u8]) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any size");
+    mac.update(message);
+    mac
+}
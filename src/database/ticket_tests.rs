@@ -0,0 +1,44 @@
+use super::{ticket::TicketError, *};
+
+const PLAYER: IpAddress = IpAddress::IpV4([10, 0, 0, 7]);
+const TOKEN: HostToken = HostToken([7; TOKEN_SIZE]);
+
+fn ticket(expires: u64) -> Vec<u8> {
+    JoinTicket::sign(&TOKEN.hash(), PLAYER, expires).serialise()
+}
+
+#[test]
+fn verifies_with_host_token() {
+    let expires = now() + 30;
+    let verified = JoinTicket::verify(&ticket(expires), &TOKEN, PLAYER).unwrap();
+    assert_eq!(verified.player_ip, PLAYER);
+    assert_eq!(verified.expires, expires);
+
+    let player = IpAddress::IpV6([0xfe80, 0, 0, 0, 0, 0, 0, 1]);
+    let ticket = JoinTicket::sign(&TOKEN.hash(), player, expires).serialise();
+    assert!(JoinTicket::verify(&ticket, &TOKEN, player).is_ok());
+}
+
+#[test]
+fn rejects_tampering() {
+    let mut tampered = ticket(now() + 30);
+    tampered[1] = 8; // Someone else's address
+    let result = JoinTicket::verify(&tampered, &TOKEN, IpAddress::IpV4([8, 0, 0, 7]));
+    assert_eq!(result, Err(TicketError::BadSignature));
+
+    let other_host = HostToken([8; TOKEN_SIZE]);
+    let result = JoinTicket::verify(&ticket(now() + 30), &other_host, PLAYER);
+    assert_eq!(result, Err(TicketError::BadSignature));
+
+    let result = JoinTicket::verify(&[0; 8], &TOKEN, PLAYER);
+    assert_eq!(result, Err(TicketError::Malformed));
+}
+
+#[test]
+fn rejects_expired_and_wrong_player() {
+    let result = JoinTicket::verify(&ticket(now() - 1), &TOKEN, PLAYER);
+    assert_eq!(result, Err(TicketError::Expired));
+
+    let result = JoinTicket::verify(&ticket(now() + 30), &TOKEN, IpAddress::IpV4([10, 0, 0, 8]));
+    assert_eq!(result, Err(TicketError::WrongPlayer));
+}
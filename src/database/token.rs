@@ -0,0 +1,50 @@
+//! Host tokens, handed to the host on Create so that only they can Modify,
+//! Destroy or rotate their lobby. Only a sha256 of the token is stored, the
+//! token has 128 random bits so a fast hash is enough.
+
+use super::{DatabaseError, Lobby};
+use crate::Serialise;
+use omicron_protocol::TOKEN_SIZE;
+use sha2::{Digest, Sha256};
+
+pub type TokenHash = [u8; 32];
+
+/// Stored for lobbies that were never issued a token, no token hashes to it.
+pub const NO_TOKEN: TokenHash = [0; 32];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HostToken(pub [u8; TOKEN_SIZE]);
+
+impl HostToken {
+    pub fn generate() -> Result<Self, DatabaseError> {
+        let mut bytes = [0; TOKEN_SIZE];
+        getrandom::getrandom(&mut bytes).map_err(|_| DatabaseError::FailedToGenerateToken)?;
+        Ok(Self(bytes))
+    }
+
+    pub fn hash(&self) -> TokenHash {
+        Sha256::digest(self.0).into()
+    }
+
+    /// Compares in constant time, so a guess's timing says nothing about the hash.
+    pub fn matches(&self, hash: &TokenHash) -> bool {
+        self.hash()
+            .iter()
+            .zip(hash)
+            .fold(0, |difference, (left, right)| difference | (left ^ right))
+            == 0
+    }
+}
+
+impl Serialise for HostToken {
+    fn serialise(self) -> Vec<u8> {
+        self.0.to_vec()
+    }
+}
+
+/// Gives the lobby a new token, returning it to be sent to the host.
+pub fn issue(lobby: &mut Lobby) -> Result<HostToken, DatabaseError> {
+    let token = HostToken::generate()?;
+    lobby.token_hash = token.hash();
+    Ok(token)
+}
@@ -0,0 +1,376 @@
+//! Write-ahead log and snapshots for the in-memory store.
+//!
+//! Both files start with a 4 byte magic and a format version byte, followed
+//! by records framed as `[u32 length][u32 crc32][payload]` (big endian).
+//! A record payload is a kind byte and then:
+//!
+//! | Kind  | Record | Payload                                                                                                    |
+//! | ----- | ------ | ---------------------------------------------------------------------------------------------------------- |
+//! | `0x1` | Put    | flags, region, ip, port, max players, name, hash, players, last seen, token hash, password auth, join code |
+//! | `0x2` | Remove | lobby key                                                                                                  |
+//! | `0x3` | Touch  | lobby key, last seen                                                                                       |
+//!
+//! Version 1 files have no last seen times, their lobbies are treated as
+//! seen when they are replayed. Versions 1 and 2 have no token hashes, their
+//! lobbies get `NO_TOKEN` and their hosts keep using the password. Versions
+//! before 4 have no join codes. Older files are rewritten in the current
+//! format as soon as they have been replayed.
+//!
+//! Every change is appended to the log and synced to disk before it is
+//! applied, so a change the client was told about survives a power loss.
+//! Only the last record can be torn by a crash; a damaged record with more
+//! after it means the file itself is corrupt, and replay refuses it.
+//!
+//! Once enough records have piled up the whole table is written to a new
+//! snapshot and the log starts over. Replaying a log on top of a snapshot that already
+//! contains its records is harmless, so a crash between the two is fine.
+
+use super::{now, Lobby, NO_TOKEN};
+use crate::{protocol::IpAddress, Serialise};
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{self, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+const LOG_MAGIC: &[u8; 4] = b"OLOG";
+const SNAPSHOT_MAGIC: &[u8; 4] = b"OSNP";
+const FORMAT_VERSION: u8 = 4;
+const HEADER_SIZE: usize = 5;
+const FRAME_SIZE: usize = 8;
+
+const LOG_FILE: &str = "lobbies.log";
+const SNAPSHOT_FILE: &str = "lobbies.snapshot";
+
+const PUT: u8 = 0x1;
+const REMOVE: u8 = 0x2;
+const TOUCH: u8 = 0x3;
+
+#[derive(Debug)]
+enum Record {
+    Put(Lobby),
+    Remove(String),
+    Touch(String, u64),
+}
+
+#[derive(Debug)]
+pub struct WriteAheadLog {
+    directory: PathBuf,
+    log: File,
+    records: u32,
+    snapshot_every: u32,
+}
+
+impl WriteAheadLog {
+    /// Opens the log in `directory`, creating it if needed, along with the
+    /// lobbies replayed from the last snapshot and the log.
+    pub fn open<P: AsRef<Path>>(
+        directory: P,
+        snapshot_every: u32,
+    ) -> io::Result<(Self, HashMap<String, Lobby>)> {
+        let directory = directory.as_ref().to_path_buf();
+        fs::create_dir_all(&directory)?;
+
+        let mut lobbies = HashMap::new();
+        let mut outdated = false;
+        let snapshot_path = directory.join(SNAPSHOT_FILE);
+        if snapshot_path.exists() {
+            let snapshot = fs::read(&snapshot_path)?;
+            let (records, valid_length, version) = read_records(&snapshot, SNAPSHOT_MAGIC)?;
+            outdated |= version < FORMAT_VERSION;
+            // Snapshots are renamed into place once complete, so any damage
+            // here is not a torn write and we must not guess.
+            if valid_length != snapshot.len() {
+                return Err(invalid_data("corrupt snapshot"));
+            }
+            records
+                .into_iter()
+                .for_each(|record| apply(&mut lobbies, record));
+        }
+
+        let log_path = directory.join(LOG_FILE);
+        let contents = if log_path.exists() {
+            fs::read(&log_path)?
+        } else {
+            Vec::new()
+        };
+
+        let mut log = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&log_path)?;
+
+        let mut records = 0;
+        if contents.len() < HEADER_SIZE {
+            // Missing, or torn while writing the header.
+            write_header(&mut log, LOG_MAGIC)?;
+        } else {
+            let (replayed, valid_length, version) = read_records(&contents, LOG_MAGIC)?;
+            outdated |= version < FORMAT_VERSION;
+            records = replayed.len() as u32;
+            replayed
+                .into_iter()
+                .for_each(|record| apply(&mut lobbies, record));
+
+            // Drop a torn last record so new records follow the valid ones.
+            log.set_len(valid_length as u64)?;
+            log.seek(SeekFrom::End(0))?;
+        }
+
+        let mut wal = Self {
+            directory,
+            log,
+            records,
+            snapshot_every,
+        };
+        if outdated {
+            wal.snapshot(&lobbies)?;
+        }
+        Ok((wal, lobbies))
+    }
+
+    pub fn put(&mut self, lobby: &Lobby) -> io::Result<()> {
+        self.append(&encode_put(lobby))
+    }
+
+    pub fn remove(&mut self, key: &str) -> io::Result<()> {
+        let mut payload = vec![REMOVE];
+        payload.extend(encode_string(key));
+        self.append(&payload)
+    }
+
+    pub fn touch(&mut self, key: &str, last_seen: u64) -> io::Result<()> {
+        let mut payload = vec![TOUCH];
+        payload.extend(encode_string(key));
+        payload.extend(last_seen.to_be_bytes());
+        self.append(&payload)
+    }
+
+    fn append(&mut self, payload: &[u8]) -> io::Result<()> {
+        self.log.write_all(&frame(payload))?;
+        self.log.sync_data()?;
+        self.records += 1;
+        Ok(())
+    }
+
+    pub fn snapshot_due(&self) -> bool {
+        self.records >= self.snapshot_every
+    }
+
+    /// Writes every lobby to a new snapshot and empties the log.
+    pub fn snapshot(&mut self, lobbies: &HashMap<String, Lobby>) -> io::Result<()> {
+        let temporary_path = self.directory.join(format!("{SNAPSHOT_FILE}.tmp"));
+        let mut snapshot = File::create(&temporary_path)?;
+        write_header(&mut snapshot, SNAPSHOT_MAGIC)?;
+
+        let mut body = Vec::new();
+        lobbies
+            .values()
+            .for_each(|lobby| body.extend(frame(&encode_put(lobby))));
+        snapshot.write_all(&body)?;
+        snapshot.sync_all()?;
+        fs::rename(&temporary_path, self.directory.join(SNAPSHOT_FILE))?;
+
+        self.log.set_len(0)?;
+        self.log.seek(SeekFrom::Start(0))?;
+        write_header(&mut self.log, LOG_MAGIC)?;
+        self.records = 0;
+        Ok(())
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn write_header(file: &mut File, magic: &[u8; 4]) -> io::Result<()> {
+    file.write_all(magic)?;
+    file.write_all(&[FORMAT_VERSION])?;
+    file.sync_data()
+}
+
+fn frame(payload: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(FRAME_SIZE + payload.len());
+    output.extend((payload.len() as u32).to_be_bytes());
+    output.extend(crc32fast::hash(payload).to_be_bytes());
+    output.extend(payload);
+    output
+}
+
+fn apply(lobbies: &mut HashMap<String, Lobby>, record: Record) {
+    match record {
+        Record::Put(lobby) => {
+            lobbies.insert(super::make_key(lobby.host_ip, lobby.host_port), lobby);
+        }
+        Record::Remove(key) => {
+            lobbies.remove(&key);
+        }
+        Record::Touch(key, last_seen) => {
+            if let Some(lobby) = lobbies.get_mut(&key) {
+                lobby.last_seen = last_seen;
+            }
+        }
+    }
+}
+
+/// Reads records up to a last one that is cut short or fails its checksum,
+/// returning them with the length of the valid prefix and the file's format
+/// version. A damaged record is only taken for a torn write if it runs to
+/// the end of the file and no whole record follows it, anything else is an
+/// error.
+fn read_records(contents: &[u8], magic: &[u8; 4]) -> io::Result<(Vec<Record>, usize, u8)> {
+    if contents.len() < HEADER_SIZE || &contents[..4] != magic {
+        return Err(invalid_data("not a lobby log"));
+    }
+    let version = contents[4];
+    if !(1..=FORMAT_VERSION).contains(&version) {
+        return Err(invalid_data("unsupported lobby log version"));
+    }
+
+    let mut records = Vec::new();
+    let mut offset = HEADER_SIZE;
+    while let Some(frame) = contents.get(offset..offset + FRAME_SIZE) {
+        let length = u32::from_be_bytes([frame[0], frame[1], frame[2], frame[3]]) as usize;
+        let end = (offset + FRAME_SIZE).saturating_add(length);
+        match read_record(contents, offset, version) {
+            Some(record) => records.push(record),
+            // Torn while writing the last record. A damaged length further
+            // back can point past the end too, but leaves records after it.
+            None if end >= contents.len() && !record_after(contents, offset, version) => break,
+            None => return Err(invalid_data("corrupt record before the end of the log")),
+        }
+        offset = end;
+    }
+
+    Ok((records, offset, version))
+}
+
+/// The record framed at `offset`, if it is whole and passes its checksum.
+fn read_record(contents: &[u8], offset: usize, version: u8) -> Option<Record> {
+    let frame = contents.get(offset..offset + FRAME_SIZE)?;
+    let length = u32::from_be_bytes([frame[0], frame[1], frame[2], frame[3]]) as usize;
+    let checksum = u32::from_be_bytes([frame[4], frame[5], frame[6], frame[7]]);
+
+    let start = offset + FRAME_SIZE;
+    contents
+        .get(start..start.saturating_add(length))
+        .filter(|payload| crc32fast::hash(payload) == checksum)
+        .and_then(|payload| decode(payload, version))
+}
+
+/// Whether a whole record starts anywhere after `offset`.
+fn record_after(contents: &[u8], offset: usize, version: u8) -> bool {
+    (offset + 1..contents.len()).any(|start| read_record(contents, start, version).is_some())
+}
+
+fn encode_string(string: &str) -> Vec<u8> {
+    let mut output = (string.len() as u16).serialise();
+    output.extend(string.as_bytes());
+    output
+}
+
+fn encode_put(lobby: &Lobby) -> Vec<u8> {
+    let mut output = vec![PUT];
+    output.extend(lobby.flags.clone().serialise());
+    output.extend(lobby.region.clone().serialise());
+    match lobby.host_ip {
+        IpAddress::IpV4(_) => output.push(4),
+        IpAddress::IpV6(_) => output.push(6),
+    }
+    output.extend(lobby.host_ip.serialise());
+    output.extend(lobby.host_port.serialise());
+    output.push(lobby.max_players);
+    output.extend(encode_string(&lobby.lobby_name));
+    output.extend(encode_string(&lobby.password));
+    output.push(lobby.current_players);
+    output.extend(lobby.last_seen.to_be_bytes());
+    output.extend(lobby.token_hash);
+    output.push(lobby.password_auth as u8);
+    // Join codes are never empty, so that stands for none.
+    output.extend(encode_string(
+        lobby.join_code.as_deref().unwrap_or_default(),
+    ));
+    output
+}
+
+struct Reader<'a> {
+    bytes: std::slice::Iter<'a, u8>,
+}
+
+impl<'a> Reader<'a> {
+    fn u8(&mut self) -> Option<u8> {
+        self.bytes.next().copied()
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        Some(((self.u8()? as u16) << 8) | self.u8()? as u16)
+    }
+
+    fn array<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let rest: &'a [u8] = self.bytes.as_slice();
+        let bytes = rest.get(..N)?;
+        self.bytes = rest[N..].iter();
+        bytes.try_into().ok()
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_be_bytes(self.array()?))
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let length = self.u16()? as usize;
+        let rest: &'a [u8] = self.bytes.as_slice();
+        let bytes = rest.get(..length)?;
+        self.bytes = rest[length..].iter();
+        String::from_utf8(bytes.to_vec()).ok()
+    }
+}
+
+fn decode(payload: &[u8], version: u8) -> Option<Record> {
+    let mut reader = Reader {
+        bytes: payload.iter(),
+    };
+
+    let record = match reader.u8()? {
+        PUT => {
+            let flags = reader.u8()?.into();
+            let region = reader.u8()?.try_into().ok()?;
+            let is_ipv6 = reader.u8()? == 6;
+            let host_ip = IpAddress::from_message(&mut reader.bytes, is_ipv6).ok()?;
+            Record::Put(Lobby {
+                flags,
+                region,
+                host_ip,
+                host_port: reader.u16()?,
+                max_players: reader.u8()?,
+                lobby_name: reader.string()?,
+                password: reader.string()?,
+                current_players: reader.u8()?,
+                last_seen: if version >= 2 { reader.u64()? } else { now() },
+                token_hash: if version >= 3 {
+                    reader.array()?
+                } else {
+                    NO_TOKEN
+                },
+                password_auth: if version >= 3 {
+                    reader.u8()? != 0
+                } else {
+                    true
+                },
+                join_code: if version >= 4 {
+                    Some(reader.string()?).filter(|join_code| !join_code.is_empty())
+                } else {
+                    None
+                },
+                reservations: Vec::new(),
+            })
+        }
+        REMOVE => Record::Remove(reader.string()?),
+        TOUCH => Record::Touch(reader.string()?, reader.u64()?),
+        _ => return None,
+    };
+
+    Some(record)
+}
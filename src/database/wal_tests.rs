@@ -0,0 +1,279 @@
+use super::*;
+use std::{
+    fs,
+    path::PathBuf,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+const HOST: IpAddress = IpAddress::IpV4([192, 168, 1, 111]);
+const LOG_HEADER_SIZE: u64 = 5;
+
+/// A fresh directory under the system temp dir, removed on drop.
+struct ScratchDir(PathBuf);
+
+impl ScratchDir {
+    fn new() -> Self {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "omicron-wal-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = fs::remove_dir_all(&path);
+        Self(path)
+    }
+
+    fn log(&self) -> PathBuf {
+        self.0.join("lobbies.log")
+    }
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn lobby(port: u16, lobby_name: &str) -> Lobby {
+    Lobby::new(
+        Flags::new(false, true, true),
+        Region::Europe,
+        HOST,
+        port,
+        8,
+        String::from(lobby_name),
+        String::from("password123"),
+    )
+    .unwrap()
+}
+
+fn sorted(store: &InMemory) -> Vec<Lobby> {
+    let mut lobbies = store.list().unwrap();
+    lobbies.sort_by_key(|lobby| lobby.host_port);
+    lobbies
+}
+
+#[test]
+fn replays_after_restart() {
+    let dir = ScratchDir::new();
+    let mut modified = lobby(2, "Renamed");
+    modified.set_player_count(5);
+
+    let created = {
+        let store = InMemory::open(&dir.0).unwrap();
+        let created = store.create(lobby(1, "One")).unwrap();
+        let token_2 = store.create(lobby(2, "Two")).unwrap().token;
+        let token_3 = store.create(lobby(3, "Three")).unwrap().token;
+        store
+            .modify(modified.clone(), HostAuth::Token(token_2))
+            .unwrap();
+        store.delete(HOST, 3, HostAuth::Token(token_3)).unwrap();
+        created
+    };
+
+    let store = InMemory::open(&dir.0).unwrap();
+    let lobbies = sorted(&store);
+    assert_eq!(lobbies, vec![lobby(1, "One"), modified]);
+
+    // The bcrypt hash, join code and token hash made it through too.
+    check_password(Some(String::from("password123")), &lobbies[0].password).unwrap();
+    assert_eq!(store.lookup(&created.join_code).unwrap(), lobby(1, "One"));
+    store
+        .delete(HOST, 1, HostAuth::Token(created.token))
+        .unwrap();
+}
+
+#[test]
+fn snapshot_compacts_log() {
+    let dir = ScratchDir::new();
+
+    {
+        let store = InMemory::open_with(&dir.0, 2).unwrap();
+        for port in 0..5 {
+            store.create(lobby(port, "Lobby")).unwrap();
+        }
+    }
+
+    // Snapshots were taken after the 2nd and 4th records, only the 5th is
+    // still in the log.
+    assert!(dir.0.join("lobbies.snapshot").exists());
+    assert!(fs::metadata(dir.log()).unwrap().len() > LOG_HEADER_SIZE);
+
+    let store = InMemory::open_with(&dir.0, 2).unwrap();
+    assert_eq!(store.list().unwrap().len(), 5);
+
+    store.create(lobby(5, "Lobby")).unwrap();
+    assert_eq!(fs::metadata(dir.log()).unwrap().len(), LOG_HEADER_SIZE);
+
+    let store = InMemory::open_with(&dir.0, 2).unwrap();
+    assert_eq!(store.list().unwrap().len(), 6);
+}
+
+#[test]
+fn torn_last_record_is_skipped() {
+    let dir = ScratchDir::new();
+
+    {
+        let store = InMemory::open(&dir.0).unwrap();
+        store.create(lobby(1, "One")).unwrap();
+        store.create(lobby(2, "Two")).unwrap();
+    }
+
+    // Crash half way through writing the second record.
+    let log = fs::read(dir.log()).unwrap();
+    fs::write(dir.log(), &log[..log.len() - 3]).unwrap();
+
+    {
+        let store = InMemory::open(&dir.0).unwrap();
+        assert_eq!(sorted(&store), vec![lobby(1, "One")]);
+        store.create(lobby(3, "Three")).unwrap();
+    }
+
+    // New records go after the last good one, not after the torn bytes.
+    let store = InMemory::open(&dir.0).unwrap();
+    assert_eq!(sorted(&store), vec![lobby(1, "One"), lobby(3, "Three")]);
+}
+
+#[test]
+fn bad_checksum_is_skipped() {
+    let dir = ScratchDir::new();
+
+    {
+        let store = InMemory::open(&dir.0).unwrap();
+        store.create(lobby(1, "One")).unwrap();
+        store.create(lobby(2, "Two")).unwrap();
+    }
+
+    let mut log = fs::read(dir.log()).unwrap();
+    let last = log.len() - 1;
+    log[last] ^= 0xFF;
+    fs::write(dir.log(), log).unwrap();
+
+    let store = InMemory::open(&dir.0).unwrap();
+    assert_eq!(sorted(&store), vec![lobby(1, "One")]);
+}
+
+#[test]
+fn corrupt_record_before_the_end_is_refused() {
+    let dir = ScratchDir::new();
+
+    {
+        let store = InMemory::open(&dir.0).unwrap();
+        store.create(lobby(1, "One")).unwrap();
+        store.create(lobby(2, "Two")).unwrap();
+    }
+
+    // The first record's last payload byte, with the second still after it.
+    let mut log = fs::read(dir.log()).unwrap();
+    let length = u32::from_be_bytes(log[5..9].try_into().unwrap()) as usize;
+    log[LOG_HEADER_SIZE as usize + 8 + length - 1] ^= 0xFF;
+    fs::write(dir.log(), &log).unwrap();
+
+    let result = InMemory::open(&dir.0);
+    assert_eq!(result.unwrap_err(), DatabaseError::NotInitialised);
+    // Left as it was for someone to look at.
+    assert_eq!(fs::read(dir.log()).unwrap(), log);
+}
+
+#[test]
+fn corrupt_length_before_the_end_is_refused() {
+    let dir = ScratchDir::new();
+
+    {
+        let store = InMemory::open(&dir.0).unwrap();
+        store.create(lobby(1, "One")).unwrap();
+        store.create(lobby(2, "Two")).unwrap();
+        store.create(lobby(3, "Three")).unwrap();
+    }
+
+    // The second record's length now points past the end of the file, as a
+    // torn last record's would, but the third is still whole after it.
+    let mut log = fs::read(dir.log()).unwrap();
+    let length = u32::from_be_bytes(log[5..9].try_into().unwrap()) as usize;
+    let second = LOG_HEADER_SIZE as usize + 8 + length;
+    log[second] ^= 0x80;
+    fs::write(dir.log(), &log).unwrap();
+
+    let result = InMemory::open(&dir.0);
+    assert_eq!(result.unwrap_err(), DatabaseError::NotInitialised);
+    assert_eq!(fs::read(dir.log()).unwrap(), log);
+}
+
+#[test]
+fn heartbeat_survives_restart() {
+    let dir = ScratchDir::new();
+    let mut stale = lobby(1, "One");
+    stale.last_seen = 1000;
+
+    {
+        let store = InMemory::open(&dir.0).unwrap();
+        store.create(stale).unwrap();
+        store.heartbeat(HOST, 1).unwrap();
+    }
+
+    let store = InMemory::open(&dir.0).unwrap();
+    assert_eq!(store.expire(now() - 60).unwrap(), 0);
+}
+
+#[test]
+fn version_1_log_is_upgraded() {
+    let dir = ScratchDir::new();
+    fs::create_dir_all(&dir.0).unwrap();
+
+    // A Put record from before lobbies had a last seen time.
+    let hash = lobby(1, "One").password;
+    let mut payload = vec![
+        0x1,
+        0b110,
+        Region::Europe as u8,
+        4,
+        192,
+        168,
+        1,
+        111,
+        0,
+        1,
+        8,
+    ];
+    payload.extend((3u16).to_be_bytes());
+    payload.extend(b"One");
+    payload.extend((hash.len() as u16).to_be_bytes());
+    payload.extend(hash.as_bytes());
+    payload.push(1);
+
+    let mut log = b"OLOG\x01".to_vec();
+    log.extend((payload.len() as u32).to_be_bytes());
+    log.extend(crc32fast::hash(&payload).to_be_bytes());
+    log.extend(payload);
+    fs::write(dir.log(), log).unwrap();
+
+    let store = InMemory::open(&dir.0).unwrap();
+    assert_eq!(sorted(&store), vec![lobby(1, "One")]);
+    assert!(store.list().unwrap()[0].last_seen >= now() - 1);
+
+    // Rewritten in the current format straight away.
+    assert_eq!(fs::read(dir.log()).unwrap(), b"OLOG\x04");
+
+    let lobbies = store.list().unwrap();
+    assert_eq!(lobbies[0].token_hash, NO_TOKEN);
+    assert_eq!(lobbies[0].join_code, None);
+
+    // Anyone could sign its tickets, so nobody joins it.
+    let result = store.join(HOST, 1, Some(String::from("password123")), now() + 30);
+    assert_eq!(result, Err(DatabaseError::InvalidCredentials));
+
+    // Nobody holds a token for it, its host keeps using the password.
+    store
+        .delete(HOST, 1, HostAuth::Password(String::from("password123")))
+        .unwrap();
+}
+
+#[test]
+fn unknown_version_is_refused() {
+    let dir = ScratchDir::new();
+    fs::create_dir_all(&dir.0).unwrap();
+    fs::write(dir.log(), b"OLOG\x09").unwrap();
+
+    let result = InMemory::open(&dir.0);
+    assert_eq!(result.unwrap_err(), DatabaseError::NotInitialised);
+}
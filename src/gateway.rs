@@ -0,0 +1,481 @@
+//! An HTTP/JSON gateway onto the lobby store, for web launchers and
+//! dashboards that cannot speak the binary protocol. It makes the same checks
+//! and store calls as the binary messages, and fails with the same codes,
+//! except that a password that is too long is `InvalidPassword`.
+//!
+//! - `GET /lobbies?region=&sort=&page=&search=&page_size=&cursor=` lists one
+//!   page, like Get, leaving out what `hide_full=&hide_password=
+//!   &fewest_players=&most_players=&family=` ask to.
+//! - `POST /lobbies` creates a lobby hosted at the client's ip, like Create.
+//! - `PATCH /lobbies/{host ip}/{port}` changes some of its fields, like Modify.
+//! - `DELETE /lobbies/{host ip}/{port}` removes it, like Destroy.
+//!
+//! The host token goes in an `Authorization: Bearer {hex}` header.
+
+use crate::{
+    config,
+    database::{
+        hash_password, now, page_limit, DatabaseError, HostAuth, HostToken, Lobby, LobbyStore, Page,
+    },
+    http::{self, Request},
+    limiter::{ConnectionGuard, Limiter, Refusal},
+    protocol::{Cursor, Filter, Flags, GetRequest, IpAddress, ParseError, Predicates, Region},
+    Serialise,
+};
+use omicron_protocol::{hex, unhex};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{
+    io,
+    net::{IpAddr, SocketAddr, TcpStream},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tracing::{debug, info_span, warn};
+
+/// Lobby fields as they are sent, never with the password or token hash.
+#[derive(Debug, Serialize)]
+pub struct LobbyJson<'a> {
+    pub name: &'a str,
+    pub region: &'static str,
+    pub host_ip: IpAddr,
+    pub port: u16,
+    pub max_players: u8,
+    pub current_players: u8,
+    pub public: bool,
+    pub has_password: bool,
+}
+
+impl<'a> From<&'a Lobby> for LobbyJson<'a> {
+    fn from(lobby: &'a Lobby) -> Self {
+        Self {
+            name: &lobby.lobby_name,
+            region: lobby.region.name(),
+            host_ip: lobby.host_ip.into(),
+            port: lobby.host_port,
+            max_players: lobby.max_players,
+            current_players: lobby.current_players,
+            public: lobby.flags.is_public(),
+            has_password: lobby.flags.has_password(),
+        }
+    }
+}
+
+/// The body of `POST /lobbies`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NewLobby {
+    port: u16,
+    region: String,
+    max_players: u8,
+    name: String,
+    public: bool,
+    /// No password if left out or empty.
+    #[serde(default)]
+    password: String,
+}
+
+/// The body of `PATCH /lobbies/{host ip}/{port}`, fields left out are kept.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LobbyChanges {
+    region: Option<String>,
+    max_players: Option<u8>,
+    current_players: Option<u8>,
+    name: Option<String>,
+    public: Option<bool>,
+    /// An empty password removes it.
+    password: Option<String>,
+}
+
+/// A failed request, sent as `{"code": 52, "error": "LobbyDoesNotExist"}`.
+#[derive(Debug)]
+enum Failure {
+    /// HTTP level errors, which have no response code.
+    NotFound,
+    MethodNotAllowed,
+    Parse(ParseError),
+    Database(DatabaseError),
+    Refused(Refusal),
+}
+
+impl From<ParseError> for Failure {
+    fn from(err: ParseError) -> Self {
+        Self::Parse(err)
+    }
+}
+
+impl From<DatabaseError> for Failure {
+    fn from(err: DatabaseError) -> Self {
+        Self::Database(err)
+    }
+}
+
+impl From<Refusal> for Failure {
+    fn from(refusal: Refusal) -> Self {
+        Self::Refused(refusal)
+    }
+}
+
+impl Failure {
+    /// The binary protocol's response code.
+    fn code(&self) -> Option<u8> {
+        match self {
+            Self::NotFound | Self::MethodNotAllowed => None,
+            Self::Parse(err) => Some(*err as u8),
+            Self::Database(err) => Some(*err as u8),
+            Self::Refused(refusal) => Some(*refusal as u8),
+        }
+    }
+
+    fn json(&self) -> serde_json::Value {
+        let error = match self {
+            Self::NotFound => String::from("NotFound"),
+            Self::MethodNotAllowed => String::from("MethodNotAllowed"),
+            Self::Parse(err) => format!("{err:?}"),
+            Self::Database(err) => format!("{err:?}"),
+            Self::Refused(refusal) => format!("{refusal:?}"),
+        };
+        json!({ "code": self.code(), "error": error })
+    }
+
+    fn status(&self) -> &'static str {
+        use DatabaseError::*;
+        match self {
+            Self::MethodNotAllowed => "405 Method Not Allowed",
+            Self::NotFound | Self::Database(LobbyDoesNotExist) => "404 Not Found",
+            Self::Database(LobbyAlreadyExists | LobbyFull) => "409 Conflict",
+            Self::Database(InvalidCredentials) => "403 Forbidden",
+            Self::Database(TooManyLobbies) | Self::Refused(Refusal::RateLimited) => {
+                "429 Too Many Requests"
+            }
+            Self::Refused(Refusal::TooManyConnections) => "503 Service Unavailable",
+            Self::Database(
+                NotInitialised
+                | FailedToHashPassword
+                | FailedToVerifyPassword
+                | StorageFailure
+                | FailedToGenerateToken,
+            ) => "500 Internal Server Error",
+            Self::Database(InvalidFilter | BadMessage) | Self::Parse(_) => "400 Bad Request",
+        }
+    }
+}
+
+type Reply = Result<(&'static str, serde_json::Value), Failure>;
+
+/// Turns a connection away without reading it, like `crate::refuse_connection`.
+pub fn refuse_connection(stream: &mut TcpStream) {
+    let failure = Failure::from(Refusal::TooManyConnections);
+    if let Err(err) = write_json(stream, failure.status(), &failure.json()) {
+        debug!(%err, "Failed to refuse an HTTP connection");
+    }
+    crate::metrics::METRICS.record_request(None, failure.code(), Duration::ZERO);
+}
+
+/// Answers one request on a gateway connection.
+pub fn handle_connection(
+    mut stream: TcpStream,
+    database: Arc<dyn LobbyStore>,
+    limiter: Arc<Limiter>,
+    _guard: ConnectionGuard,
+) {
+    let client_address = match stream.peer_addr() {
+        Ok(address) => address,
+        Err(err) => return warn!(%err, "Failed to get client address"),
+    };
+    let span = info_span!("http", client = %client_address);
+    let _entered = span.enter();
+    let start = Instant::now();
+
+    let deadline = start + Duration::from_secs(config::get().timeouts.receive);
+    let request = match http::read_request(&mut stream, deadline) {
+        Ok(request) => request,
+        Err(err) => {
+            debug!(%err, "Failed to read an HTTP request");
+            return crate::log_request(None, None, None, start.elapsed());
+        }
+    };
+
+    let message_type = route_type(&request);
+    let reply = match message_type {
+        Some(message_type) => limiter
+            .check(client_address.ip(), message_type)
+            .map_err(Failure::from)
+            .and_then(|()| answer(&request, client_address, database.as_ref())),
+        None if request.path == "/lobbies" || request.path.starts_with("/lobbies/") => {
+            Err(Failure::MethodNotAllowed)
+        }
+        None => Err(Failure::NotFound),
+    };
+
+    let (status, body, code) = match reply {
+        Ok((status, body)) => (status, body, Some(10)),
+        Err(failure) => {
+            debug!(?failure, "Request failed");
+            (failure.status(), failure.json(), failure.code())
+        }
+    };
+    if let Err(err) = write_json(&mut stream, status, &body) {
+        debug!(%err, "Failed to write an HTTP response");
+    }
+    crate::log_request(None, message_type, code, start.elapsed());
+}
+
+fn write_json(stream: &mut TcpStream, status: &str, body: &serde_json::Value) -> io::Result<()> {
+    http::write_response(
+        stream,
+        status,
+        "application/json",
+        body.to_string().as_bytes(),
+    )
+}
+
+/// The binary message type a request stands in for, `None` if it is not
+/// one the gateway answers.
+fn route_type(request: &Request) -> Option<&'static str> {
+    let lobby = request.path.strip_prefix("/lobbies/").is_some();
+    match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/lobbies") => Some("get"),
+        ("POST", "/lobbies") => Some("create"),
+        ("PATCH", _) if lobby => Some("modify"),
+        ("DELETE", _) if lobby => Some("destroy"),
+        _ => None,
+    }
+}
+
+fn answer(request: &Request, client_address: SocketAddr, database: &dyn LobbyStore) -> Reply {
+    match route_type(request) {
+        Some("get") => list(request, database),
+        Some("create") => create(request, client_address, database),
+        Some("modify") => modify(request, client_address, database),
+        Some("destroy") => destroy(request, client_address, database),
+        _ => Err(Failure::NotFound),
+    }
+}
+
+/// `hide_full` and `hide_password` (`true` or `false`), `fewest_players`,
+/// `most_players` and `family` (`ipv4` or `ipv6`).
+fn predicates(request: &Request) -> Result<Predicates, Failure> {
+    let flag = |name| match request.query(name) {
+        Some(value) => value.parse().map_err(|_| DatabaseError::BadMessage),
+        None => Ok(false),
+    };
+    let players = |name, default| match request.query(name) {
+        Some(count) => count.parse().map_err(|_| DatabaseError::BadMessage),
+        None => Ok(default),
+    };
+    let ipv6 = match request.query("family") {
+        Some("ipv4") => Some(false),
+        Some("ipv6") => Some(true),
+        Some(_) => return Err(ParseError::InvalidFilter.into()),
+        None => None,
+    };
+    Ok(Predicates {
+        hide_full: flag("hide_full")?,
+        hide_password: flag("hide_password")?,
+        players: players("fewest_players", 0)?..=players("most_players", u8::MAX)?,
+        ipv6,
+    })
+}
+
+fn list(request: &Request, database: &dyn LobbyStore) -> Reply {
+    let regions = match request.query("region") {
+        Some(names) => names
+            .split(',')
+            .map(region)
+            .collect::<Result<Vec<_>, _>>()?,
+        None => Region::get_regions(0),
+    };
+    let filter = match request.query("sort").unwrap_or("name_asc") {
+        "name_asc" => Filter::NameAscending,
+        "name_desc" => Filter::NameDescending,
+        "players_asc" => Filter::PlayerCountAscending,
+        "players_desc" => Filter::PlayerCountDescending,
+        _ => return Err(ParseError::InvalidFilter.into()),
+    };
+    let page_num = match request.query("page") {
+        Some(page) => page.parse().map_err(|_| DatabaseError::BadMessage)?,
+        None => 0,
+    };
+    let page_size = match request.query("page_size") {
+        Some(size) => Some(size.parse().map_err(|_| DatabaseError::BadMessage)?),
+        None => None,
+    };
+    // The cursor a previous page returned as `next`, in the order it was made for.
+    let cursor = match request.query("cursor") {
+        Some(cursor) => match unhex(cursor).map(|bytes| Cursor::try_from(&bytes[..])) {
+            Some(Ok(cursor)) if cursor.filter == filter => Some(cursor),
+            _ => return Err(ParseError::InvalidCursor.into()),
+        },
+        None => None,
+    };
+    let search = request.query("search").map(str::to_string);
+    if let Some(search) = &search {
+        check_name(search)?;
+    }
+
+    let get_request = GetRequest {
+        filter,
+        regions,
+        page_num,
+        search,
+        page_size,
+        cursor,
+        predicates: predicates(request)?,
+    };
+    let page_size = page_limit(&get_request);
+    let page: Page = database.get(get_request)?;
+    let lobbies: Vec<_> = page.lobbies.iter().map(LobbyJson::from).collect();
+    Ok((
+        "200 OK",
+        json!({
+            "lobbies": lobbies,
+            "page": page.page_number,
+            "page_size": page_size,
+            "total_lobbies": page.total_lobbies,
+            "next": page.next.as_ref().map(|cursor| hex(&cursor.serialise())),
+        }),
+    ))
+}
+
+fn create(request: &Request, client_address: SocketAddr, database: &dyn LobbyStore) -> Reply {
+    let new: NewLobby = body(request)?;
+    check_name(&new.name)?;
+    check_password(&new.password)?;
+
+    let host_ip = IpAddress::from(client_address);
+    let flags = Flags::new(
+        matches!(host_ip, IpAddress::IpV6(_)),
+        new.public,
+        !new.password.is_empty(),
+    );
+    let lobby = Lobby::new(
+        flags,
+        region(&new.region)?,
+        host_ip,
+        new.port,
+        new.max_players,
+        new.name,
+        new.password,
+    )
+    .ok_or(DatabaseError::FailedToHashPassword)?;
+
+    let created = database.create(lobby)?;
+    Ok((
+        "201 Created",
+        json!({
+            "token": hex(&created.token.0),
+            "join_code": created.join_code,
+        }),
+    ))
+}
+
+fn modify(request: &Request, client_address: SocketAddr, database: &dyn LobbyStore) -> Reply {
+    let (host_ip, port) = host(request, client_address)?;
+    let token = token(request)?;
+    let changes: LobbyChanges = body(request)?;
+
+    if let Some(name) = &changes.name {
+        check_name(name)?;
+    }
+    let region = changes.region.as_deref().map(region).transpose()?;
+    // bcrypt is slow on purpose, so hash before the store is held.
+    let password = match changes.password {
+        Some(password) => {
+            check_password(&password)?;
+            Some((!password.is_empty(), hash_password(password)?))
+        }
+        None => None,
+    };
+
+    database.update(host_ip, port, token, &|lobby| {
+        if let Some(name) = &changes.name {
+            lobby.lobby_name = name.clone();
+        }
+        if let Some(region) = &region {
+            lobby.region = region.clone();
+        }
+        if let Some(max_players) = changes.max_players {
+            lobby.max_players = max_players;
+        }
+        if let Some(current_players) = changes.current_players {
+            lobby.set_player_count(current_players);
+        }
+        let public = changes.public.unwrap_or(lobby.flags.is_public());
+        let mut has_password = lobby.flags.has_password();
+        if let Some((set, hash)) = &password {
+            has_password = *set;
+            lobby.password = hash.clone();
+        }
+        lobby.flags = Flags::new(matches!(host_ip, IpAddress::IpV6(_)), public, has_password);
+        lobby.last_seen = now();
+    })?;
+    Ok(("200 OK", json!({})))
+}
+
+fn destroy(request: &Request, client_address: SocketAddr, database: &dyn LobbyStore) -> Reply {
+    let (host_ip, port) = host(request, client_address)?;
+    database.delete(host_ip, port, HostAuth::Token(token(request)?))?;
+    Ok(("200 OK", json!({})))
+}
+
+/// The lobby named by the path, which only its host may change.
+fn host(request: &Request, client_address: SocketAddr) -> Result<(IpAddress, u16), Failure> {
+    let host = request
+        .path
+        .strip_prefix("/lobbies/")
+        .and_then(|host| host.rsplit_once('/'))
+        .and_then(|(ip, port)| {
+            let ip: IpAddr = http::percent_decode(ip).parse().ok()?;
+            Some(SocketAddr::new(ip, port.parse().ok()?))
+        })
+        .ok_or(Failure::NotFound)?;
+
+    let host_ip = IpAddress::from(host);
+    if host_ip != IpAddress::from(client_address) {
+        return Err(ParseError::MismatchedIP.into());
+    }
+    Ok((host_ip, host.port()))
+}
+
+fn token(request: &Request) -> Result<HostToken, Failure> {
+    request
+        .header("authorization")
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .and_then(|value| unhex(value.trim()))
+        .and_then(|bytes| bytes.try_into().ok())
+        .map(HostToken)
+        .ok_or_else(|| DatabaseError::InvalidCredentials.into())
+}
+
+fn body<'a, T: Deserialize<'a>>(request: &'a Request) -> Result<T, Failure> {
+    serde_json::from_slice(&request.body).map_err(|err| {
+        debug!(%err, "Bad JSON body");
+        DatabaseError::BadMessage.into()
+    })
+}
+
+fn region(name: &str) -> Result<Region, Failure> {
+    Ok(name.parse::<Region>()?)
+}
+
+/// Names fit the binary protocol too: the limit counts characters like
+/// version 1, and is capped so that any name within it fits a version 0 entry.
+fn check_name(name: &str) -> Result<(), Failure> {
+    let max_length = config::get().limits.max_name_length;
+    if name.chars().count() > max_length {
+        return Err(ParseError::InvalidName.into());
+    }
+    Ok(())
+}
+
+/// Passwords too long for the binary protocol are `InvalidPassword`, which
+/// it sends as `InvalidName` instead.
+fn check_password(password: &str) -> Result<(), Failure> {
+    let max_length = config::get().limits.max_password_length;
+    if password.chars().count() > max_length {
+        return Err(ParseError::InvalidPassword.into());
+    }
+    Ok(())
+}
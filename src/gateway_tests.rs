@@ -0,0 +1,256 @@
+use crate::{
+    database::{Hub, InMemory, LobbyStore},
+    http::percent_decode,
+    limiter::Limiter,
+    serve,
+    server_tests::unlimited,
+    udp::Udp,
+    Listeners,
+};
+use serde_json::{json, Value};
+use std::{
+    io::{Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::Arc,
+    thread,
+};
+
+fn start_gateway() -> (SocketAddr, Arc<dyn LobbyStore>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let database: Arc<dyn LobbyStore> = Arc::new(InMemory::new());
+
+    let listeners = Listeners {
+        tcp: Vec::new(),
+        udp: Udp::bind(&[]).unwrap(),
+        http: Some(listener),
+        websocket: None,
+    };
+    let server_database = Arc::clone(&database);
+    let limiter = Arc::new(Limiter::new(unlimited()));
+    thread::spawn(move || serve(listeners, server_database, Arc::new(Hub::new()), limiter, 2));
+
+    (address, database)
+}
+
+/// Sends one request, returning the status code and the JSON body.
+fn send(
+    address: SocketAddr,
+    method: &str,
+    path: &str,
+    token: Option<&str>,
+    body: Option<Value>,
+) -> (u16, Value) {
+    let mut stream = TcpStream::connect(address).unwrap();
+    let body = body.map(|body| body.to_string()).unwrap_or_default();
+    let authorization = token
+        .map(|token| format!("Authorization: Bearer {token}\r\n"))
+        .unwrap_or_default();
+    write!(
+        stream,
+        "{method} {path} HTTP/1.1\r\nHost: localhost\r\n{authorization}Content-Length: {}\r\n\r\n{body}",
+        body.len()
+    )
+    .unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.contains("Content-Type: application/json\r\n"));
+    let status = response[9..12].parse().unwrap();
+    let (_, body) = response.split_once("\r\n\r\n").unwrap();
+    (status, serde_json::from_str(body).unwrap())
+}
+
+fn new_lobby(port: u16, name: &str, region: &str) -> Value {
+    json!({
+        "port": port,
+        "region": region,
+        "max_players": 8,
+        "name": name,
+        "public": true,
+        "password": "hunter2",
+    })
+}
+
+fn names(body: &Value) -> Vec<&str> {
+    body["lobbies"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|lobby| lobby["name"].as_str().unwrap())
+        .collect()
+}
+
+#[test]
+fn lobby_lifecycle() {
+    let (address, database) = start_gateway();
+
+    let (status, created) = send(
+        address,
+        "POST",
+        "/lobbies",
+        None,
+        Some(new_lobby(7777, "Web lobby", "europe")),
+    );
+    assert_eq!(status, 201);
+    let token = created["token"].as_str().unwrap().to_string();
+    assert_eq!(token.len(), 32);
+    assert!(created["join_code"].is_string());
+
+    let (status, page) = send(address, "GET", "/lobbies", None, None);
+    assert_eq!(status, 200);
+    assert_eq!(page["total_lobbies"], 1);
+    let lobby = &page["lobbies"][0];
+    assert_eq!(
+        *lobby,
+        json!({
+            "name": "Web lobby",
+            "region": "europe",
+            "host_ip": "127.0.0.1",
+            "port": 7777,
+            "max_players": 8,
+            "current_players": 1,
+            "public": true,
+            "has_password": true,
+        })
+    );
+
+    let changes = json!({ "name": "Renamed", "current_players": 3 });
+    let path = "/lobbies/127.0.0.1/7777";
+    let (status, body) = send(address, "PATCH", path, None, Some(changes.clone()));
+    assert_eq!(
+        (status, body),
+        (403, json!({ "code": 55, "error": "InvalidCredentials" }))
+    );
+    let (status, _) = send(address, "PATCH", path, Some(&token), Some(changes));
+    assert_eq!(status, 200);
+    let stored = &database.list().unwrap()[0];
+    assert_eq!(stored.lobby_name, "Renamed");
+    assert_eq!(stored.current_players, 3);
+    assert!(stored.flags.has_password());
+
+    let changes = json!({ "password": "" });
+    let (status, _) = send(address, "PATCH", path, Some(&token), Some(changes));
+    assert_eq!(status, 200);
+    let stored = &database.list().unwrap()[0];
+    assert_eq!(stored.lobby_name, "Renamed");
+    assert!(!stored.flags.has_password());
+
+    let (status, _) = send(address, "DELETE", path, Some(&token), None);
+    assert_eq!(status, 200);
+    assert!(database.list().unwrap().is_empty());
+    let (status, body) = send(address, "DELETE", path, Some(&token), None);
+    assert_eq!((status, body["code"].clone()), (404, json!(52)));
+}
+
+#[test]
+fn list_queries() {
+    let (address, _) = start_gateway();
+    for (port, name, region) in [
+        (1, "Alpha lobby", "europe"),
+        (2, "Beta lobby", "asia"),
+        (3, "Gamma", "europe"),
+    ] {
+        let (status, _) = send(
+            address,
+            "POST",
+            "/lobbies",
+            None,
+            Some(new_lobby(port, name, region)),
+        );
+        assert_eq!(status, 201);
+    }
+
+    let (_, body) = send(address, "GET", "/lobbies?sort=name_desc", None, None);
+    assert_eq!(names(&body), ["Gamma", "Beta lobby", "Alpha lobby"]);
+    let (_, body) = send(address, "GET", "/lobbies?region=europe", None, None);
+    assert_eq!(names(&body), ["Alpha lobby", "Gamma"]);
+    let (_, body) = send(
+        address,
+        "GET",
+        "/lobbies?region=asia,europe&search=a+LOBBY",
+        None,
+        None,
+    );
+    assert_eq!(names(&body), ["Alpha lobby", "Beta lobby"]);
+    let (_, body) = send(address, "GET", "/lobbies?page=1", None, None);
+    assert_eq!(names(&body), Vec::<&str>::new());
+    let (_, body) = send(
+        address,
+        "GET",
+        "/lobbies?hide_full=true&family=ipv4&most_players=1",
+        None,
+        None,
+    );
+    assert_eq!(names(&body), ["Alpha lobby", "Beta lobby", "Gamma"]);
+    let (_, body) = send(address, "GET", "/lobbies?hide_password=true", None, None);
+    assert_eq!(
+        (names(&body), body["total_lobbies"].clone()),
+        (vec![], json!(0))
+    );
+    let (_, body) = send(address, "GET", "/lobbies?fewest_players=2", None, None);
+    assert_eq!(names(&body), Vec::<&str>::new());
+
+    let (_, body) = send(address, "GET", "/lobbies?page_size=2", None, None);
+    assert_eq!(names(&body), ["Alpha lobby", "Beta lobby"]);
+    assert_eq!(
+        (body["page_size"].clone(), body["total_lobbies"].clone()),
+        (json!(2), json!(3))
+    );
+    let next = body["next"].as_str().unwrap();
+    let path = format!("/lobbies?page_size=2&cursor={next}");
+    let (_, body) = send(address, "GET", &path, None, None);
+    assert_eq!(names(&body), ["Gamma"]);
+    assert_eq!(body["next"], Value::Null);
+}
+
+#[test]
+fn errors_use_protocol_codes() {
+    let (address, database) = start_gateway();
+    let error = |method, path, body| send(address, method, path, None, body);
+
+    assert_eq!(
+        error("GET", "/lobbies?region=mars", None),
+        (400, json!({ "code": 43, "error": "InvalidRegion" }))
+    );
+    assert_eq!(error("GET", "/lobbies?sort=random", None).1["code"], 47);
+    assert_eq!(error("GET", "/lobbies?page=-1", None).1["code"], 57);
+    assert_eq!(error("GET", "/lobbies?cursor=00ff", None).1["code"], 48);
+    assert_eq!(error("GET", "/lobbies?family=ipx", None).1["code"], 47);
+    assert_eq!(error("GET", "/lobbies?hide_full=yes", None).1["code"], 57);
+    assert_eq!(
+        error("POST", "/lobbies", Some(json!({ "port": 1 }))).1["code"],
+        57
+    );
+
+    let long_name = new_lobby(1, &"x".repeat(33), "europe");
+    assert_eq!(error("POST", "/lobbies", Some(long_name)).1["code"], 44);
+    // The longest name of the widest characters still fits a version 0 entry.
+    let wide_name = new_lobby(1, &"\u{1F3AE}".repeat(32), "europe");
+    assert_eq!(error("POST", "/lobbies", Some(wide_name)).0, 201);
+    assert!(database.list().unwrap()[0].serialise_fields().len() <= u8::MAX as usize);
+    let mut long_password = new_lobby(1, "Lobby", "europe");
+    long_password["password"] = json!("x".repeat(33));
+    assert_eq!(
+        error("POST", "/lobbies", Some(long_password)),
+        (400, json!({ "code": 49, "error": "InvalidPassword" }))
+    );
+
+    // Only the host may change their lobby.
+    let (status, body) = error("DELETE", "/lobbies/10.0.0.1/1", None);
+    assert_eq!((status, body["code"].clone()), (400, json!(45)));
+
+    assert_eq!(
+        error("GET", "/players", None),
+        (404, json!({ "code": null, "error": "NotFound" }))
+    );
+    assert_eq!(error("PUT", "/lobbies", None).0, 405);
+}
+
+#[test]
+fn decodes_query_strings() {
+    assert_eq!(percent_decode("a+b%20c"), "a b c");
+    assert_eq!(percent_decode("%E2%9C%93"), "✓");
+    assert_eq!(percent_decode("100%"), "100%");
+    assert_eq!(percent_decode("%zz"), "%zz");
+}
@@ -0,0 +1,148 @@
+//! Keeps one client from taking the server down: token bucket rate limits
+//! per client ip and message type, and a cap on connections from everyone.
+
+use crate::config::{Rate, RateLimits};
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, PoisonError,
+    },
+    time::{Duration, Instant},
+};
+
+/// Sweeps out idle buckets once there are this many.
+const SWEEP_AT: usize = 10_000;
+/// How long to wait between sweeps, they look at every bucket.
+const SWEEP_EVERY: Duration = Duration::from_secs(1);
+/// The most buckets to keep, new clients are rate limited once there are this
+/// many that are not idle.
+const MAX_BUCKETS: usize = 1_000_000;
+
+/// Response codes for requests the limiter turned away.
+#[repr(u8)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Refusal {
+    /// The client sent too many messages of this type, it should wait.
+    RateLimited = 61,
+    /// The server is handling as many connections as it allows.
+    TooManyConnections = 62,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn full(rate: Rate, now: Instant) -> Self {
+        Self {
+            tokens: rate.burst as f64,
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, rate: Rate, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate.per_second).min(rate.burst as f64);
+        self.updated = now;
+    }
+}
+
+#[derive(Debug, Default)]
+struct Buckets {
+    buckets: HashMap<(IpAddr, &'static str), Bucket>,
+    swept: Option<Instant>,
+}
+
+pub struct Limiter {
+    rates: RateLimits,
+    buckets: Mutex<Buckets>,
+    max_buckets: usize,
+    connections: AtomicUsize,
+}
+
+impl Limiter {
+    pub fn new(rates: RateLimits) -> Self {
+        Self::with_max_buckets(rates, MAX_BUCKETS)
+    }
+
+    pub(crate) fn with_max_buckets(rates: RateLimits, max_buckets: usize) -> Self {
+        Self {
+            rates,
+            buckets: Mutex::new(Buckets::default()),
+            max_buckets,
+            connections: AtomicUsize::new(0),
+        }
+    }
+
+    /// Takes one request of `message_type` from `client`'s bucket.
+    pub fn check(&self, client: IpAddr, message_type: &'static str) -> Result<(), Refusal> {
+        let rate = self.rates.rate(message_type);
+        let now = Instant::now();
+        let mut guard = self.buckets.lock().unwrap_or_else(PoisonError::into_inner);
+        let Buckets { buckets, swept } = &mut *guard;
+
+        let sweep_due = swept.is_none_or(|swept| now.duration_since(swept) >= SWEEP_EVERY);
+        if buckets.len() >= SWEEP_AT.min(self.max_buckets) && sweep_due {
+            // A full bucket is the same as no bucket, so dropping them is free.
+            buckets.retain(|(_, message_type), bucket| {
+                let rate = self.rates.rate(message_type);
+                bucket.refill(rate, now);
+                bucket.tokens < rate.burst as f64
+            });
+            *swept = Some(now);
+        }
+
+        let key = (client_key(client), message_type);
+        if buckets.len() >= self.max_buckets && !buckets.contains_key(&key) {
+            return Err(Refusal::RateLimited);
+        }
+        let bucket = buckets
+            .entry(key)
+            .or_insert_with(|| Bucket::full(rate, now));
+        bucket.refill(rate, now);
+
+        if bucket.tokens < 1.0 {
+            return Err(Refusal::RateLimited);
+        }
+        bucket.tokens -= 1.0;
+        Ok(())
+    }
+
+    /// Counts a new connection until the guard is dropped, `None` if there are
+    /// already `max_connections`.
+    pub fn connect(self: &Arc<Self>) -> Option<ConnectionGuard> {
+        self.connections
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |connections| {
+                (connections < self.rates.max_connections).then_some(connections + 1)
+            })
+            .ok()?;
+        Some(ConnectionGuard(Arc::clone(self)))
+    }
+}
+
+pub struct ConnectionGuard(Arc<Limiter>);
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.0.connections.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// IPv6 clients usually get a whole /64, so they share one bucket, and the
+/// stores count their lobbies together.
+pub fn client_key(client: IpAddr) -> IpAddr {
+    match client {
+        IpAddr::V4(_) => client,
+        // IPv4 clients of a dual stack listener.
+        IpAddr::V6(ip) if ip.to_ipv4_mapped().is_some() => client.to_canonical(),
+        IpAddr::V6(ip) => {
+            let mut segments = ip.segments();
+            segments[4..].fill(0);
+            IpAddr::from(segments)
+        }
+    }
+}
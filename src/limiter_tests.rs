@@ -0,0 +1,162 @@
+use super::{
+    config::{Rate, RateLimits},
+    limiter::*,
+};
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+
+const CLIENT: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 111));
+
+fn limiter(per_second: f64, burst: u32) -> Limiter {
+    Limiter::new(RateLimits {
+        default: Rate { per_second, burst },
+        ..RateLimits::default()
+    })
+}
+
+#[test]
+fn allows_a_burst() {
+    let limiter = limiter(0.001, 3);
+    for _ in 0..3 {
+        limiter.check(CLIENT, "get").unwrap();
+    }
+    assert_eq!(limiter.check(CLIENT, "get"), Err(Refusal::RateLimited));
+}
+
+#[test]
+fn refills() {
+    let limiter = limiter(50.0, 1);
+    limiter.check(CLIENT, "get").unwrap();
+    assert_eq!(limiter.check(CLIENT, "get"), Err(Refusal::RateLimited));
+
+    thread::sleep(Duration::from_millis(40));
+    limiter.check(CLIENT, "get").unwrap();
+}
+
+#[test]
+fn separate_buckets() {
+    let limiter = limiter(0.001, 1);
+    limiter.check(CLIENT, "get").unwrap();
+    limiter.check(CLIENT, "heartbeat").unwrap();
+    limiter
+        .check(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 112)), "get")
+        .unwrap();
+    assert_eq!(limiter.check(CLIENT, "get"), Err(Refusal::RateLimited));
+}
+
+#[test]
+fn ipv6_shares_a_prefix() {
+    let limiter = limiter(0.001, 1);
+    limiter
+        .check(
+            IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 1, 0, 0, 0, 1)),
+            "get",
+        )
+        .unwrap();
+    assert_eq!(
+        limiter.check(
+            IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 1, 0, 0, 0, 2)),
+            "get"
+        ),
+        Err(Refusal::RateLimited)
+    );
+    limiter
+        .check(
+            IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 2, 0, 0, 0, 1)),
+            "get",
+        )
+        .unwrap();
+}
+
+#[test]
+fn ipv4_mapped_is_ipv4() {
+    let limiter = limiter(0.001, 1);
+    limiter.check(CLIENT, "get").unwrap();
+
+    let IpAddr::V4(client) = CLIENT else {
+        unreachable!()
+    };
+    let mapped = IpAddr::V6(client.to_ipv6_mapped());
+    assert_eq!(limiter.check(mapped, "get"), Err(Refusal::RateLimited));
+}
+
+#[test]
+fn buckets_are_capped() {
+    let limiter = Limiter::with_max_buckets(
+        RateLimits {
+            default: Rate {
+                per_second: 0.001,
+                burst: 2,
+            },
+            ..RateLimits::default()
+        },
+        2,
+    );
+    let other = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 112));
+    limiter.check(CLIENT, "get").unwrap();
+    limiter.check(other, "get").unwrap();
+
+    // Neither bucket is idle, so a new one does not fit.
+    let new = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 113));
+    assert_eq!(limiter.check(new, "get"), Err(Refusal::RateLimited));
+    limiter.check(CLIENT, "get").unwrap();
+}
+
+#[test]
+fn sweeps_once_per_interval() {
+    let limiter = Limiter::with_max_buckets(
+        RateLimits {
+            default: Rate {
+                per_second: 50.0,
+                burst: 1,
+            },
+            ..RateLimits::default()
+        },
+        2,
+    );
+    limiter.check(CLIENT, "get").unwrap();
+    limiter
+        .check(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 112)), "get")
+        .unwrap();
+    let new = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 113));
+    assert_eq!(limiter.check(new, "get"), Err(Refusal::RateLimited));
+
+    // Both buckets are idle again, but they were just swept.
+    thread::sleep(Duration::from_millis(40));
+    assert_eq!(limiter.check(new, "get"), Err(Refusal::RateLimited));
+}
+
+#[test]
+fn bcrypt_types_are_slower() {
+    let rate_limits = RateLimits::default();
+    assert!(rate_limits.rate("create").per_second < rate_limits.default.per_second);
+    assert_eq!(rate_limits.rate("get"), rate_limits.default);
+
+    let mut configured = rate_limits.clone();
+    let rate = Rate {
+        per_second: 100.0,
+        burst: 100,
+    };
+    configured.types.insert(String::from("create"), rate);
+    assert_eq!(configured.rate("create"), rate);
+}
+
+#[test]
+fn connection_cap() {
+    let limiter = Arc::new(Limiter::new(RateLimits {
+        max_connections: 2,
+        ..RateLimits::default()
+    }));
+
+    let first = limiter.connect().unwrap();
+    let _second = limiter.connect().unwrap();
+    assert!(limiter.connect().is_none());
+
+    drop(first);
+    let _third = limiter.connect().unwrap();
+    assert!(limiter.connect().is_none());
+}
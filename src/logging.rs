@@ -0,0 +1,43 @@
+//! Where log events go, and the debug commands an operator can type into the
+//! server's stdin when `logging.debug_commands` is on.
+
+use crate::{
+    config::{LogFormat, Logging},
+    database::LobbyStore,
+};
+use std::{
+    io::{self, BufRead, IsTerminal},
+    sync::Arc,
+    thread::{self, JoinHandle},
+};
+use tracing::{info, warn};
+use tracing_subscriber::EnvFilter;
+
+/// Sends log events to stderr. `Config::validate` has already checked the
+/// filter.
+pub fn init(logging: &Logging) {
+    let subscriber = tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::new(&logging.filter))
+        .with_writer(io::stderr)
+        .with_ansi(io::stderr().is_terminal());
+
+    match logging.format {
+        LogFormat::Pretty => subscriber.init(),
+        LogFormat::Json => subscriber.json().with_span_list(false).init(),
+    }
+}
+
+/// Runs commands read from stdin, one per line, until stdin closes.
+pub fn spawn_debug_commands(store: Arc<dyn LobbyStore>) -> JoinHandle<()> {
+    thread::spawn(move || {
+        info!("Debug commands enabled, type `dump` to log the lobby table");
+        for line in io::stdin().lock().lines() {
+            let Ok(line) = line else { break };
+            match line.trim() {
+                "" => {}
+                "dump" => store.dump(),
+                command => warn!(command, "Unknown debug command, the only one is `dump`"),
+            }
+        }
+    })
+}
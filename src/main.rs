@@ -1,126 +1,535 @@
+use config::{Args, Config};
+use database::{DatabaseError, Hub, JoinTicket, LobbyStore, Notifying};
+use limiter::{ConnectionGuard, Limiter, Refusal};
+use omicron_protocol::Serialise;
+use protocol::{Filter, GetRequest, ParseError};
 use std::{
-    io::{BufReader, Read, Write},
-    net::{SocketAddr, TcpListener, TcpStream},
+    env,
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream, UdpSocket},
+    process::ExitCode,
+    sync::Arc,
     thread,
     time::{Duration, Instant},
 };
+use thread_pool::ThreadPool;
+use tracing::{debug, error, info, info_span, trace, warn};
+use udp::Udp;
 
+mod config;
+#[cfg(test)]
+mod config_tests;
 mod database;
+mod gateway;
+#[cfg(test)]
+mod gateway_tests;
+mod http;
+mod limiter;
+#[cfg(test)]
+mod limiter_tests;
+mod logging;
+mod metrics;
+#[cfg(test)]
+mod metrics_tests;
 mod protocol;
+#[cfg(test)]
+mod server_tests;
+mod session;
+mod subscription;
+mod thread_pool;
+mod udp;
+#[cfg(test)]
+mod udp_tests;
+mod websocket;
+#[cfg(test)]
+mod websocket_tests;
 
-macro_rules! etprintln {
-    () => {
-        etprintln!(as "[%Y/%m/%d-%H:%M:%S]")
+fn main() -> ExitCode {
+    let args = match Args::parse(env::args().skip(1)) {
+        Ok(args) if args.help => {
+            print!("{}", config::USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("{err}");
+            return ExitCode::from(2);
+        }
     };
-    (as $f:expr) => {
-        eprintln!("{}", chrono::Local::now().format($f))
+    let config = match Config::load(&args) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("Invalid configuration: {err}");
+            return ExitCode::FAILURE;
+        }
     };
-    (as $f:expr; $($arg:tt)*) => {
-        {
-            eprint!("{} ", chrono::Local::now().format($f));
-            eprintln!($( $arg )*)
+    config::install(config);
+    let config = config::get();
+    logging::init(&config.logging);
+
+    let database = match database::init(&config.storage, config.rate_limits.max_lobbies_per_ip) {
+        Ok(database) => database,
+        Err(err) => {
+            error!(?err, backend = ?config.storage.backend, "Failed to open the database");
+            return ExitCode::FAILURE;
         }
     };
-    ($($arg:tt)*) => {
-        etprintln!(as "[%Y/%m/%d-%H:%M:%S]"; $( $arg )*)
+    let hub = Arc::new(Hub::new());
+    let database: Arc<dyn LobbyStore> = Arc::new(Notifying::new(database, Arc::clone(&hub)));
+
+    let mut tcp = Vec::new();
+    for address in &config.bind {
+        match TcpListener::bind(address) {
+            Ok(listener) => {
+                let address = listener.local_addr().unwrap_or(*address);
+                info!(%address, "Listening");
+                tcp.push(listener);
+            }
+            Err(err) => {
+                error!(%err, %address, "Failed to bind the tcp server");
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    let udp = match Udp::bind(&config.udp.bind) {
+        Ok(udp) => udp,
+        Err(err) => {
+            error!(%err, addresses = ?config.udp.bind, "Failed to bind the udp server");
+            return ExitCode::FAILURE;
+        }
     };
-}
+    for socket in udp.sockets() {
+        if let Ok(address) = socket.local_addr() {
+            info!(%address, "Listening for datagrams");
+        }
+    }
 
-pub trait Serialise {
-    fn serialise(self) -> Vec<u8>;
-}
+    let http = match config.http.bind.map(TcpListener::bind) {
+        None => None,
+        Some(Ok(listener)) => {
+            if let Ok(address) = listener.local_addr() {
+                info!(%address, "Serving the HTTP gateway");
+            }
+            Some(listener)
+        }
+        Some(Err(err)) => {
+            error!(%err, address = ?config.http.bind, "Failed to bind the HTTP gateway");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let websocket = match config.websocket.bind.map(TcpListener::bind) {
+        None => None,
+        Some(Ok(listener)) => {
+            if let Ok(address) = listener.local_addr() {
+                info!(%address, "Listening for WebSockets");
+            }
+            Some(listener)
+        }
+        Some(Err(err)) => {
+            error!(%err, address = ?config.websocket.bind, "Failed to bind the WebSocket server");
+            return ExitCode::FAILURE;
+        }
+    };
 
-impl Serialise for String {
-    fn serialise(self) -> Vec<u8> {
-        let mut output = Vec::new();
-        output.push(self.len() as u8);
-        output.extend(self.bytes().collect::<Vec<u8>>());
-        output
+    if let Some(address) = config.metrics.bind {
+        match TcpListener::bind(address) {
+            Ok(listener) => {
+                let address = listener.local_addr().unwrap_or(address);
+                info!(%address, "Serving metrics");
+                metrics::spawn_server(listener, Arc::clone(&database));
+            }
+            Err(err) => {
+                error!(%err, %address, "Failed to bind the metrics server");
+                return ExitCode::FAILURE;
+            }
+        }
     }
-}
 
-impl Serialise for u16 {
-    fn serialise(self) -> Vec<u8> {
-        vec![(self >> 8) as u8, (self & 0xFF) as u8]
+    if config.logging.debug_commands {
+        logging::spawn_debug_commands(Arc::clone(&database));
     }
+    database::spawn_reaper(
+        Arc::clone(&database),
+        Duration::from_secs(config.timeouts.lobby),
+        Duration::from_secs(config.timeouts.reap_interval),
+    );
+    let limiter = Arc::new(Limiter::new(config.rate_limits.clone()));
+    let listeners = Listeners {
+        tcp,
+        udp,
+        http,
+        websocket,
+    };
+    serve(listeners, database, hub, limiter, config.worker_threads);
+    ExitCode::SUCCESS
 }
 
-impl<T: Serialise + Copy> Serialise for Vec<T> {
-    fn serialise(self) -> Vec<u8> {
-        let mut temp = Vec::new();
-        self.iter().for_each(|el| temp.extend(el.serialise()));
-        let mut output = Vec::new();
-        output.extend((temp.len() as u16).serialise());
-        output.extend(temp);
-        output
-    }
+/// Everything the server answers requests on.
+struct Listeners {
+    /// The binary protocol.
+    tcp: Vec<TcpListener>,
+    /// Get and Modify over UDP.
+    udp: Udp,
+    /// The HTTP/JSON gateway, if it is on.
+    http: Option<TcpListener>,
+    /// The binary protocol in WebSocket messages, if it is on.
+    websocket: Option<TcpListener>,
 }
 
-const RECV_TIME_OUT: u64 = 5; // in seconds
-const IP_ADDRESS: &'static str = "192.168.1.100:5475";
+/// Accepts on every listener and receives on every udp socket, handing
+/// connections and datagrams to one shared pool.
+fn serve(
+    listeners: Listeners,
+    database: Arc<dyn LobbyStore>,
+    hub: Arc<Hub>,
+    limiter: Arc<Limiter>,
+    workers: usize,
+) {
+    let pool = ThreadPool::new(workers);
+    let Listeners {
+        tcp,
+        udp,
+        http,
+        websocket,
+    } = listeners;
+    let udp = Arc::new(udp);
 
-fn main() {
-    database::init();
-    let listener = match TcpListener::bind(IP_ADDRESS) {
-        Ok(listener) => listener,
-        Err(err) => {
-            etprintln!("Failed to bind the tcp server to {IP_ADDRESS}: {err:?}");
-            return;
+    thread::scope(|scope| {
+        if let Some(listener) = &http {
+            let pool = &pool;
+            let database = &database;
+            let limiter = &limiter;
+            scope.spawn(move || {
+                for stream in listener.incoming() {
+                    let mut stream = match stream {
+                        Ok(stream) => stream,
+                        Err(err) => {
+                            warn!(%err, "Failed to accept an HTTP connection");
+                            continue;
+                        }
+                    };
+                    let Some(guard) = limiter.connect() else {
+                        gateway::refuse_connection(&mut stream);
+                        continue;
+                    };
+
+                    let database = Arc::clone(database);
+                    let limiter = Arc::clone(limiter);
+                    pool.execute(move || {
+                        gateway::handle_connection(stream, database, limiter, guard)
+                    });
+                }
+            });
+        }
+
+        if let Some(listener) = &websocket {
+            let pool = &pool;
+            let database = &database;
+            let limiter = &limiter;
+            scope.spawn(move || {
+                for stream in listener.incoming() {
+                    let mut stream = match stream {
+                        Ok(stream) => stream,
+                        Err(err) => {
+                            warn!(%err, "Failed to accept a WebSocket connection");
+                            continue;
+                        }
+                    };
+                    let Some(guard) = limiter.connect() else {
+                        websocket::refuse_connection(&mut stream);
+                        continue;
+                    };
+
+                    let database = Arc::clone(database);
+                    let limiter = Arc::clone(limiter);
+                    pool.execute(move || {
+                        websocket::handle_connection(stream, database, limiter, guard)
+                    });
+                }
+            });
+        }
+
+        for (index, socket) in udp.sockets().iter().enumerate() {
+            let pool = &pool;
+            let udp = &udp;
+            let database = &database;
+            let limiter = &limiter;
+            scope.spawn(move || loop {
+                let (datagram, client_address) = match receive(socket) {
+                    Ok(received) => received,
+                    Err(err) => {
+                        warn!(%err, "Failed to receive a datagram");
+                        continue;
+                    }
+                };
+                if udp.challenge(index, &datagram, client_address) {
+                    continue;
+                }
+                // Dropped rather than refused, the refusal could be sent to
+                // a faked address.
+                let Some(guard) = limiter.connect() else {
+                    continue;
+                };
+
+                let udp = Arc::clone(udp);
+                let database = Arc::clone(database);
+                let limiter = Arc::clone(limiter);
+                pool.execute(move || {
+                    udp.answer(
+                        index,
+                        &datagram,
+                        client_address,
+                        database.as_ref(),
+                        &limiter,
+                    );
+                    drop(guard);
+                });
+            });
         }
-    };
 
-    etprintln!("Connected on {IP_ADDRESS}");
+        for listener in &tcp {
+            let pool = &pool;
+            let database = &database;
+            let hub = &hub;
+            let limiter = &limiter;
+            scope.spawn(move || {
+                for stream in listener.incoming() {
+                    let mut stream = match stream {
+                        Ok(stream) => stream,
+                        Err(err) => {
+                            warn!(%err, "Failed to accept a connection");
+                            continue;
+                        }
+                    };
+                    let Some(guard) = limiter.connect() else {
+                        refuse_connection(&mut stream);
+                        continue;
+                    };
 
-    for stream in listener.incoming() {
-        etprintln!("Connection incoming.");
-        match stream {
-            Ok(stream) => handle_connection(stream),
-            Err(err) => etprintln!("Connection failed: {err:?}"),
+                    let database = Arc::clone(database);
+                    let hub = Arc::clone(hub);
+                    let limiter = Arc::clone(limiter);
+                    pool.execute(move || handle_connection(stream, database, hub, limiter, guard));
+                }
+            });
         }
+    });
+}
+
+fn receive(socket: &UdpSocket) -> io::Result<(Vec<u8>, SocketAddr)> {
+    let mut buffer = [0; u16::MAX as usize];
+    let (length, client_address) = socket.recv_from(&mut buffer)?;
+    Ok((buffer[..length].to_vec(), client_address))
+}
+
+/// Turns a connection away without reading it, the server is already
+/// handling as many as it allows.
+fn refuse_connection(stream: &mut TcpStream) {
+    let code = Refusal::TooManyConnections as u8;
+    match stream.peer_addr() {
+        Ok(client) => debug!(%client, "Too many connections, refusing"),
+        Err(err) => debug!(%err, "Too many connections, refusing"),
+    }
+    if let Err(err) = stream.write_all(&[code]) {
+        debug!(%err, "Failed to refuse a connection");
     }
+    metrics::METRICS.record_request(None, Some(code), Duration::ZERO);
 }
 
-fn handle_connection(mut stream: TcpStream) {
+fn handle_connection(
+    mut stream: TcpStream,
+    database: Arc<dyn LobbyStore>,
+    hub: Arc<Hub>,
+    limiter: Arc<Limiter>,
+    guard: ConnectionGuard,
+) {
     let client_address: SocketAddr = match stream.peer_addr() {
         Ok(addr) => addr,
         Err(err) => {
-            etprintln!("Failed to get client address: {err:?}");
+            warn!(%err, "Failed to get client address");
             return;
         }
     };
 
-    etprintln!("Recieved client ip: {client_address}");
+    let span = info_span!("connection", client = %client_address);
+    let entered = span.enter();
+    let start = Instant::now();
 
-    let message = if let Some(msg) = get_message(&mut stream, client_address) {
-        msg
-    } else {
-        return;
+    let deadline = start + Duration::from_secs(config::get().timeouts.receive);
+    let message = match get_message(&mut stream, deadline) {
+        Ok(message) => message,
+        Err((part, err)) => {
+            let code = connection_failed(&mut stream, client_address, part, err);
+            return log_request(None, None, code, start.elapsed());
+        }
     };
 
-    etprintln!("Recieved message: {message:?}");
+    let response = respond(&message, client_address, database.as_ref(), &limiter, false);
+    write_response(&mut stream, client_address, response.code);
+    if !response.body.is_empty() {
+        let mut body = (response.body.len() as u16).serialise();
+        body.extend(&response.body);
+        if let Err(err) = stream.write_all(&body) {
+            warn!(%err, "Failed to write response body");
+        }
+    }
+    log_request(
+        None,
+        response.message_type,
+        Some(response.code),
+        start.elapsed(),
+    );
 
-    let parse_result = protocol::parse_message(message.as_slice(), client_address.into());
-    let parse_output = match parse_result {
+    // Sessions and subscriptions can sit idle for a long time, so they get
+    // their own thread rather than holding on to a worker. They still count
+    // as a connection until they end.
+    drop(entered);
+    match response.upgrade {
+        Upgrade::None => {}
+        Upgrade::Session => {
+            thread::spawn(move || {
+                let _entered = span.enter();
+                session::run(stream, client_address, database.as_ref(), &limiter);
+                drop(guard);
+            });
+        }
+        Upgrade::Subscribe(request) => {
+            let changes = hub.subscribe();
+            thread::spawn(move || {
+                let _entered = span.enter();
+                subscription::run(stream, request, changes, database.as_ref());
+                drop(guard);
+            });
+        }
+    }
+}
+
+/// The answer to one request.
+struct Response {
+    /// As logged, `None` if the message has no valid type.
+    message_type: Option<&'static str>,
+    code: u8,
+    body: Vec<u8>,
+    upgrade: Upgrade,
+}
+
+/// What the connection turns into once the response is sent.
+enum Upgrade {
+    /// Nothing, it is closed.
+    None,
+    /// Later requests come in session frames.
+    Session,
+    /// Changes to the lobbies matching the request are pushed to the client.
+    Subscribe(GetRequest),
+}
+
+/// Logs and counts one request, `request_id` is only set inside a session.
+fn log_request(
+    request_id: Option<u32>,
+    message_type: Option<&'static str>,
+    code: Option<u8>,
+    latency: Duration,
+) {
+    info!(
+        request_id,
+        message_type,
+        code,
+        latency_ms = latency.as_secs_f64() * 1000.0,
+        "Request handled"
+    );
+    metrics::METRICS.record_request(message_type, code, latency);
+}
+
+/// Handles one message from the client, `in_session` if it came in a session
+/// frame.
+fn respond(
+    message: &[u8],
+    client_address: SocketAddr,
+    database: &dyn LobbyStore,
+    limiter: &Limiter,
+    in_session: bool,
+) -> Response {
+    trace!(bytes = message.len(), "Received message");
+    let message_type = protocol::message_type(message);
+    let mut response = Response {
+        message_type,
+        code: 10,
+        body: Vec::new(),
+        upgrade: Upgrade::None,
+    };
+
+    // Before parsing, which is where a Create's password is hashed.
+    let limited_type = message_type.unwrap_or(metrics::UNKNOWN_TYPE);
+    if let Err(refusal) = limiter.check(client_address.ip(), limited_type) {
+        debug!("Rate limited");
+        response.code = refusal as u8;
+        return response;
+    }
+
+    let parse_output = match protocol::parse_message(message, client_address.into()) {
         Err(err) => {
-            etprintln!("Bad message: {err:?}");
-            return write_response(&mut stream, client_address, err as u8);
+            debug!(?err, "Bad message");
+            response.code = err as u8;
+            return response;
         }
         Ok(po) => po,
     };
 
-    let mut response_body: Vec<u8> = Vec::new();
+    let version = protocol::message_version(message).unwrap_or_default();
+    let response_body = &mut response.body;
 
     let database_result = match parse_output {
-        protocol::ParseOutput::Create(lobby) => database::create(lobby),
-        protocol::ParseOutput::Modify(lobby) => database::modify(lobby),
-        protocol::ParseOutput::Destroy((host_ip, port, password)) => {
-            database::delete(host_ip, port, password)
+        protocol::ParseOutput::Session | protocol::ParseOutput::Subscribe(_) if in_session => {
+            debug!("Session or subscription opened inside a session");
+            response.code = ParseError::InvalidType as u8;
+            return response;
+        }
+        protocol::ParseOutput::Session => {
+            response.upgrade = Upgrade::Session;
+            Ok(())
         }
+        protocol::ParseOutput::Subscribe(GetRequest {
+            filter: Filter::Search,
+            ..
+        }) => Err(DatabaseError::InvalidFilter),
+        protocol::ParseOutput::Subscribe(request) => {
+            response.upgrade = Upgrade::Subscribe(request);
+            Ok(())
+        }
+        protocol::ParseOutput::Create(lobby) => lobby
+            .ok_or(DatabaseError::FailedToHashPassword)
+            .and_then(|lobby| database.create(lobby))
+            .map(|created| *response_body = created.serialise()),
+        protocol::ParseOutput::Modify((lobby, auth)) => lobby
+            .ok_or(DatabaseError::FailedToHashPassword)
+            .and_then(|lobby| database.modify(lobby, auth)),
+        protocol::ParseOutput::Destroy((host_ip, port, auth)) => {
+            database.delete(host_ip, port, auth)
+        }
+        protocol::ParseOutput::Rotate((host_ip, port, token)) => database
+            .rotate_token(host_ip, port, token)
+            .map(|token| *response_body = token.serialise()),
+        protocol::ParseOutput::Lookup(join_code) => database
+            .lookup(&join_code)
+            .map(|lobby| *response_body = lobby.serialise_host()),
+        protocol::ParseOutput::Join((host_ip, port, password)) => {
+            let expires = database::now() + config::get().timeouts.join_ticket;
+            database
+                .join(host_ip, port, password, expires)
+                .map(|lobby| {
+                    let ticket =
+                        JoinTicket::sign(&lobby.token_hash, client_address.into(), expires);
+                    *response_body = ticket.serialise();
+                })
+        }
+        protocol::ParseOutput::Heartbeat((host_ip, port)) => database.heartbeat(host_ip, port),
         protocol::ParseOutput::Get(get_request) => {
-            let page_result = database::get(get_request);
+            let page_result = database.get(get_request);
             match page_result {
                 Ok(page) => {
-                    response_body = page.serialise();
+                    *response_body = protocol::serialise_page(page, version);
                     Ok(())
                 }
                 Err(err) => Err(err),
@@ -128,85 +537,83 @@ fn handle_connection(mut stream: TcpStream) {
         }
     };
 
-    let response: u8 = match database_result {
-        Err(err) => {
-            etprintln!("Bad message: {err:?}");
-            err as u8
-        }
-        Ok(()) => 10,
-    };
-
-    write_response(&mut stream, client_address, response);
-    database::dbg_database();
-
-    if !response_body.is_empty() {
-        let length = response_body.len() as u16;
-        let mut new_body = length.serialise();
-        new_body.extend(response_body);
-
-        if let Err(err) = stream.write_all(&new_body) {
-            etprintln!("Failed to write response_body: {err:?}");
-        } else {
-            etprintln!("Sent page to client.");
-        }
+    if let Err(err) = database_result {
+        debug!(?err, "Request failed");
+        response.code = err as u8;
+        response.body.clear();
     }
+    response
 }
 
-fn get_message(stream: &mut TcpStream, client_address: SocketAddr) -> Option<Vec<u8>> {
-    let mut thread_stream = match stream.try_clone() {
-        Ok(ts) => ts,
-        Err(err) => {
-            etprintln!("Failed to duplicate stream: {err:?}");
-            return None;
-        }
+/// The message, or the part being read when the client stopped sending.
+fn get_message(
+    stream: &mut TcpStream,
+    deadline: Instant,
+) -> Result<Vec<u8>, (&'static str, io::Error)> {
+    let mut length: [u8; 1] = [0];
+    read_before(stream, &mut length, deadline).map_err(|err| ("message length", err))?;
+
+    // A zero length byte starts a long frame with a u16 length.
+    let length = if length[0] == 0 {
+        let mut long_length: [u8; 2] = [0; 2];
+        read_before(stream, &mut long_length, deadline).map_err(|err| ("message length", err))?;
+        u16::from_be_bytes(long_length) as usize
+    } else {
+        length[0] as usize
     };
 
-    let recv_thread = thread::spawn(move || {
-        let mut length: [u8; 1] = [0];
-        if let Err(err) = thread_stream.read_exact(&mut length) {
-            etprintln!("Connection interupted: Failed to get message length. Error: {err:?}");
-            return None;
-        };
+    let mut message = vec![0; length];
+    read_before(stream, &mut message, deadline).map_err(|err| ("message body", err))?;
+
+    Ok(message)
+}
 
-        let mut message = vec![0; length[0] as usize];
-        if let Err(err) = BufReader::new(&mut thread_stream).read_exact(&mut message) {
-            etprintln!("Connection interupted: Failed to get message body. Error: {err:?}");
-            return None;
+/// Fills `buffer`, giving up once `deadline` has passed so a slow client
+/// cannot hold on to a worker thread.
+fn read_before(stream: &mut TcpStream, buffer: &mut [u8], deadline: Instant) -> io::Result<()> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(io::ErrorKind::TimedOut.into());
         }
+        stream.set_read_timeout(Some(remaining))?;
 
-        Some(message)
-    });
+        match stream.read(&mut buffer[filled..]) {
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => filled += n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
 
-    let start = Instant::now();
-    loop {
-        if recv_thread.is_finished() {
-            let message = match recv_thread.join() {
-                Ok(msg) => msg,
-                Err(err) => {
-                    etprintln!("Failed to join thread: {err:?}");
-                    return None;
-                }
-            };
-            break message;
-        }
-        if start.elapsed() > Duration::from_secs(RECV_TIME_OUT) {
-            etprintln!("Connection timed out. Ending connection.");
-            write_response(stream, client_address, 101);
-            match stream.shutdown(std::net::Shutdown::Both) {
-                Err(err) => etprintln!("Failed to shutdown connection: {err:?}"),
-                Ok(()) => (),
+/// Tells a client that timed out so, returning the code sent if any.
+fn connection_failed(
+    stream: &mut TcpStream,
+    client_address: SocketAddr,
+    part: &str,
+    err: io::Error,
+) -> Option<u8> {
+    match err.kind() {
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => {
+            debug!("Connection timed out");
+            write_response(stream, client_address, metrics::TIMEOUT_CODE);
+            if let Err(err) = stream.shutdown(std::net::Shutdown::Both) {
+                debug!(%err, "Failed to shut down connection");
             }
-            return None;
+            Some(metrics::TIMEOUT_CODE)
+        }
+        _ => {
+            debug!(%err, "Connection interrupted while reading the {part}");
+            None
         }
-
-        std::thread::sleep(Duration::from_millis(100));
     }
 }
 
 fn write_response(stream: &mut TcpStream, client_address: SocketAddr, response: u8) {
     if let Err(err) = stream.write(&[response]) {
-        etprintln!("Failed to write to stream. Client: {client_address} / Code: {response}. Error: {err:?}");
-    } else {
-        etprintln!("Sent response {response} to client {client_address}.");
+        warn!(%err, client = %client_address, code = response, "Failed to write response");
     }
 }
@@ -0,0 +1,203 @@
+//! Request counters and latency histograms, served with the lobby counts on a
+//! plain HTTP `/metrics` listener in the Prometheus text format.
+
+use crate::{config, database::LobbyStore, http, protocol::Region};
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    io,
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Mutex, PoisonError},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+use tracing::{debug, warn};
+
+/// Upper bounds of the latency buckets, in seconds.
+const LATENCY_BUCKETS: [f64; 12] = [
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0,
+];
+
+/// Requests whose type could not be parsed are counted under this type.
+pub const UNKNOWN_TYPE: &str = "unknown";
+
+/// The response code of a connection that was dropped before one was sent.
+const NO_CODE: &str = "none";
+
+/// The connection timed out before the whole message arrived.
+pub const TIMEOUT_CODE: u8 = 101;
+
+/// The server's metrics.
+pub static METRICS: Metrics = Metrics::new();
+
+pub struct Metrics {
+    requests: Mutex<BTreeMap<(&'static str, Option<u8>), u64>>,
+    latencies: Mutex<BTreeMap<&'static str, Histogram>>,
+}
+
+#[derive(Default)]
+struct Histogram {
+    /// Requests at or under each of `LATENCY_BUCKETS`, not cumulative.
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, seconds: f64) {
+        if let Some(bucket) = LATENCY_BUCKETS.iter().position(|bound| seconds <= *bound) {
+            self.buckets[bucket] += 1;
+        }
+        self.count += 1;
+        self.sum += seconds;
+    }
+}
+
+impl Metrics {
+    pub const fn new() -> Self {
+        Self {
+            requests: Mutex::new(BTreeMap::new()),
+            latencies: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Counts one connection. `message_type` is `None` if the message never
+    /// arrived or could not be parsed, `code` if no response was sent.
+    pub fn record_request(
+        &self,
+        message_type: Option<&'static str>,
+        code: Option<u8>,
+        latency: Duration,
+    ) {
+        let message_type = message_type.unwrap_or(UNKNOWN_TYPE);
+        *self
+            .requests
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry((message_type, code))
+            .or_default() += 1;
+        self.latencies
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry(message_type)
+            .or_default()
+            .observe(latency.as_secs_f64());
+    }
+
+    /// Every metric in the text exposition format, with the lobby gauges
+    /// read from `store`.
+    pub fn render(&self, store: &dyn LobbyStore) -> String {
+        let mut output = String::new();
+
+        output.push_str(
+            "# HELP omicron_requests_total Requests by message type and response code.\n",
+        );
+        output.push_str("# TYPE omicron_requests_total counter\n");
+        let requests = self.requests.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut timeouts = 0;
+        for ((message_type, code), count) in requests.iter() {
+            if *code == Some(TIMEOUT_CODE) {
+                timeouts += count;
+            }
+            let code = code.map_or(NO_CODE.to_string(), |code| code.to_string());
+            let _ = writeln!(
+                output,
+                "omicron_requests_total{{type=\"{message_type}\",code=\"{code}\"}} {count}"
+            );
+        }
+        drop(requests);
+
+        output.push_str("# HELP omicron_connection_timeouts_total Connections that timed out before sending a whole message.\n");
+        output.push_str("# TYPE omicron_connection_timeouts_total counter\n");
+        let _ = writeln!(output, "omicron_connection_timeouts_total {timeouts}");
+
+        output.push_str("# HELP omicron_request_duration_seconds Time from accepting a connection to answering it.\n");
+        output.push_str("# TYPE omicron_request_duration_seconds histogram\n");
+        let latencies = self
+            .latencies
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        for (message_type, histogram) in latencies.iter() {
+            let mut cumulative = 0;
+            for (bound, count) in LATENCY_BUCKETS.iter().zip(histogram.buckets) {
+                cumulative += count;
+                let _ = writeln!(
+                    output,
+                    "omicron_request_duration_seconds_bucket{{type=\"{message_type}\",le=\"{bound}\"}} {cumulative}"
+                );
+            }
+            let _ = writeln!(
+                output,
+                "omicron_request_duration_seconds_bucket{{type=\"{message_type}\",le=\"+Inf\"}} {}\n\
+                 omicron_request_duration_seconds_sum{{type=\"{message_type}\"}} {}\n\
+                 omicron_request_duration_seconds_count{{type=\"{message_type}\"}} {}",
+                histogram.count, histogram.sum, histogram.count
+            );
+        }
+        drop(latencies);
+
+        // An empty list if the store fails, so the request metrics still get through.
+        let lobbies = store.list().unwrap_or_default();
+        let mut per_region: BTreeMap<&str, (u64, u64)> = Region::get_regions(0)
+            .iter()
+            .map(|region| (region.name(), (0, 0)))
+            .collect();
+        for lobby in &lobbies {
+            let (count, players) = per_region.entry(lobby.region.name()).or_default();
+            *count += 1;
+            *players += lobby.current_players as u64;
+        }
+
+        output.push_str("# HELP omicron_lobbies Lobbies by region.\n");
+        output.push_str("# TYPE omicron_lobbies gauge\n");
+        for (region, (count, _)) in &per_region {
+            let _ = writeln!(output, "omicron_lobbies{{region=\"{region}\"}} {count}");
+        }
+        output.push_str("# HELP omicron_players Players in lobbies by region.\n");
+        output.push_str("# TYPE omicron_players gauge\n");
+        for (region, (_, players)) in &per_region {
+            let _ = writeln!(output, "omicron_players{{region=\"{region}\"}} {players}");
+        }
+
+        output
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Answers `GET /metrics` on `listener`, one scrape at a time.
+pub fn spawn_server(listener: TcpListener, store: Arc<dyn LobbyStore>) -> JoinHandle<()> {
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(mut stream) => {
+                    if let Err(err) = answer(&mut stream, &METRICS, store.as_ref()) {
+                        debug!(%err, "Failed to answer a metrics request");
+                    }
+                }
+                Err(err) => warn!(%err, "Failed to accept a metrics connection"),
+            }
+        }
+    })
+}
+
+fn answer(stream: &mut TcpStream, metrics: &Metrics, store: &dyn LobbyStore) -> io::Result<()> {
+    let deadline = Instant::now() + Duration::from_secs(config::get().timeouts.receive);
+    let request = http::read_request(stream, deadline)?;
+
+    let (status, content_type, body) = match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/metrics") => ("200 OK", "text/plain; version=0.0.4", metrics.render(store)),
+        ("GET", _) => ("404 Not Found", "text/plain", String::from("Not Found\n")),
+        _ => (
+            "405 Method Not Allowed",
+            "text/plain",
+            String::from("Method Not Allowed\n"),
+        ),
+    };
+
+    http::write_response(stream, status, content_type, body.as_bytes())
+}
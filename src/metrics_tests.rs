@@ -0,0 +1,139 @@
+use super::metrics::*;
+use crate::{
+    database::{InMemory, Lobby, LobbyStore},
+    protocol::{Flags, IpAddress, Region},
+};
+use std::{
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::Arc,
+    time::Duration,
+};
+
+fn store_with_lobbies() -> InMemory {
+    let store = InMemory::new();
+    for (port, region, players) in [
+        (1, Region::Europe, 3),
+        (2, Region::Europe, 2),
+        (3, Region::Asia, 5),
+    ] {
+        let mut lobby = Lobby::new(
+            Flags::new(false, true, false),
+            region,
+            IpAddress::IpV4([127, 0, 0, 1]),
+            port,
+            8,
+            format!("Lobby {port}"),
+            String::new(),
+        )
+        .unwrap();
+        lobby.set_player_count(players);
+        store.create(lobby).unwrap();
+    }
+    store
+}
+
+fn line<'a>(output: &'a str, metric: &str) -> Option<&'a str> {
+    output
+        .lines()
+        .find(|line| line.starts_with(metric) && line[metric.len()..].starts_with(' '))
+        .map(|line| &line[metric.len() + 1..])
+}
+
+#[test]
+fn counts_requests() {
+    let metrics = Metrics::new();
+    metrics.record_request(Some("create"), Some(10), Duration::from_millis(2));
+    metrics.record_request(Some("create"), Some(10), Duration::from_millis(3));
+    metrics.record_request(Some("join"), Some(60), Duration::from_millis(1));
+    metrics.record_request(None, Some(TIMEOUT_CODE), Duration::from_secs(5));
+    metrics.record_request(None, None, Duration::from_millis(1));
+
+    let output = metrics.render(&InMemory::new());
+    let count = |labels: &str| line(&output, &format!("omicron_requests_total{{{labels}}}"));
+    assert_eq!(count(r#"type="create",code="10""#), Some("2"));
+    assert_eq!(count(r#"type="join",code="60""#), Some("1"));
+    assert_eq!(count(r#"type="unknown",code="101""#), Some("1"));
+    assert_eq!(count(r#"type="unknown",code="none""#), Some("1"));
+    assert_eq!(
+        line(&output, "omicron_connection_timeouts_total"),
+        Some("1")
+    );
+}
+
+#[test]
+fn latency_histogram() {
+    let metrics = Metrics::new();
+    metrics.record_request(Some("get"), Some(10), Duration::from_micros(500));
+    metrics.record_request(Some("get"), Some(10), Duration::from_millis(20));
+    metrics.record_request(Some("get"), Some(10), Duration::from_secs(30));
+
+    let output = metrics.render(&InMemory::new());
+    let bucket = |le: &str| {
+        line(
+            &output,
+            &format!(r#"omicron_request_duration_seconds_bucket{{type="get",le="{le}"}}"#),
+        )
+    };
+    assert_eq!(bucket("0.001"), Some("1"));
+    assert_eq!(bucket("0.01"), Some("1"));
+    assert_eq!(bucket("0.025"), Some("2"));
+    assert_eq!(bucket("5"), Some("2"));
+    assert_eq!(bucket("+Inf"), Some("3"));
+    assert_eq!(
+        line(
+            &output,
+            r#"omicron_request_duration_seconds_count{type="get"}"#
+        ),
+        Some("3")
+    );
+}
+
+#[test]
+fn lobbies_per_region() {
+    let output = Metrics::new().render(&store_with_lobbies());
+
+    assert_eq!(
+        line(&output, r#"omicron_lobbies{region="europe"}"#),
+        Some("2")
+    );
+    assert_eq!(
+        line(&output, r#"omicron_lobbies{region="asia"}"#),
+        Some("1")
+    );
+    assert_eq!(
+        line(&output, r#"omicron_lobbies{region="oceania"}"#),
+        Some("0")
+    );
+    assert_eq!(
+        line(&output, r#"omicron_players{region="europe"}"#),
+        Some("5")
+    );
+    assert_eq!(
+        line(&output, r#"omicron_players{region="asia"}"#),
+        Some("5")
+    );
+}
+
+fn http_get(address: std::net::SocketAddr, path: &str) -> String {
+    let mut stream = TcpStream::connect(address).unwrap();
+    write!(stream, "GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+#[test]
+fn http_endpoint() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let store: Arc<dyn LobbyStore> = Arc::new(store_with_lobbies());
+    spawn_server(listener, store);
+
+    let response = http_get(address, "/metrics");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.contains("Content-Type: text/plain; version=0.0.4\r\n"));
+    assert!(response.contains("\nomicron_lobbies{region=\"europe\"} 2\n"));
+
+    assert!(http_get(address, "/").starts_with("HTTP/1.1 404 Not Found\r\n"));
+}
@@ -1,109 +1,89 @@
-#[derive(Debug)]
-#[repr(u8)]
-pub enum ParseError {
-    EmptyMessage = 40,
-    InvalidType = 41,
-    MissingMessagePart = 42,
-    InvalidRegion = 43,
-    InvalidName = 44,
-    MismatchedIP = 45,
-    OutOfDate = 46,
-    InvalidFilter = 47,
-    // 50+ is reserved currently
-}
-
 #[derive(Debug, PartialEq)]
 pub enum ParseOutput {
     Create(Option<Lobby>),
-    Modify(Option<Lobby>),
-    Destroy((IpAddress, u16, Option<String>)),
+    Modify((Option<Lobby>, HostAuth)),
+    Destroy((IpAddress, u16, HostAuth)),
+    Rotate((IpAddress, u16, HostToken)),
+    Lookup(String),
+    Join((IpAddress, u16, Option<String>)),
     Get(GetRequest),
+    Heartbeat((IpAddress, u16)),
+    /// Switches the connection to session frames.
+    Session,
+    /// Switches the connection to pushed changes to the matching lobbies.
+    Subscribe(GetRequest),
 }
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
-pub enum IpAddress {
-    IpV4([u8; 4]),
-    IpV6([u16; 8]),
-}
-impl Default for IpAddress {
-    fn default() -> Self {
-        Self::IpV4([0; 4])
-    }
-}
-
-impl IpAddress {
-    fn from_message(msg: &mut std::slice::Iter<u8>, is_ipv6: bool) -> Result<Self, ParseError> {
-        if is_ipv6 {
-            let mut parts: [u16; 8] = [0; 8];
-
-            for i in 0..8 {
-                let part1 = *msg.next().ok_or(ParseError::MissingMessagePart)? as u16;
-                let part2 = *msg.next().ok_or(ParseError::MissingMessagePart)? as u16;
-                parts[i] = (part1 << 8) | part2;
-            }
-
-            Ok(IpAddress::IpV6(parts))
-        } else {
-            let mut parts: [u8; 4] = [0; 4];
+#[cfg(test)]
+mod parse_tests;
+mod version0;
+mod version1;
+mod version2;
 
-            for i in 0..4 {
-                parts[i] = *msg.next().ok_or(ParseError::MissingMessagePart)?;
-            }
+use crate::{
+    database::{HostAuth, HostToken, Lobby, Page},
+    Serialise,
+};
+pub use omicron_protocol::{
+    Cursor, Filter, Flags, GetRequest, IpAddress, ParseError, Predicates, Region, Types,
+};
 
-            Ok(IpAddress::IpV4(parts))
-        }
-    }
+/// The protocol version in the low nibble of a message's type byte.
+pub fn message_version(message: &[u8]) -> Result<u8, ParseError> {
+    message
+        .first()
+        .map(|m_type| m_type & 0xF)
+        .ok_or(ParseError::EmptyMessage)
 }
 
-impl Display for IpAddress {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            IpAddress::IpV4(ip) => write!(f, "{}/4", ip.map(|n| n.to_string()).join(".")),
-            IpAddress::IpV6(ip) => write!(f, "{}/6", ip.map(|n| format!("{n:x}")).join(":")),
-        }
-    }
-}
+/// Every message type, as logged.
+pub const MESSAGE_TYPES: [&str; 10] = [
+    "create",
+    "modify",
+    "heartbeat",
+    "destroy",
+    "rotate",
+    "lookup",
+    "join",
+    "get",
+    "session",
+    "subscribe",
+];
 
-impl Into<IpAddress> for std::net::SocketAddr {
-    fn into(self) -> IpAddress {
-        fn to_u16(high: u8, low: u8) -> u16 {
-            ((high as u16) << 8) | (low as u16)
-        }
+/// The message type in the high nibble of a message's type byte, as logged.
+/// `None` if it is not one.
+pub fn message_type(message: &[u8]) -> Option<&'static str> {
+    let name = match Types::from(message.first()? >> 4) {
+        Types::None => return None,
+        Types::Create => "create",
+        Types::Modify => "modify",
+        Types::Heartbeat => "heartbeat",
+        Types::Destroy => "destroy",
+        Types::Rotate => "rotate",
+        Types::Lookup => "lookup",
+        Types::Join => "join",
+        Types::Get => "get",
+        Types::Session => "session",
+        Types::Subscribe => "subscribe",
+    };
+    Some(name)
+}
 
-        match self {
-            std::net::SocketAddr::V4(addr4) => IpAddress::IpV4(addr4.ip().octets()),
-            std::net::SocketAddr::V6(addr6) => {
-                let octets = addr6.ip().octets();
-                IpAddress::IpV6([
-                    to_u16(octets[0], octets[1]),
-                    to_u16(octets[2], octets[3]),
-                    to_u16(octets[4], octets[5]),
-                    to_u16(octets[6], octets[7]),
-                    to_u16(octets[8], octets[9]),
-                    to_u16(octets[10], octets[11]),
-                    to_u16(octets[12], octets[13]),
-                    to_u16(octets[14], octets[15]),
-                ])
-            }
-        }
+/// Parses a message with the protocol version named by its type byte.
+pub fn parse_message(message: &[u8], ip_address: IpAddress) -> Result<ParseOutput, ParseError> {
+    match message_version(message)? {
+        version0::VERSION => version0::parse_message(message, ip_address),
+        version1::VERSION => version1::parse_message(message, ip_address),
+        version2::VERSION => version2::parse_message(message, ip_address),
+        _ => Err(ParseError::OutOfDate),
     }
 }
 
-impl Serialise for IpAddress {
-    fn serialise(self) -> Vec<u8> {
-        let mut output = Vec::new();
-        match self {
-            IpAddress::IpV4(octets) => output.extend(octets),
-            IpAddress::IpV6(hexets) => hexets.iter().for_each(|hex| output.extend(hex.serialise())),
-        }
-        output
+/// Serialises a page for a client speaking `version`.
+pub fn serialise_page(page: Page, version: u8) -> Vec<u8> {
+    match version {
+        version1::VERSION => version1::serialise_page(page),
+        version2::VERSION => version2::serialise_page(page),
+        _ => page.serialise(),
     }
 }
-
-#[cfg(test)]
-mod parse_tests;
-mod version0;
-
-use crate::{database::Lobby, Serialise};
-use std::fmt::Display;
-pub use version0::{parse_message, Filter, Flags, GetRequest, Region};
@@ -2,14 +2,14 @@ use super::*;
 
 #[cfg(test)]
 fn basic_lobby_message(typ: u8) -> Vec<u8> {
-    let type_version = (typ << 4) + 0x0; // CREATE | V0
+    let type_version = typ << 4; // CREATE | V0
     let flags = 0b100; // Has password
     let ip_address = [192, 168, 1, 111];
     let (port_high, port_low) = {
         let port = 25565;
         ((port >> 8) as u8, (port & 0xFF) as u8)
     };
-    let region = 5; // Oceania
+    let region = 32; // Oceania
     let max_players = 10;
     let (lobby_name_size, lobby_name_bytes) = {
         let lobby_name = String::from("Test Lobby!");
@@ -59,6 +59,7 @@ fn create() {
             assert!(lobby.is_some());
             let lobby = lobby.unwrap();
             assert_eq!(expected_lobby, lobby);
+            assert!(lobby.password_auth);
         }
         _ => panic!("Incorrect protocol type."),
     }
@@ -84,10 +85,29 @@ fn modify() {
 
     let parsed = parse_message(message.as_slice(), IpAddress::IpV4([192, 168, 1, 111]));
     match parsed.unwrap() {
-        ParseOutput::Modify(lobby) => {
+        ParseOutput::Modify((lobby, auth)) => {
             assert!(lobby.is_some());
             let lobby = lobby.unwrap();
             assert_eq!(expected_lobby, lobby);
+            assert_eq!(auth, HostAuth::Password(String::from("password123")));
+        }
+        _ => panic!("Incorrect protocol type."),
+    }
+}
+
+#[test]
+fn modify_v1() {
+    let mut message = v1_lobby_message(0b10, b"Test Lobby!");
+    message.push(25);
+    message.extend([7; 16]); // Host token
+
+    let parsed = parse_message(message.as_slice(), IpAddress::IpV4([192, 168, 1, 111]));
+    match parsed.unwrap() {
+        ParseOutput::Modify((lobby, auth)) => {
+            let lobby = lobby.unwrap();
+            assert_eq!(lobby.current_players, 25);
+            assert!(bcrypt::verify("password123", &lobby.password).unwrap());
+            assert_eq!(auth, HostAuth::Token(HostToken([7; 16])));
         }
         _ => panic!("Incorrect protocol type."),
     }
@@ -95,7 +115,7 @@ fn modify() {
 
 #[test]
 fn destory() {
-    let type_version = (0b100 << 4) + 0x0; // CREATE | V0
+    let type_version = 0b100 << 4; // DESTROY | V0
     let ip_address = [192, 168, 1, 111];
     let port = {
         let port = 25565;
@@ -114,11 +134,15 @@ fn destory() {
     message2.push(pass_size);
     message2.extend(pass_bytes);
 
-    let expected1 = (IpAddress::IpV4(ip_address), 25565, None);
+    let expected1 = (
+        IpAddress::IpV4(ip_address),
+        25565,
+        HostAuth::Password(String::new()),
+    );
     let expected2 = (
         IpAddress::IpV4(ip_address),
         25565,
-        Some(String::from("password123")),
+        HostAuth::Password(String::from("password123")),
     );
 
     let parsed = parse_message(message1.as_slice(), IpAddress::IpV4(ip_address));
@@ -127,3 +151,277 @@ fn destory() {
     let parsed = parse_message(message2.as_slice(), IpAddress::IpV4(ip_address));
     assert_eq!(parsed.unwrap(), ParseOutput::Destroy(expected2));
 }
+
+#[test]
+fn destroy_v1() {
+    let type_version = (0b100 << 4) | 1; // DESTROY | V1
+    let ip_address = [192, 168, 1, 111];
+    let mut message = vec![type_version, false as u8];
+    message.extend(ip_address);
+    message.extend([0x63, 0xDD]); // 25565
+
+    let parsed = parse_message(message.as_slice(), IpAddress::IpV4(ip_address));
+    assert!(matches!(parsed, Err(ParseError::MissingMessagePart)));
+
+    message.extend([7; 16]); // Host token
+    let parsed = parse_message(message.as_slice(), IpAddress::IpV4(ip_address));
+    assert_eq!(
+        parsed.unwrap(),
+        ParseOutput::Destroy((
+            IpAddress::IpV4(ip_address),
+            25565,
+            HostAuth::Token(HostToken([7; 16]))
+        ))
+    );
+}
+
+#[test]
+fn lookup() {
+    let mut message = vec![0b110 << 4]; // LOOKUP | V0
+    message.extend(String::from("ABC234").serialise());
+
+    let parsed = parse_message(message.as_slice(), IpAddress::IpV4([192, 168, 1, 111]));
+    assert_eq!(parsed.unwrap(), ParseOutput::Lookup(String::from("ABC234")));
+
+    let mut message = vec![0b110 << 4];
+    message.extend(String::from("ABC2345").serialise());
+    let parsed = parse_message(message.as_slice(), IpAddress::IpV4([192, 168, 1, 111]));
+    assert!(matches!(parsed, Err(ParseError::InvalidName)));
+}
+
+#[test]
+fn join() {
+    let type_version = 0b111 << 4; // JOIN | V0
+    let mut message = vec![type_version, false as u8];
+    message.extend([192, 168, 1, 111]); // Someone else's lobby
+    message.extend([0x63, 0xDD]); // 25565
+
+    let host = IpAddress::IpV4([192, 168, 1, 111]);
+    let player = IpAddress::IpV4([10, 0, 0, 7]);
+    let parsed = parse_message(message.as_slice(), player);
+    assert_eq!(parsed.unwrap(), ParseOutput::Join((host, 25565, None)));
+
+    message.extend(String::from("password123").serialise());
+    let parsed = parse_message(message.as_slice(), player);
+    assert_eq!(
+        parsed.unwrap(),
+        ParseOutput::Join((host, 25565, Some(String::from("password123"))))
+    );
+}
+
+#[test]
+fn rotate() {
+    let type_version = 0b101 << 4; // ROTATE | V0
+    let ip_address = [192, 168, 1, 111];
+    let mut message = vec![type_version, false as u8];
+    message.extend(ip_address);
+    message.extend([0x63, 0xDD]); // 25565
+    message.extend([7; 15]);
+
+    let parsed = parse_message(message.as_slice(), IpAddress::IpV4(ip_address));
+    assert!(matches!(parsed, Err(ParseError::MissingMessagePart)));
+
+    message.push(7);
+    let parsed = parse_message(message.as_slice(), IpAddress::IpV4(ip_address));
+    assert_eq!(
+        parsed.unwrap(),
+        ParseOutput::Rotate((IpAddress::IpV4(ip_address), 25565, HostToken([7; 16])))
+    );
+}
+
+#[test]
+fn heartbeat() {
+    let type_version = 0b11 << 4; // HEARTBEAT | V0
+    let ip_address = [192, 168, 1, 111];
+    let mut message = vec![type_version, false as u8];
+    message.extend(ip_address);
+    message.extend([0x63, 0xDD]); // 25565
+
+    let parsed = parse_message(message.as_slice(), IpAddress::IpV4(ip_address));
+    assert_eq!(
+        parsed.unwrap(),
+        ParseOutput::Heartbeat((IpAddress::IpV4(ip_address), 25565))
+    );
+
+    let parsed = parse_message(message.as_slice(), IpAddress::IpV4([10, 0, 0, 1]));
+    assert!(matches!(parsed, Err(ParseError::MismatchedIP)));
+}
+
+#[test]
+fn session() {
+    let ip_address = IpAddress::IpV4([192, 168, 1, 111]);
+    for type_version in [0b1001 << 4, (0b1001 << 4) | 1] {
+        let parsed = parse_message(&[type_version, 0], ip_address);
+        assert_eq!(parsed.unwrap(), ParseOutput::Session);
+    }
+}
+
+/// `basic_lobby_message` as version 1, with `lobby_name` as the raw name bytes.
+fn v1_lobby_message(typ: u8, lobby_name: &[u8]) -> Vec<u8> {
+    let mut message = basic_lobby_message(typ);
+    message[0] |= 1; // V1
+    let name_start = 10;
+    let name_end = name_start + 1 + message[name_start] as usize;
+
+    let mut name = vec![lobby_name.len() as u8];
+    name.extend(lobby_name);
+    message.splice(name_start..name_end, name);
+    message
+}
+
+#[test]
+fn create_v1_utf8() {
+    let lobby_name = "Café ☕ ñandú";
+    let message = v1_lobby_message(0b1, lobby_name.as_bytes());
+
+    let parsed = parse_message(message.as_slice(), IpAddress::IpV4([192, 168, 1, 111]));
+    match parsed.unwrap() {
+        ParseOutput::Create(Some(lobby)) => {
+            assert_eq!(lobby.lobby_name, lobby_name);
+            assert!(!lobby.password_auth);
+        }
+        _ => panic!("Incorrect protocol type."),
+    }
+}
+
+#[test]
+fn create_v1_invalid_name() {
+    let ip_address = IpAddress::IpV4([192, 168, 1, 111]);
+
+    let not_utf8 = v1_lobby_message(0b1, &[b'a', 0xC3, b'b']);
+    let parsed = parse_message(not_utf8.as_slice(), ip_address);
+    assert!(matches!(parsed, Err(ParseError::InvalidName)));
+
+    // 32 characters is fine however many bytes they take, 33 is not.
+    let longest = "é".repeat(32);
+    let message = v1_lobby_message(0b1, longest.as_bytes());
+    assert!(parse_message(message.as_slice(), ip_address).is_ok());
+
+    let too_long = "é".repeat(33);
+    let message = v1_lobby_message(0b1, too_long.as_bytes());
+    let parsed = parse_message(message.as_slice(), ip_address);
+    assert!(matches!(parsed, Err(ParseError::InvalidName)));
+}
+
+#[test]
+fn get_v1() {
+    let mut message = vec![(0b1000 << 4) | 1, 0x80 | 3, 4, 0x01, 0x2C];
+    let search = "Café";
+    message.push(search.len() as u8);
+    message.extend(search.as_bytes());
+
+    let parsed = parse_message(message.as_slice(), IpAddress::IpV4([192, 168, 1, 111]));
+    assert_eq!(
+        parsed.unwrap(),
+        ParseOutput::Get(GetRequest {
+            filter: Filter::PlayerCountDescending,
+            regions: vec![Region::Europe],
+            page_num: 300,
+            search: Some(String::from(search)),
+            page_size: None,
+            cursor: None,
+            predicates: Predicates::default(),
+        })
+    );
+}
+
+#[test]
+fn get_v2() {
+    let cursor = Cursor {
+        filter: Filter::NameDescending,
+        lobby_name: String::from("Café"),
+        current_players: 3,
+        key: String::from("10.0.0.1:7777"),
+    };
+    let cursor_bytes = cursor.serialise();
+    let message = |filter: u8, page_size: u8, cursor: &[u8]| {
+        let mut message = vec![(0b1000 << 4) | 2, 0x80 | filter, 4, page_size];
+        message.extend([0b0011, 2, 9]); // Hide full and password, 2 to 9 players.
+        message.extend((cursor.len() as u16).serialise());
+        message.extend(cursor);
+        message.extend(b"\x03caf");
+        message
+    };
+    let ip_address = IpAddress::IpV4([192, 168, 1, 111]);
+
+    let parsed = parse_message(&message(1, 40, &cursor_bytes), ip_address);
+    assert_eq!(
+        parsed.unwrap(),
+        ParseOutput::Get(GetRequest {
+            filter: Filter::NameDescending,
+            regions: vec![Region::Europe],
+            page_num: 0,
+            search: Some(String::from("caf")),
+            page_size: Some(40),
+            cursor: Some(cursor),
+            predicates: Predicates {
+                hide_full: true,
+                hide_password: true,
+                players: 2..=9,
+                ipv6: None,
+            },
+        })
+    );
+
+    // The first page, in the server's page size.
+    match parse_message(&message(1, 0, &[]), ip_address) {
+        Ok(ParseOutput::Get(request)) => {
+            assert_eq!((request.page_size, request.cursor), (None, None));
+        }
+        parsed => panic!("{parsed:?}"),
+    }
+
+    // A cursor from another order, or one that was tampered with.
+    let parsed = parse_message(&message(0, 40, &cursor_bytes), ip_address);
+    assert!(matches!(parsed, Err(ParseError::InvalidCursor)));
+    let parsed = parse_message(&message(1, 40, &cursor_bytes[..5]), ip_address);
+    assert!(matches!(parsed, Err(ParseError::InvalidCursor)));
+    let mut both_families = message(1, 40, &[]);
+    both_families[4] = 0b1100;
+    let parsed = parse_message(&both_families, ip_address);
+    assert!(matches!(parsed, Err(ParseError::InvalidFilter)));
+    let mut cut_short = message(1, 40, &cursor_bytes);
+    cut_short.truncate(12);
+    let parsed = parse_message(&cut_short, ip_address);
+    assert!(matches!(parsed, Err(ParseError::MissingMessagePart)));
+}
+
+#[test]
+fn unknown_version() {
+    let message = [(0b1000 << 4) | 3, 0, 0, 0];
+    let parsed = parse_message(&message, IpAddress::IpV4([192, 168, 1, 111]));
+    assert!(matches!(parsed, Err(ParseError::OutOfDate)));
+}
+
+#[test]
+fn message_type_names() {
+    assert_eq!(message_type(&basic_lobby_message(0x1)), Some("create"));
+    assert_eq!(message_type(&[(0x8 << 4) | 1]), Some("get"));
+    assert_eq!(message_type(&[0x9 << 4]), Some("session"));
+    assert_eq!(message_type(&[0xF0]), None);
+    assert_eq!(message_type(&[]), None);
+}
+
+#[test]
+fn subscribe() {
+    let ip_address = IpAddress::IpV4([192, 168, 1, 111]);
+    let mut message = vec![0b1010 << 4, 0x80 | 3, 0b100];
+    message.extend(String::from("Lobby").serialise());
+
+    let parsed = parse_message(&message, ip_address).unwrap();
+    assert_eq!(
+        parsed,
+        ParseOutput::Subscribe(GetRequest {
+            filter: Filter::PlayerCountDescending,
+            regions: vec![Region::Europe],
+            page_num: 0,
+            search: Some(String::from("Lobby")),
+            page_size: None,
+            cursor: None,
+            predicates: Predicates::default(),
+        })
+    );
+
+    message[0] |= 1; // V1
+    assert_eq!(parse_message(&message, ip_address).unwrap(), parsed);
+}
@@ -1,191 +1,26 @@
-use super::{IpAddress, ParseError, ParseOutput};
-use crate::{database::Lobby, Serialise};
-
-const VERSION: u8 = 0;
-const MAX_LOBBY_NAME_SIZE: usize = 32;
-const MAX_LOBBY_PASS_SIZE: usize = 32;
-
-type IterU8<'a> = std::slice::Iter<'a, u8>;
-
-#[repr(u8)]
-pub enum Types {
-    None = 0x0,
-    Create = 0x1,
-    Modify = 0x2,
-    Destroy = 0x4,
-    Get = 0x8,
+use super::{
+    Filter, Flags, GetRequest, IpAddress, ParseError, ParseOutput, Predicates, Region, Types,
+};
+use crate::{
+    config,
+    database::{now, HostAuth, HostToken, Lobby, JOIN_CODE_LENGTH, NO_TOKEN, TOKEN_SIZE},
+};
+
+pub(super) const VERSION: u8 = 0;
+
+pub(super) fn max_name_length() -> usize {
+    config::get().limits.max_name_length
 }
 
-impl From<u8> for Types {
-    fn from(value: u8) -> Self {
-        match value {
-            0x1 => Self::Create,
-            0x2 => Self::Modify,
-            0x4 => Self::Destroy,
-            0x8 => Self::Get,
-            _ => Self::None,
-        }
-    }
-}
-
-impl From<Types> for u8 {
-    fn from(value: Types) -> Self {
-        value as u8
-    }
-}
-
-#[derive(Default, PartialEq, Debug, Clone)]
-pub struct Flags {
-    is_ipv6: bool,
-    is_public: bool,
-    has_password: bool,
-}
-
-impl Serialise for Flags {
-    fn serialise(self) -> Vec<u8> {
-        let mut output = 0;
-        if self.is_ipv6 {
-            output |= 1;
-        }
-        if self.is_public {
-            output |= 2;
-        }
-        if self.has_password {
-            output |= 4;
-        }
-        vec![output]
-    }
-}
-
-#[cfg(test)]
-impl Flags {
-    pub fn new(is_ipv6: bool, is_public: bool, has_password: bool) -> Self {
-        Self {
-            is_ipv6,
-            is_public,
-            has_password,
-        }
-    }
-}
-
-impl Into<Flags> for u8 {
-    fn into(self) -> Flags {
-        let mut flags = Flags::default();
-        flags.is_ipv6 = self & 0x1 != 0;
-        flags.is_public = self & 0x2 != 0;
-        flags.has_password = self & 0x4 != 0;
-        flags
-    }
-}
-
-#[repr(u8)]
-#[derive(Default, Debug, PartialEq, Eq, Clone)]
-pub enum Region {
-    #[default]
-    Africa = 1,
-    Asia = 2,
-    Europe = 4,
-    NorthAmerica = 8,
-    SouthAmerica = 16,
-    Oceania = 32,
-}
-
-impl Serialise for Region {
-    fn serialise(self) -> Vec<u8> {
-        vec![self as u8]
-    }
-}
-
-impl TryInto<Region> for u8 {
-    type Error = ParseError;
-
-    fn try_into(self) -> Result<Region, Self::Error> {
-        let region = match self {
-            1 => Region::Africa,
-            2 => Region::Asia,
-            4 => Region::Europe,
-            8 => Region::NorthAmerica,
-            16 => Region::SouthAmerica,
-            32 => Region::Oceania,
-            _ => Err(ParseError::InvalidRegion)?,
-        };
-
-        Ok(region)
-    }
-}
-
-impl Region {
-    pub fn get_regions(value: u8) -> Vec<Region> {
-        let mut output = Vec::new();
-
-        if value & 1 == 1 {
-            output.push(Region::Africa);
-        }
-        if value & 2 == 2 {
-            output.push(Region::Asia);
-        }
-        if value & 4 == 4 {
-            output.push(Region::Europe);
-        }
-        if value & 8 == 8 {
-            output.push(Region::NorthAmerica);
-        }
-        if value & 16 == 16 {
-            output.push(Region::SouthAmerica);
-        }
-        if value & 32 == 32 {
-            output.push(Region::Oceania);
-        }
-
-        if output.is_empty() {
-            output = vec![
-                Region::Africa,
-                Region::Asia,
-                Region::Europe,
-                Region::NorthAmerica,
-                Region::SouthAmerica,
-                Region::Oceania,
-            ];
-        }
-
-        output
-    }
-}
-
-#[derive(Debug, PartialEq, Eq)]
-pub struct GetRequest {
-    pub filter: Filter,
-    pub regions: Vec<Region>,
-    pub page_num: u8,
-    pub search: Option<String>,
+fn max_password_length() -> usize {
+    config::get().limits.max_password_length
 }
 
-#[repr(u8)]
-#[derive(Debug, PartialEq, Eq)]
-pub enum Filter {
-    NameAscending = 0,
-    NameDescending = 1,
-    PlayerCountAscending = 2,
-    PlayerCountDescending = 3,
-    Search = 255,
-}
+pub(super) type IterU8<'a> = std::slice::Iter<'a, u8>;
 
-impl TryInto<Filter> for u8 {
-    type Error = ParseError;
-
-    fn try_into(self) -> Result<Filter, Self::Error> {
-        let filter = match self {
-            0 => Filter::NameAscending,
-            1 => Filter::NameDescending,
-            2 => Filter::PlayerCountAscending,
-            3 => Filter::PlayerCountDescending,
-            255 => Filter::Search,
-            _ => Err(ParseError::InvalidFilter)?,
-        };
-
-        Ok(filter)
-    }
-}
+/// Reads a length prefixed string of at most `max_length` characters,
+/// `None` if the message has already ended.
+pub(super) type StringDecoder = fn(&mut IterU8, usize) -> Result<Option<String>, ParseError>;
 
 fn deserialise_string(
     message: &mut IterU8,
@@ -211,7 +46,7 @@ fn deserialise_string(
 }
 
 pub fn parse_message(message: &[u8], ip_address: IpAddress) -> Result<ParseOutput, ParseError> {
-    let m_type: u8 = *message.get(0).ok_or(ParseError::EmptyMessage)?;
+    let m_type: u8 = *message.first().ok_or(ParseError::EmptyMessage)?;
 
     let version: u8 = m_type & 0xF;
     if version != VERSION {
@@ -223,33 +58,53 @@ pub fn parse_message(message: &[u8], ip_address: IpAddress) -> Result<ParseOutpu
         return Err(ParseError::EmptyMessage);
     }
     let mut msg = message[1..].iter();
+    let strings: StringDecoder = deserialise_string;
 
     match typ {
         Types::None => Err(ParseError::InvalidType),
-        Types::Create => {
-            parse_create_lobby(&mut msg, ip_address).map(|lobby| ParseOutput::Create(lobby))
-        }
-        Types::Modify => {
-            parse_modify_lobby(&mut msg, ip_address).map(|lobby| ParseOutput::Modify(lobby))
-        }
-        Types::Destroy => {
-            parse_destroy_lobby(&mut msg, ip_address).map(|del_info| ParseOutput::Destroy(del_info))
-        }
-        Types::Get => parse_get(&mut msg).map(|get| ParseOutput::Get(get)),
+        // Version 0 hosts keep proving a lobby is theirs with its password,
+        // as they did before tokens.
+        Types::Create => parse_create_lobby(&mut msg, ip_address, strings).map(|lobby| {
+            ParseOutput::Create(lobby.map(|lobby| Lobby {
+                password_auth: true,
+                ..lobby
+            }))
+        }),
+        Types::Modify => parse_password_modify(&mut msg, ip_address).map(ParseOutput::Modify),
+        Types::Heartbeat => parse_host(&mut msg, ip_address).map(ParseOutput::Heartbeat),
+        Types::Destroy => parse_password_destroy(&mut msg, ip_address).map(ParseOutput::Destroy),
+        Types::Rotate => parse_host_and_token(&mut msg, ip_address).map(ParseOutput::Rotate),
+        Types::Lookup => parse_lookup(&mut msg, strings).map(ParseOutput::Lookup),
+        Types::Join => parse_join(&mut msg, strings).map(ParseOutput::Join),
+        Types::Get => parse_get(&mut msg).map(ParseOutput::Get),
+        Types::Session => Ok(ParseOutput::Session),
+        Types::Subscribe => parse_subscribe(&mut msg, strings).map(ParseOutput::Subscribe),
     }
 }
 
-fn parse_create_lobby(
+pub(super) fn parse_create_lobby(
     message: &mut IterU8,
     ip_address: IpAddress,
+    deserialise_string: StringDecoder,
 ) -> Result<Option<Lobby>, ParseError> {
+    let (lobby, password) = parse_lobby(message, ip_address, deserialise_string)?;
+    Ok(lobby.with_password(password))
+}
+
+/// The lobby fields shared by Create and Modify, with the password as sent.
+/// The returned lobby has no password hash yet.
+fn parse_lobby(
+    message: &mut IterU8,
+    ip_address: IpAddress,
+    deserialise_string: StringDecoder,
+) -> Result<(Lobby, String), ParseError> {
     let flags: Flags = message
         .next()
         .ok_or(ParseError::MissingMessagePart)?
         .to_owned()
         .into();
 
-    let ip = IpAddress::from_message(message, flags.is_ipv6)?;
+    let ip = IpAddress::from_message(message, flags.is_ipv6())?;
     if ip != ip_address {
         return Err(ParseError::MismatchedIP);
     }
@@ -268,65 +123,157 @@ fn parse_create_lobby(
 
     let max_players: u8 = *message.next().ok_or(ParseError::MissingMessagePart)?;
     let lobby_name: String =
-        deserialise_string(message, MAX_LOBBY_NAME_SIZE)?.ok_or(ParseError::MissingMessagePart)?;
-    let lobby_password: String =
-        deserialise_string(message, MAX_LOBBY_PASS_SIZE)?.ok_or(ParseError::MissingMessagePart)?;
+        deserialise_string(message, max_name_length())?.ok_or(ParseError::MissingMessagePart)?;
+    let lobby_password: String = deserialise_string(message, max_password_length())?
+        .ok_or(ParseError::MissingMessagePart)?;
 
-    Ok(Lobby::new(
+    let lobby = Lobby {
         flags,
         region,
-        ip,
-        port,
+        host_ip: ip,
+        host_port: port,
         max_players,
         lobby_name,
-        lobby_password,
-    ))
+        password: String::new(),
+        current_players: 1,
+        last_seen: now(),
+        token_hash: NO_TOKEN,
+        password_auth: false,
+        join_code: None,
+        reservations: Vec::new(),
+    };
+    Ok((lobby, lobby_password))
 }
 
-fn parse_modify_lobby(
+/// A Modify from version 1 on: the lobby, its players and the host's token.
+pub(super) fn parse_modify_lobby(
     message: &mut IterU8,
     ip_address: IpAddress,
-) -> Result<Option<Lobby>, ParseError> {
-    if let Some(mut lobby) = parse_create_lobby(message, ip_address)? {
-        lobby.set_player_count(*message.next().ok_or(ParseError::MissingMessagePart)?);
-        Ok(Some(lobby))
-    } else {
-        Ok(None)
-    }
+    deserialise_string: StringDecoder,
+) -> Result<(Option<Lobby>, HostAuth), ParseError> {
+    let (mut lobby, password) = parse_lobby(message, ip_address, deserialise_string)?;
+    lobby.set_player_count(*message.next().ok_or(ParseError::MissingMessagePart)?);
+    let token = parse_token(message)?;
+
+    Ok((lobby.with_password(password), HostAuth::Token(token)))
+}
+
+/// A version 0 Modify has no token. The password authenticates the host, it
+/// is checked against the stored hash rather than replacing it.
+fn parse_password_modify(
+    message: &mut IterU8,
+    ip_address: IpAddress,
+) -> Result<(Option<Lobby>, HostAuth), ParseError> {
+    let (mut lobby, password) = parse_lobby(message, ip_address, deserialise_string)?;
+    lobby.set_player_count(*message.next().ok_or(ParseError::MissingMessagePart)?);
+
+    Ok((Some(lobby), HostAuth::Password(password)))
+}
+
+/// A Destroy from version 1 on: the host and their token.
+pub(super) fn parse_destroy_lobby(
+    message: &mut IterU8,
+    ip_address: IpAddress,
+) -> Result<(IpAddress, u16, HostAuth), ParseError> {
+    let (ip, port, token) = parse_host_and_token(message, ip_address)?;
+    Ok((ip, port, HostAuth::Token(token)))
 }
 
-fn parse_destroy_lobby(
+/// A version 0 Destroy: the host and the lobby's password, which open lobbies
+/// leave out. A missing password counts as empty.
+fn parse_password_destroy(
     message: &mut IterU8,
     ip_address: IpAddress,
+) -> Result<(IpAddress, u16, HostAuth), ParseError> {
+    let (ip, port) = parse_host(message, ip_address)?;
+    let password = deserialise_string(message, max_password_length())?.unwrap_or_default();
+
+    Ok((ip, port, HostAuth::Password(password)))
+}
+
+/// The host followed by their token, as sent by Rotate and later Destroys.
+pub(super) fn parse_host_and_token(
+    message: &mut IterU8,
+    ip_address: IpAddress,
+) -> Result<(IpAddress, u16, HostToken), ParseError> {
+    let (ip, port) = parse_host(message, ip_address)?;
+    let token = parse_token(message)?;
+
+    Ok((ip, port, token))
+}
+
+/// The lobby to join, which is someone else's, and its password if any.
+pub(super) fn parse_join(
+    message: &mut IterU8,
+    deserialise_string: StringDecoder,
 ) -> Result<(IpAddress, u16, Option<String>), ParseError> {
-    let is_ipv6 = message.next().ok_or(ParseError::MissingMessagePart)? == &1;
-    let ip = IpAddress::from_message(message, is_ipv6)?;
+    let (ip, port) = parse_address(message)?;
+    let password = deserialise_string(message, max_password_length())?;
+
+    Ok((ip, port, password))
+}
+
+pub(super) fn parse_lookup(
+    message: &mut IterU8,
+    deserialise_string: StringDecoder,
+) -> Result<String, ParseError> {
+    deserialise_string(message, JOIN_CODE_LENGTH)?.ok_or(ParseError::MissingMessagePart)
+}
+
+fn parse_token(message: &mut IterU8) -> Result<HostToken, ParseError> {
+    let mut token = [0; TOKEN_SIZE];
+    for byte in token.iter_mut() {
+        *byte = *message.next().ok_or(ParseError::MissingMessagePart)?;
+    }
+    Ok(HostToken(token))
+}
+
+/// The IpV byte, address and port identifying a host's lobby.
+pub(super) fn parse_host(
+    message: &mut IterU8,
+    ip_address: IpAddress,
+) -> Result<(IpAddress, u16), ParseError> {
+    let (ip, port) = parse_address(message)?;
 
     if ip != ip_address {
         return Err(ParseError::MismatchedIP);
     }
 
+    Ok((ip, port))
+}
+
+/// An IpV byte, address and port, without checking who sent them.
+fn parse_address(message: &mut IterU8) -> Result<(IpAddress, u16), ParseError> {
+    let is_ipv6 = message.next().ok_or(ParseError::MissingMessagePart)? == &1;
+    let ip = IpAddress::from_message(message, is_ipv6)?;
+
     let port = {
         let high = *message.next().ok_or(ParseError::MissingMessagePart)? as u16;
         let low = *message.next().ok_or(ParseError::MissingMessagePart)? as u16;
         (high << 8) | low
     };
 
-    let password = deserialise_string(message, MAX_LOBBY_PASS_SIZE)?;
-
-    Ok((ip, port, password))
+    Ok((ip, port))
 }
 
-fn parse_get(message: &mut IterU8) -> Result<GetRequest, ParseError> {
+/// The search bit, filter and regions at the start of every Get.
+pub(super) fn parse_get_header(
+    message: &mut IterU8,
+) -> Result<(bool, Filter, Vec<Region>), ParseError> {
     let search_and_filter = *message.next().ok_or(ParseError::MissingMessagePart)?;
     let search = search_and_filter & 0x80 == 0x80;
     let filter: Filter = (search_and_filter & 0x7F).try_into()?;
 
     let regions = Region::get_regions(*message.next().ok_or(ParseError::MissingMessagePart)?);
-    let page_num = *message.next().ok_or(ParseError::MissingMessagePart)?;
+    Ok((search, filter, regions))
+}
+
+fn parse_get(message: &mut IterU8) -> Result<GetRequest, ParseError> {
+    let (search, filter, regions) = parse_get_header(message)?;
+    let page_num = *message.next().ok_or(ParseError::MissingMessagePart)? as u16;
 
     let search = if search {
-        deserialise_string(message, MAX_LOBBY_NAME_SIZE)?
+        deserialise_string(message, max_name_length())?
     } else {
         None
     };
@@ -336,5 +283,33 @@ fn parse_get(message: &mut IterU8) -> Result<GetRequest, ParseError> {
         regions,
         page_num,
         search,
+        page_size: None,
+        cursor: None,
+        predicates: Predicates::default(),
+    })
+}
+
+/// A Get without the page number, the filter orders the lobbies sent when
+/// the subscription starts.
+pub(super) fn parse_subscribe(
+    message: &mut IterU8,
+    strings: StringDecoder,
+) -> Result<GetRequest, ParseError> {
+    let (search, filter, regions) = parse_get_header(message)?;
+
+    let search = if search {
+        strings(message, max_name_length())?
+    } else {
+        None
+    };
+
+    Ok(GetRequest {
+        filter,
+        regions,
+        page_num: 0,
+        search,
+        page_size: None,
+        cursor: None,
+        predicates: Predicates::default(),
     })
 }
@@ -0,0 +1,126 @@
+//! Version 1 lays its messages out like version 0, except that strings are
+//! UTF-8 (the length prefix counts bytes, the limits count characters) and
+//! Get pages are numbered with a `u16`.
+
+use super::{
+    version0::{
+        max_name_length, parse_create_lobby, parse_destroy_lobby, parse_get_header, parse_host,
+        parse_host_and_token, parse_join, parse_lookup, parse_modify_lobby, parse_subscribe,
+        IterU8, StringDecoder,
+    },
+    GetRequest, IpAddress, ParseError, ParseOutput, Predicates, Types,
+};
+use crate::{
+    database::{Lobby, Page},
+    Serialise,
+};
+
+pub(super) const VERSION: u8 = 1;
+
+pub(super) fn deserialise_string(
+    message: &mut IterU8,
+    max_length: usize,
+) -> Result<Option<String>, ParseError> {
+    let length = match message.next() {
+        Some(length) => *length as usize,
+        None => return Ok(None),
+    };
+
+    let rest = message.as_slice();
+    let bytes = rest.get(..length).ok_or(ParseError::MissingMessagePart)?;
+    *message = rest[length..].iter();
+
+    let string = std::str::from_utf8(bytes).map_err(|_| ParseError::InvalidName)?;
+    if string.chars().count() > max_length {
+        return Err(ParseError::InvalidName);
+    }
+
+    Ok(Some(string.to_owned()))
+}
+
+pub fn parse_message(message: &[u8], ip_address: IpAddress) -> Result<ParseOutput, ParseError> {
+    parse_with(message, ip_address, VERSION, parse_get)
+}
+
+/// Parses a message of `version`, laid out like version 1 but for its Get.
+pub(super) fn parse_with(
+    message: &[u8],
+    ip_address: IpAddress,
+    version: u8,
+    parse_get: fn(&mut IterU8) -> Result<GetRequest, ParseError>,
+) -> Result<ParseOutput, ParseError> {
+    let m_type: u8 = *message.first().ok_or(ParseError::EmptyMessage)?;
+
+    if m_type & 0xF != version {
+        return Err(ParseError::OutOfDate);
+    }
+
+    let typ: Types = (m_type >> 4).into();
+    if message.len() < 2 {
+        return Err(ParseError::EmptyMessage);
+    }
+    let mut msg = message[1..].iter();
+    let strings: StringDecoder = deserialise_string;
+
+    match typ {
+        Types::None => Err(ParseError::InvalidType),
+        Types::Create => parse_create_lobby(&mut msg, ip_address, strings).map(ParseOutput::Create),
+        Types::Modify => parse_modify_lobby(&mut msg, ip_address, strings).map(ParseOutput::Modify),
+        Types::Heartbeat => parse_host(&mut msg, ip_address).map(ParseOutput::Heartbeat),
+        Types::Destroy => parse_destroy_lobby(&mut msg, ip_address).map(ParseOutput::Destroy),
+        Types::Rotate => parse_host_and_token(&mut msg, ip_address).map(ParseOutput::Rotate),
+        Types::Lookup => parse_lookup(&mut msg, strings).map(ParseOutput::Lookup),
+        Types::Join => parse_join(&mut msg, strings).map(ParseOutput::Join),
+        Types::Get => parse_get(&mut msg).map(ParseOutput::Get),
+        Types::Session => Ok(ParseOutput::Session),
+        Types::Subscribe => parse_subscribe(&mut msg, strings).map(ParseOutput::Subscribe),
+    }
+}
+
+fn parse_get(message: &mut IterU8) -> Result<GetRequest, ParseError> {
+    let (search, filter, regions) = parse_get_header(message)?;
+
+    let page_num = {
+        let high = *message.next().ok_or(ParseError::MissingMessagePart)? as u16;
+        let low = *message.next().ok_or(ParseError::MissingMessagePart)? as u16;
+        (high << 8) | low
+    };
+
+    let search = if search {
+        deserialise_string(message, max_name_length())?
+    } else {
+        None
+    };
+
+    Ok(GetRequest {
+        filter,
+        regions,
+        page_num,
+        search,
+        page_size: None,
+        cursor: None,
+        predicates: Predicates::default(),
+    })
+}
+
+/// `[u16 lobby count]`, then each lobby as `[u16 length][lobby]`, then
+/// `[u16 page number][u16 total pages]`.
+pub fn serialise_page(page: Page) -> Vec<u8> {
+    let total_pages = page.total_pages().min(u16::MAX as usize) as u16;
+
+    let mut output = serialise_lobbies(&page.lobbies);
+    output.extend(page.page_number.serialise());
+    output.extend(total_pages.serialise());
+    output
+}
+
+/// `[u16 lobby count]`, then each lobby as `[u16 length][lobby]`.
+pub(super) fn serialise_lobbies(lobbies: &[Lobby]) -> Vec<u8> {
+    let mut output = (lobbies.len() as u16).serialise();
+    for lobby in lobbies {
+        let fields = lobby.serialise_fields();
+        output.extend((fields.len() as u16).serialise());
+        output.extend(fields);
+    }
+    output
+}
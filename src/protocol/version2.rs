@@ -0,0 +1,86 @@
+//! Version 2 lays its messages out like version 1, except for Get: it asks
+//! for a page size, can leave lobbies out with predicates and pages with a
+//! cursor instead of a page number, and its response counts the matching
+//! lobbies with a `u32` and ends with the cursor of the next page.
+
+use super::{
+    version0::max_name_length,
+    version0::{parse_get_header, IterU8},
+    version1::{self, deserialise_string, serialise_lobbies},
+    Cursor, GetRequest, IpAddress, ParseError, ParseOutput, Predicates,
+};
+use crate::{database::Page, Serialise};
+
+pub(super) const VERSION: u8 = 2;
+
+pub fn parse_message(message: &[u8], ip_address: IpAddress) -> Result<ParseOutput, ParseError> {
+    version1::parse_with(message, ip_address, VERSION, parse_get)
+}
+
+/// `[u8 page size]`, 0 for the server's default, then the three bytes of
+/// `Predicates`, then `[u16 cursor length][cursor]`, empty for the first page,
+/// where a version 1 Get has its page number.
+fn parse_get(message: &mut IterU8) -> Result<GetRequest, ParseError> {
+    let (search, filter, regions) = parse_get_header(message)?;
+
+    let page_size = match *message.next().ok_or(ParseError::MissingMessagePart)? {
+        0 => None,
+        size => Some(size),
+    };
+
+    let mut predicates = [0; 3];
+    for byte in &mut predicates {
+        *byte = *message.next().ok_or(ParseError::MissingMessagePart)?;
+    }
+    let predicates = Predicates::try_from(predicates)?;
+
+    let cursor_length = {
+        let high = *message.next().ok_or(ParseError::MissingMessagePart)? as usize;
+        let low = *message.next().ok_or(ParseError::MissingMessagePart)? as usize;
+        (high << 8) | low
+    };
+    let rest = message.as_slice();
+    let cursor = rest
+        .get(..cursor_length)
+        .ok_or(ParseError::MissingMessagePart)?;
+    *message = rest[cursor_length..].iter();
+    let cursor = match cursor {
+        [] => None,
+        // A cursor only makes sense in the order it was made for.
+        cursor => match Cursor::try_from(cursor)? {
+            cursor if cursor.filter == filter => Some(cursor),
+            _ => return Err(ParseError::InvalidCursor),
+        },
+    };
+
+    let search = if search {
+        deserialise_string(message, max_name_length())?
+    } else {
+        None
+    };
+
+    Ok(GetRequest {
+        filter,
+        regions,
+        page_num: 0,
+        search,
+        page_size,
+        cursor,
+        predicates,
+    })
+}
+
+/// `[u32 total lobbies][u16 lobby count]`, then each lobby as
+/// `[u16 length][lobby]`, then `[u16 cursor length][cursor]`, empty on the
+/// last page.
+pub fn serialise_page(page: Page) -> Vec<u8> {
+    let total_lobbies = page.total_lobbies.min(u32::MAX as usize) as u32;
+
+    let mut output = total_lobbies.serialise();
+    output.extend(serialise_lobbies(&page.lobbies));
+    let cursor = page.next.as_ref().map(Serialise::serialise);
+    let cursor = cursor.unwrap_or_default();
+    output.extend((cursor.len() as u16).serialise());
+    output.extend(cursor);
+    output
+}
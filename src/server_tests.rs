@@ -0,0 +1,642 @@
+use super::*;
+use crate::{
+    config::{Rate, RateLimits},
+    database::HostToken,
+    protocol::{IpAddress, Predicates, Region},
+};
+use std::{sync::Barrier, thread};
+
+const CLIENTS: u16 = 200;
+pub(crate) const FIRST_PORT: u16 = 20000;
+
+fn start_server() -> (SocketAddr, Arc<dyn LobbyStore>) {
+    start_limited_server(unlimited())
+}
+
+fn start_limited_server(rate_limits: RateLimits) -> (SocketAddr, Arc<dyn LobbyStore>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let hub = Arc::new(Hub::new());
+    let database: Arc<dyn LobbyStore> = Arc::new(Notifying::new(
+        Arc::new(database::InMemory::new()),
+        Arc::clone(&hub),
+    ));
+
+    let server_database = Arc::clone(&database);
+    let limiter = Arc::new(Limiter::new(rate_limits));
+    let workers = config::get().worker_threads;
+    let listeners = Listeners {
+        tcp: vec![listener],
+        udp: Udp::bind(&[]).unwrap(),
+        http: None,
+        websocket: None,
+    };
+    thread::spawn(move || serve(listeners, server_database, hub, limiter, workers));
+
+    (address, database)
+}
+
+/// So that tests sending many messages from 127.0.0.1 are not limited.
+pub(crate) fn unlimited() -> RateLimits {
+    let rate = Rate {
+        per_second: f64::MAX,
+        burst: u32::MAX,
+    };
+    RateLimits {
+        max_connections: usize::MAX,
+        max_lobbies_per_ip: usize::MAX,
+        default: rate,
+        types: protocol::MESSAGE_TYPES
+            .iter()
+            .map(|message_type| (message_type.to_string(), rate))
+            .collect(),
+    }
+}
+
+pub(crate) fn lobby_message(typ: u8, port: u16, lobby_name: &str) -> Vec<u8> {
+    let mut message = vec![typ << 4, 0b110]; // Public | Has password
+    message.extend([127, 0, 0, 1]);
+    message.extend(port.serialise());
+    message.push(4); // Europe
+    message.push(10);
+    message.extend(String::from(lobby_name).serialise());
+    message.extend(String::from("hunter2").serialise());
+    message
+}
+
+/// `message` as version 1, where hosts send their token.
+fn v1(mut message: Vec<u8>) -> Vec<u8> {
+    message[0] |= 1;
+    message
+}
+
+/// A version 1 Destroy or Rotate for the lobby at `FIRST_PORT`.
+fn host_message(typ: u8, token: [u8; 16]) -> Vec<u8> {
+    let mut message = vec![(typ << 4) | 1, 0]; // IpV4
+    message.extend([127, 0, 0, 1]);
+    message.extend(FIRST_PORT.serialise());
+    message.extend(token);
+    message
+}
+
+/// The token at the start of a Create or Rotate response body.
+pub(crate) fn host_token(body: &[u8]) -> [u8; 16] {
+    body[2..18].try_into().unwrap()
+}
+
+pub(crate) fn get_message(page_num: u8) -> Vec<u8> {
+    vec![0x8 << 4, 0, 0, page_num]
+}
+
+pub(crate) fn request(address: SocketAddr, message: &[u8]) -> (u8, Vec<u8>) {
+    let mut stream = TcpStream::connect(address).unwrap();
+    let mut framed = vec![message.len() as u8];
+    framed.extend(message);
+    stream.write_all(&framed).unwrap();
+
+    let mut response = [0];
+    stream.read_exact(&mut response).unwrap();
+    let mut body = Vec::new();
+    stream.read_to_end(&mut body).unwrap();
+    (response[0], body)
+}
+
+#[test]
+fn concurrent_clients() {
+    let (address, database) = start_server();
+    let barrier = Arc::new(Barrier::new(CLIENTS as usize));
+
+    let clients: Vec<_> = (0..CLIENTS)
+        .map(|i| {
+            let barrier = Arc::clone(&barrier);
+            thread::spawn(move || {
+                let port = FIRST_PORT + i;
+                let lobby_name = format!("Lobby {i}");
+                barrier.wait();
+
+                let (code, _) = request(address, &lobby_message(0x1, port, &lobby_name));
+                assert_eq!(code, 10, "create {i}");
+
+                let mut modify = lobby_message(0x2, port, &lobby_name);
+                modify.push((i % 10) as u8 + 1);
+                let (code, _) = request(address, &modify);
+                assert_eq!(code, 10, "modify {i}");
+
+                let (code, body) = request(address, &get_message(0));
+                assert_eq!(code, 10, "get {i}");
+                assert!(!body.is_empty());
+            })
+        })
+        .collect();
+
+    for client in clients {
+        client.join().unwrap();
+    }
+
+    let mut lobbies = database.list().unwrap();
+    assert_eq!(lobbies.len(), CLIENTS as usize);
+
+    lobbies.sort_by_key(|lobby| lobby.host_port);
+    for (i, lobby) in (0..CLIENTS).zip(lobbies) {
+        assert_eq!(lobby.host_ip, IpAddress::IpV4([127, 0, 0, 1]));
+        assert_eq!(lobby.host_port, FIRST_PORT + i);
+        assert_eq!(lobby.lobby_name, format!("Lobby {i}"));
+        assert_eq!(lobby.current_players, (i % 10) as u8 + 1);
+    }
+}
+
+#[test]
+fn host_token_guards_lobby() {
+    let (address, database) = start_server();
+
+    let (code, body) = request(address, &v1(lobby_message(0x1, FIRST_PORT, "Host's Lobby")));
+    assert_eq!(code, 10);
+    let token = host_token(&body);
+
+    // Someone else behind the same NAT, without the token.
+    let mut takeover = v1(lobby_message(0x2, FIRST_PORT, "Mine Now"));
+    takeover.push(1);
+    takeover.extend([0; 16]);
+    assert_eq!(request(address, &takeover).0, 55);
+    assert_eq!(request(address, &host_message(0x4, [0; 16])).0, 55);
+
+    // Or with the password, over version 0.
+    let mut takeover = lobby_message(0x2, FIRST_PORT, "Mine Now");
+    takeover.push(1);
+    assert_eq!(request(address, &takeover).0, 55);
+    assert_eq!(database.list().unwrap()[0].lobby_name, "Host's Lobby");
+
+    let (code, body) = request(address, &host_message(0x5, token));
+    assert_eq!(code, 10);
+    let rotated = host_token(&body);
+    assert_ne!(rotated, token);
+
+    let mut modify = v1(lobby_message(0x2, FIRST_PORT, "Renamed"));
+    modify.push(4);
+    let mut stale = modify.clone();
+    stale.extend(token);
+    assert_eq!(request(address, &stale).0, 55);
+    modify.extend(rotated);
+    assert_eq!(request(address, &modify).0, 10);
+    assert_eq!(database.list().unwrap()[0].lobby_name, "Renamed");
+
+    assert_eq!(request(address, &host_message(0x4, rotated)).0, 10);
+    assert!(database.list().unwrap().is_empty());
+}
+
+#[test]
+fn version_0_host_uses_password() {
+    let (address, database) = start_server();
+
+    let (code, _) = request(address, &lobby_message(0x1, FIRST_PORT, "Old Client"));
+    assert_eq!(code, 10);
+
+    let mut modify = lobby_message(0x2, FIRST_PORT, "Renamed");
+    modify.push(3);
+    assert_eq!(request(address, &modify).0, 10);
+    assert_eq!(database.list().unwrap()[0].lobby_name, "Renamed");
+
+    let mut destroy = vec![0x4 << 4, 0]; // IpV4
+    destroy.extend([127, 0, 0, 1]);
+    destroy.extend(FIRST_PORT.serialise());
+    let mut wrong = destroy.clone();
+    wrong.extend(String::from("hunter3").serialise());
+    assert_eq!(request(address, &wrong).0, 55);
+    destroy.extend(String::from("hunter2").serialise());
+    assert_eq!(request(address, &destroy).0, 10);
+    assert!(database.list().unwrap().is_empty());
+
+    // The token is sent all the same, for when the host moves to version 1.
+    let (code, body) = request(address, &lobby_message(0x1, FIRST_PORT, "Old Client"));
+    assert_eq!(code, 10);
+    assert_eq!(
+        request(address, &host_message(0x4, host_token(&body))).0,
+        10
+    );
+    assert!(database.list().unwrap().is_empty());
+}
+
+#[test]
+fn private_lobby_lookup() {
+    let (address, _) = start_server();
+
+    let mut create = lobby_message(0x1, FIRST_PORT, "Friends Only");
+    create[1] = 0b100; // Private | Has password
+    let (code, body) = request(address, &create);
+    assert_eq!(code, 10);
+    assert_eq!(body[..2], [0, 16 + 1 + 6]);
+    let join_code = String::from_utf8(body[19..].to_vec()).unwrap();
+
+    let (code, body) = request(address, &get_message(0));
+    assert_eq!(code, 10);
+    assert_eq!(body, [0, 4, 0, 0, 0, 0]); // No lobbies
+
+    let mut lookup = vec![0x6 << 4];
+    lookup.extend(join_code.to_lowercase().serialise());
+    let (code, body) = request(address, &lookup);
+    assert_eq!(code, 10);
+    let mut expected = vec![0, 7, 0b100, 127, 0, 0, 1];
+    expected.extend(FIRST_PORT.serialise());
+    assert_eq!(body, expected);
+
+    let mut lookup = vec![0x6 << 4];
+    lookup.extend(String::from("ZZZZZZ").serialise());
+    assert_eq!(request(address, &lookup).0, 52);
+}
+
+#[test]
+fn join_reserves_slot() {
+    let (address, database) = start_server();
+
+    let mut create = lobby_message(0x1, FIRST_PORT, "Two Player");
+    create[9] = 2; // Max players
+    let (code, body) = request(address, &create);
+    assert_eq!(code, 10);
+    let token = HostToken(host_token(&body));
+
+    let mut join = vec![0x7 << 4, 0]; // IpV4
+    join.extend([127, 0, 0, 1]);
+    join.extend(FIRST_PORT.serialise());
+    let mut wrong_password = join.clone();
+    wrong_password.extend(String::from("hunter3").serialise());
+    join.extend(String::from("hunter2").serialise());
+
+    assert_eq!(request(address, &wrong_password).0, 55);
+
+    let (code, body) = request(address, &join);
+    assert_eq!(code, 10);
+    let player = IpAddress::IpV4([127, 0, 0, 1]);
+    let ticket = JoinTicket::verify(&body[2..], &token, player).unwrap();
+    assert!(ticket.expires > database::now());
+    assert_eq!(database.list().unwrap()[0].reservations, [ticket.expires]);
+
+    assert_eq!(request(address, &join).0, 60);
+}
+
+#[test]
+fn slow_client_does_not_block_others() {
+    let (address, _) = start_server();
+
+    // Connects but never sends anything.
+    let _slow_client = TcpStream::connect(address).unwrap();
+
+    let start = Instant::now();
+    let (code, _) = request(address, &lobby_message(0x1, FIRST_PORT, "Quick"));
+    assert_eq!(code, 10);
+    assert!(start.elapsed() < Duration::from_secs(config::get().timeouts.receive));
+}
+
+#[test]
+fn v1_long_frame() {
+    let (address, database) = start_server();
+    let lobby_name = "Ünïcödé Lobby";
+
+    let mut create = lobby_message(0x1, FIRST_PORT, "placeholder");
+    create[0] |= 1; // V1
+    create.truncate(10);
+    create.push(lobby_name.len() as u8);
+    create.extend(lobby_name.as_bytes());
+    create.extend(String::from("hunter2").serialise());
+    assert_eq!(request(address, &create).0, 10);
+    assert_eq!(database.list().unwrap()[0].lobby_name, lobby_name);
+
+    // A zero length byte, then the u16 length.
+    let get = [(0x8 << 4) | 1, 0, 0, 0, 0];
+    let mut stream = TcpStream::connect(address).unwrap();
+    let mut framed = vec![0];
+    framed.extend((get.len() as u16).serialise());
+    framed.extend(get);
+    stream.write_all(&framed).unwrap();
+
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).unwrap();
+    assert_eq!(response[0], 10);
+
+    let body = &response[3..];
+    assert_eq!(&body[..2], [0, 1]); // One lobby
+    let lobby_length = u16::from_be_bytes([body[2], body[3]]) as usize;
+    let lobby = &body[4..4 + lobby_length];
+    let name_start = 1 + 1 + 4 + 2 + 1;
+    assert_eq!(lobby[name_start] as usize, lobby_name.len());
+    assert_eq!(
+        &lobby[name_start + 1..name_start + 1 + lobby_name.len()],
+        lobby_name.as_bytes()
+    );
+    assert_eq!(&body[4 + lobby_length..], [0, 0, 0, 1]); // Page 0 of 1
+}
+
+#[test]
+fn serves_every_listener() {
+    let listeners: Vec<_> = (0..2)
+        .map(|_| TcpListener::bind("127.0.0.1:0").unwrap())
+        .collect();
+    let addresses: Vec<_> = listeners
+        .iter()
+        .map(|listener| listener.local_addr().unwrap())
+        .collect();
+    let database: Arc<dyn LobbyStore> = Arc::new(database::InMemory::new());
+    let limiter = Arc::new(Limiter::new(unlimited()));
+    let listeners = Listeners {
+        tcp: listeners,
+        udp: Udp::bind(&[]).unwrap(),
+        http: None,
+        websocket: None,
+    };
+    thread::spawn(move || serve(listeners, database, Arc::new(Hub::new()), limiter, 2));
+
+    let create = lobby_message(0x1, FIRST_PORT, "Twice");
+    assert_eq!(request(addresses[0], &create).0, 10);
+    // Both listeners share the one store.
+    assert_eq!(
+        request(addresses[1], &create).0,
+        DatabaseError::LobbyAlreadyExists as u8
+    );
+}
+
+#[test]
+fn rate_limited_create() {
+    let mut rate_limits = unlimited();
+    rate_limits.types.insert(
+        String::from("create"),
+        Rate {
+            per_second: 0.001,
+            burst: 2,
+        },
+    );
+    let (address, database) = start_limited_server(rate_limits);
+
+    for port in [FIRST_PORT, FIRST_PORT + 1] {
+        assert_eq!(request(address, &lobby_message(0x1, port, "Allowed")).0, 10);
+    }
+    let refused = request(address, &lobby_message(0x1, FIRST_PORT + 2, "Refused"));
+    assert_eq!(refused.0, Refusal::RateLimited as u8);
+    assert_eq!(database.list().unwrap().len(), 2);
+
+    // Other types have their own bucket.
+    let get = [0x8 << 4, 0, 0, 0];
+    assert_eq!(request(address, &get).0, 10);
+}
+
+#[test]
+fn too_many_connections() {
+    let mut rate_limits = unlimited();
+    rate_limits.max_connections = 1;
+    let (address, _) = start_limited_server(rate_limits);
+
+    // Holds the only connection until it times out.
+    let _slow_client = TcpStream::connect(address).unwrap();
+
+    let mut refused = TcpStream::connect(address).unwrap();
+    let mut response = Vec::new();
+    refused.read_to_end(&mut response).unwrap();
+    assert_eq!(response, [Refusal::TooManyConnections as u8]);
+}
+
+#[test]
+fn protocol_client() {
+    use omicron_protocol::{Client, Error, LobbySettings};
+
+    let (address, database) = start_server();
+    let mut lobby = LobbySettings {
+        host_ip: IpAddress::IpV4([127, 0, 0, 1]),
+        host_port: FIRST_PORT,
+        region: Region::Asia,
+        max_players: 6,
+        name: String::from("Client lobby"),
+        public: true,
+        password: String::from("secret"),
+    };
+    let all = GetRequest {
+        filter: Filter::NameAscending,
+        regions: Region::get_regions(0),
+        page_num: 0,
+        search: None,
+        page_size: None,
+        cursor: None,
+        predicates: Predicates::default(),
+    };
+
+    for version in [0, 1, 2] {
+        let client = Client::new(address).with_version(version);
+        let created = client.create(&lobby).unwrap();
+        assert_eq!(created.join_code.len(), database::JOIN_CODE_LENGTH);
+        assert!(matches!(
+            client.create(&lobby),
+            Err(Error::Database(DatabaseError::LobbyAlreadyExists))
+        ));
+
+        lobby.name = format!("Version {version}");
+        client.modify(&lobby, 3, &created.token).unwrap();
+        let page = client.get(&all).unwrap();
+        assert_eq!(page.lobbies.len(), 1);
+        let listing = &page.lobbies[0];
+        assert_eq!(listing.name, lobby.name);
+        assert_eq!((listing.current_players, listing.max_players), (3, 6));
+        assert_eq!(listing.region, Region::Asia);
+        assert!(listing.flags.has_password());
+
+        assert!(matches!(
+            client.destroy(lobby.host_ip, FIRST_PORT, &[0; 16]),
+            Err(Error::Database(DatabaseError::InvalidCredentials))
+        ));
+        client
+            .destroy(lobby.host_ip, FIRST_PORT, &created.token)
+            .unwrap();
+        assert!(database.list().unwrap().is_empty());
+    }
+
+    lobby.host_ip = IpAddress::IpV4([10, 0, 0, 1]);
+    assert!(matches!(
+        Client::new(address).create(&lobby),
+        Err(Error::Parse(ParseError::MismatchedIP))
+    ));
+}
+
+/// Connects and opens a session.
+fn open_session(address: SocketAddr) -> TcpStream {
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream.write_all(&[2, 0x9 << 4, 0]).unwrap();
+    let mut response = [0];
+    stream.read_exact(&mut response).unwrap();
+    assert_eq!(response[0], 10);
+    stream
+}
+
+/// Sends one request in a session frame and reads its response frame.
+fn session_request(stream: &mut TcpStream, id: u32, message: &[u8]) -> (u32, u8, Vec<u8>) {
+    let mut framed = id.to_be_bytes().to_vec();
+    framed.extend((message.len() as u16).serialise());
+    framed.extend(message);
+    stream.write_all(&framed).unwrap();
+
+    let mut header = [0; 7];
+    stream.read_exact(&mut header).unwrap();
+    let mut body = vec![0; u16::from_be_bytes([header[5], header[6]]) as usize];
+    stream.read_exact(&mut body).unwrap();
+    (
+        u32::from_be_bytes(header[..4].try_into().unwrap()),
+        header[4],
+        body,
+    )
+}
+
+#[test]
+fn session_carries_many_requests() {
+    let (address, database) = start_server();
+    let mut session = open_session(address);
+
+    let (id, code, body) = session_request(
+        &mut session,
+        7,
+        &lobby_message(0x1, FIRST_PORT, "Session lobby"),
+    );
+    assert_eq!((id, code), (7, 10));
+    // Session bodies have no length in front of them.
+    let token: [u8; 16] = body[..16].try_into().unwrap();
+
+    let (id, code, body) = session_request(&mut session, 8, &get_message(0));
+    assert_eq!((id, code), (8, 10));
+    assert!(!body.is_empty());
+
+    let (id, code, body) = session_request(&mut session, 9, &host_message(0x4, token));
+    assert_eq!((id, code, body), (9, 10, Vec::new()));
+    assert!(database.list().unwrap().is_empty());
+
+    // Bad messages get their code without ending the session.
+    assert_eq!(session_request(&mut session, 10, &[]).1, 40);
+    assert_eq!(session_request(&mut session, 11, &[0x9 << 4, 0]).1, 41);
+    assert_eq!(session_request(&mut session, 12, &get_message(0)).1, 10);
+
+    // Single-shot clients are unaffected.
+    assert_eq!(request(address, &get_message(0)).0, 10);
+}
+
+#[test]
+fn session_holds_its_connection() {
+    let mut rate_limits = unlimited();
+    rate_limits.max_connections = 1;
+    let (address, _) = start_limited_server(rate_limits);
+
+    let mut session = open_session(address);
+    assert_eq!(session_request(&mut session, 1, &get_message(0)).1, 10);
+
+    let mut refused = TcpStream::connect(address).unwrap();
+    let mut response = Vec::new();
+    refused.read_to_end(&mut response).unwrap();
+    assert_eq!(response, [Refusal::TooManyConnections as u8]);
+
+    drop(session);
+    thread::sleep(Duration::from_millis(200));
+    assert_eq!(request(address, &get_message(0)).0, 10);
+}
+
+/// Reads one pushed change, `[kind][u16 length][body]`.
+fn read_push(stream: &mut TcpStream) -> (u8, Vec<u8>) {
+    let mut header = [0; 3];
+    stream.read_exact(&mut header).unwrap();
+    let mut body = vec![0; u16::from_be_bytes([header[1], header[2]]) as usize];
+    stream.read_exact(&mut body).unwrap();
+    (header[0], body)
+}
+
+/// The lobby name in a pushed add or update.
+fn pushed_name(body: &[u8]) -> &str {
+    // Flags, region, ipv4 host, port and max players come first.
+    let length = body[9] as usize;
+    std::str::from_utf8(&body[10..10 + length]).unwrap()
+}
+
+#[test]
+fn subscription_pushes_changes() {
+    let (address, _) = start_server();
+    let (_, body) = request(address, &lobby_message(0x1, FIRST_PORT, "Already there"));
+    let first_token = host_token(&body);
+
+    let mut subscription = TcpStream::connect(address).unwrap();
+    subscription
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    subscription.write_all(&[3, 0xA << 4, 0, 0]).unwrap(); // Every region
+    let mut response = [0];
+    subscription.read_exact(&mut response).unwrap();
+    assert_eq!(response[0], 10);
+
+    let (kind, body) = read_push(&mut subscription);
+    assert_eq!((kind, pushed_name(&body)), (1, "Already there"));
+
+    let (_, body) = request(address, &lobby_message(0x1, FIRST_PORT + 1, "New"));
+    let second_token = host_token(&body);
+    let (kind, body) = read_push(&mut subscription);
+    assert_eq!((kind, pushed_name(&body)), (1, "New"));
+
+    let mut renamed = lobby_message(0x2, FIRST_PORT + 1, "Renamed");
+    renamed.push(3);
+    renamed.extend(second_token);
+    assert_eq!(request(address, &renamed).0, 10);
+    let (kind, body) = read_push(&mut subscription);
+    assert_eq!((kind, pushed_name(&body)), (2, "Renamed"));
+
+    // Going private takes it off the list.
+    let mut private = lobby_message(0x2, FIRST_PORT + 1, "Renamed");
+    private[1] = 0b100; // Has password
+    private.push(3);
+    private.extend(second_token);
+    assert_eq!(request(address, &private).0, 10);
+    let mut host = vec![0, 127, 0, 0, 1];
+    host.extend((FIRST_PORT + 1).serialise());
+    assert_eq!(read_push(&mut subscription), (3, host));
+
+    assert_eq!(request(address, &host_message(0x4, first_token)).0, 10);
+    let mut host = vec![0, 127, 0, 0, 1];
+    host.extend(FIRST_PORT.serialise());
+    assert_eq!(read_push(&mut subscription), (3, host));
+
+    // Sending anything ends it.
+    subscription.write_all(&[0]).unwrap();
+    let mut rest = Vec::new();
+    subscription.read_to_end(&mut rest).unwrap();
+    assert!(rest.is_empty());
+}
+
+#[test]
+fn subscription_filters_lobbies() {
+    let (address, _) = start_server();
+
+    // Asia only, with "match" in the name.
+    let mut subscribe = vec![0xA << 4, 0x80, 0b10];
+    subscribe.extend(String::from("MATCH").serialise());
+    let mut subscription = TcpStream::connect(address).unwrap();
+    subscription
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let mut framed = vec![subscribe.len() as u8];
+    framed.extend(&subscribe);
+    subscription.write_all(&framed).unwrap();
+    let mut response = [0];
+    subscription.read_exact(&mut response).unwrap();
+    assert_eq!(response[0], 10);
+
+    let mut asia = lobby_message(0x1, FIRST_PORT, "A match");
+    asia[8] = 2; // Asia
+    let mut europe = lobby_message(0x1, FIRST_PORT + 1, "A match");
+    europe[8] = 4; // Europe
+    let mut other_name = lobby_message(0x1, FIRST_PORT + 2, "Something else");
+    other_name[8] = 2;
+    let mut second = lobby_message(0x1, FIRST_PORT + 3, "Second match");
+    second[8] = 2;
+    for message in [asia, europe, other_name, second] {
+        assert_eq!(request(address, &message).0, 10);
+    }
+
+    let (kind, body) = read_push(&mut subscription);
+    assert_eq!((kind, pushed_name(&body)), (1, "A match"));
+    let (kind, body) = read_push(&mut subscription);
+    assert_eq!((kind, pushed_name(&body)), (1, "Second match"));
+}
+
+#[test]
+fn subscribe_in_session() {
+    let (address, _) = start_server();
+    let mut session = open_session(address);
+    assert_eq!(session_request(&mut session, 1, &[0xA << 4, 0, 0]).1, 41);
+}
@@ -0,0 +1,79 @@
+//! Keep-alive sessions, opened with a Session message. Each request then comes
+//! in a frame with a request id, which its response echoes, until the client
+//! closes the connection or stays idle for `timeouts.session_idle`.
+//!
+//! Request frame: `[u32 request id][u16 length][message]`
+//! Response frame: `[u32 request id][u8 code][u16 length][body]`
+
+use crate::{config, database::LobbyStore, limiter::Limiter, Serialise};
+use std::{
+    io::{self, Write},
+    net::{SocketAddr, TcpStream},
+    time::{Duration, Instant},
+};
+use tracing::{debug, warn};
+
+/// Answers session frames one at a time until the client is done.
+pub fn run(
+    mut stream: TcpStream,
+    client_address: SocketAddr,
+    database: &dyn LobbyStore,
+    limiter: &Limiter,
+) {
+    let timeouts = &config::get().timeouts;
+    loop {
+        // The client may wait as long as it likes between requests, but once
+        // one starts it has to arrive like any other message.
+        let mut id = [0; 4];
+        let idle_deadline = Instant::now() + Duration::from_secs(timeouts.session_idle);
+        match crate::read_before(&mut stream, &mut id[..1], idle_deadline) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                return debug!("Session closed by the client");
+            }
+            Err(err) => return debug!(%err, "Session idle, closing"),
+        }
+
+        let start = Instant::now();
+        let deadline = start + Duration::from_secs(timeouts.receive);
+        let (id, message) = match read_frame(&mut stream, id, deadline) {
+            Ok(frame) => frame,
+            Err((part, err)) => {
+                return debug!(%err, "Session interrupted while reading the {part}");
+            }
+        };
+
+        let response = crate::respond(&message, client_address, database, limiter, true);
+        let mut frame = id.to_be_bytes().to_vec();
+        frame.push(response.code);
+        frame.extend((response.body.len() as u16).serialise());
+        frame.extend(response.body);
+        if let Err(err) = stream.write_all(&frame) {
+            warn!(%err, "Failed to write session response");
+            return;
+        }
+        crate::log_request(
+            Some(id),
+            response.message_type,
+            Some(response.code),
+            start.elapsed(),
+        );
+    }
+}
+
+/// Reads the rest of a frame whose first byte is already in `id`.
+fn read_frame(
+    stream: &mut TcpStream,
+    mut id: [u8; 4],
+    deadline: Instant,
+) -> Result<(u32, Vec<u8>), (&'static str, io::Error)> {
+    crate::read_before(stream, &mut id[1..], deadline).map_err(|err| ("request id", err))?;
+
+    let mut length = [0; 2];
+    crate::read_before(stream, &mut length, deadline).map_err(|err| ("message length", err))?;
+
+    let mut message = vec![0; u16::from_be_bytes(length) as usize];
+    crate::read_before(stream, &mut message, deadline).map_err(|err| ("message body", err))?;
+
+    Ok((u32::from_be_bytes(id), message))
+}
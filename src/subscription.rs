@@ -0,0 +1,163 @@
+//! Subscriptions, opened with a Subscribe message. The server sends every
+//! public lobby matching the request, then keeps the connection open and
+//! pushes each change to the matching lobbies as it happens. Sending
+//! anything, or closing the connection, ends the subscription.
+//!
+//! Every push is `[u8 kind][u16 length][body]`. Adds and updates carry the
+//! lobby as it is sent in a Get page, removes carry `[IpV][host ip][port]`.
+
+use crate::{
+    config,
+    database::{Change, LobbyStore},
+    protocol::{GetRequest, IpAddress},
+    Serialise,
+};
+use std::{
+    collections::HashSet,
+    io::{self, Read, Write},
+    net::TcpStream,
+    sync::mpsc::{Receiver, RecvTimeoutError},
+    time::Duration,
+};
+use tracing::{debug, trace};
+
+/// How often to check that a quiet subscriber is still connected.
+const LIVENESS_INTERVAL: Duration = Duration::from_secs(1);
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy)]
+enum Push {
+    /// A lobby the client has not been sent yet.
+    Add = 1,
+    /// A lobby the client has, with new fields.
+    Update = 2,
+    /// A lobby the client has that was removed or stopped matching.
+    Remove = 3,
+}
+
+/// Pushes the lobbies matching `request`, then every change to them from
+/// `changes`, until the client leaves. `changes` should be subscribed to
+/// before the lobbies are read, so that nothing is missed in between.
+pub fn run(
+    mut stream: TcpStream,
+    request: GetRequest,
+    changes: Receiver<Change>,
+    database: &dyn LobbyStore,
+) {
+    // A client that stops reading should not hold on to the thread forever.
+    let timeout = Duration::from_secs(config::get().timeouts.receive);
+    if let Err(err) = stream.set_write_timeout(Some(timeout)) {
+        return debug!(%err, "Failed to set the subscription's write timeout");
+    }
+
+    let mut sent = HashSet::new();
+    if let Err(err) = send_matching(&mut stream, &request, database, &mut sent) {
+        return debug!(%err, "Subscription ended while sending the lobbies");
+    }
+
+    loop {
+        let change = match changes.recv_timeout(LIVENESS_INTERVAL) {
+            Ok(change) => change,
+            Err(RecvTimeoutError::Timeout) if client_left(&mut stream) => {
+                return debug!("Subscription ended by the client");
+            }
+            Err(RecvTimeoutError::Timeout) => continue,
+            Err(RecvTimeoutError::Disconnected) => {
+                return debug!("Subscription fell behind, closing");
+            }
+        };
+
+        let result = match change {
+            Change::Put(lobby)
+                if request.matches(
+                    &lobby.flags,
+                    &lobby.region,
+                    &lobby.lobby_name,
+                    lobby.current_players,
+                    lobby.max_players,
+                ) =>
+            {
+                let push = match sent.insert((lobby.host_ip, lobby.host_port)) {
+                    true => Push::Add,
+                    false => Push::Update,
+                };
+                send(&mut stream, push, lobby.serialise_fields())
+            }
+            Change::Put(lobby) => remove(&mut stream, &mut sent, lobby.host_ip, lobby.host_port),
+            Change::Remove(host_ip, port) => remove(&mut stream, &mut sent, host_ip, port),
+        };
+        if let Err(err) = result {
+            return debug!(%err, "Subscription ended");
+        }
+    }
+}
+
+/// Sends every lobby matching `request` as an add, a page at a time.
+fn send_matching(
+    stream: &mut TcpStream,
+    request: &GetRequest,
+    database: &dyn LobbyStore,
+    sent: &mut HashSet<(IpAddress, u16)>,
+) -> io::Result<()> {
+    let mut request = GetRequest {
+        page_num: 0,
+        page_size: Some(u8::MAX),
+        ..request.clone()
+    };
+    loop {
+        let page = database
+            .get(request.clone())
+            .map_err(|err| io::Error::other(format!("{err:?}")))?;
+
+        for lobby in &page.lobbies {
+            // A lobby that changed while the pages are read can sort again
+            // after the cursor.
+            if sent.insert((lobby.host_ip, lobby.host_port)) {
+                send(stream, Push::Add, lobby.serialise_fields())?;
+            }
+        }
+        match page.next {
+            Some(cursor) => request.cursor = Some(cursor),
+            None => return Ok(()),
+        }
+    }
+}
+
+/// Tells the client a lobby is gone, if it was sent one.
+fn remove(
+    stream: &mut TcpStream,
+    sent: &mut HashSet<(IpAddress, u16)>,
+    host_ip: IpAddress,
+    port: u16,
+) -> io::Result<()> {
+    if !sent.remove(&(host_ip, port)) {
+        return Ok(());
+    }
+    let mut body = vec![matches!(host_ip, IpAddress::IpV6(_)) as u8];
+    body.extend(host_ip.serialise());
+    body.extend(port.serialise());
+    send(stream, Push::Remove, body)
+}
+
+fn send(stream: &mut TcpStream, push: Push, body: Vec<u8>) -> io::Result<()> {
+    trace!(?push, "Pushing a change");
+    let mut frame = vec![push as u8];
+    frame.extend((body.len() as u16).serialise());
+    frame.extend(body);
+    stream.write_all(&frame)
+}
+
+/// Whether the client closed the connection or sent something, either of
+/// which ends the subscription.
+fn client_left(stream: &mut TcpStream) -> bool {
+    if stream.set_nonblocking(true).is_err() {
+        return true;
+    }
+    // Reading what was sent lets the connection close cleanly, rather than
+    // being reset over unread data.
+    let left = !matches!(
+        stream.read(&mut [0; 64]),
+        Err(err) if err.kind() == io::ErrorKind::WouldBlock
+    );
+    stream.set_nonblocking(false).is_err() || left
+}
@@ -0,0 +1,211 @@
+//! Get and Modify over UDP, for clients that poll the lobby list or send
+//! player counts often enough that a TCP handshake per request adds up.
+//!
+//! Request: `[u32 request id][u64 cookie][message]`
+//! Response: `[u32 request id][u8 code][u8 index][u8 count][part of the body]`
+//!
+//! A source address is easy to fake over UDP, so every request needs a cookie
+//! that proves the client can receive at its address. Until it echoes one,
+//! all it is sent is the cookie, in a datagram no larger than its request,
+//! and it touches neither the store nor the limiter.
+
+use crate::{config, database::LobbyStore, limiter::Limiter, protocol, Response, Upgrade};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::{
+    io,
+    net::{IpAddr, SocketAddr, UdpSocket},
+    time::Instant,
+};
+use tracing::{debug, info_span};
+
+/// Sent with a cookie as the body, repeat the request with it.
+pub const COOKIE_REQUIRED: u8 = 64;
+
+const REQUEST_HEADER: usize = 12;
+pub const RESPONSE_HEADER: usize = 7;
+const COOKIE_SIZE: usize = 8;
+
+/// A cookie is good for the epoch it was issued in and the next one.
+const COOKIE_EPOCH: u64 = 60;
+
+/// The UDP listeners and the key their cookies are made with.
+pub struct Udp {
+    sockets: Vec<UdpSocket>,
+    cookies: Cookies,
+}
+
+impl Udp {
+    /// Binds every address, none turns UDP off.
+    pub fn bind(addresses: &[SocketAddr]) -> io::Result<Self> {
+        Ok(Self {
+            sockets: addresses
+                .iter()
+                .map(UdpSocket::bind)
+                .collect::<io::Result<_>>()?,
+            cookies: Cookies::new()?,
+        })
+    }
+
+    pub fn sockets(&self) -> &[UdpSocket] {
+        &self.sockets
+    }
+
+    /// Answers a datagram that arrived on `self.sockets()[socket]` without a
+    /// good cookie with a fresh one, returning whether it did. That is all an
+    /// unproven client gets: the address may be faked, so it must not cost a
+    /// real client's rate limit or hold a connection slot. Cheap enough to
+    /// run on the receiving thread.
+    pub fn challenge(&self, socket: usize, datagram: &[u8], client_address: SocketAddr) -> bool {
+        let Some((request_id, cookie, message)) = split_request(datagram) else {
+            // Too short to even say which request failed.
+            debug!(client = %client_address, bytes = datagram.len(), "Datagram too short");
+            return true;
+        };
+        if self.cookies.check(client_address.ip(), cookie) {
+            return false;
+        }
+
+        let start = Instant::now();
+        let max_datagram = config::get().udp.max_datagram;
+        let cookie = self.cookies.issue(client_address.ip(), epoch());
+        let response = split_response(request_id, COOKIE_REQUIRED, &cookie, max_datagram);
+        if response[0].len() > datagram.len() {
+            debug!(client = %client_address, "Request too short to be sent a cookie");
+        } else if let Err(err) = self.sockets[socket].send_to(&response[0], client_address) {
+            debug!(%err, client = %client_address, "Failed to send a datagram");
+        }
+        crate::log_request(
+            Some(request_id),
+            protocol::message_type(message),
+            Some(COOKIE_REQUIRED),
+            start.elapsed(),
+        );
+        true
+    }
+
+    /// Answers one datagram that arrived on `self.sockets()[socket]` and
+    /// passed `challenge`.
+    pub fn answer(
+        &self,
+        socket: usize,
+        datagram: &[u8],
+        client_address: SocketAddr,
+        database: &dyn LobbyStore,
+        limiter: &Limiter,
+    ) {
+        let span = info_span!("datagram", client = %client_address);
+        let _entered = span.enter();
+        let start = Instant::now();
+
+        let Some((request_id, _, message)) = split_request(datagram) else {
+            return debug!(bytes = datagram.len(), "Datagram too short");
+        };
+        let message_type = protocol::message_type(message);
+
+        let response = match message_type {
+            Some("get" | "modify") => {
+                crate::respond(message, client_address, database, limiter, true)
+            }
+            _ => {
+                debug!(?message_type, "Not answered over UDP");
+                Response {
+                    message_type,
+                    code: protocol::ParseError::InvalidType as u8,
+                    body: Vec::new(),
+                    upgrade: Upgrade::None,
+                }
+            }
+        };
+
+        let max_datagram = config::get().udp.max_datagram;
+        let datagrams = split_response(request_id, response.code, &response.body, max_datagram);
+        for datagram in &datagrams {
+            if let Err(err) = self.sockets[socket].send_to(datagram, client_address) {
+                debug!(%err, "Failed to send a datagram");
+                break;
+            }
+        }
+        crate::log_request(
+            Some(request_id),
+            message_type,
+            Some(response.code),
+            start.elapsed(),
+        );
+    }
+}
+
+/// The request id, cookie and message, `None` if it is too short for them.
+fn split_request(datagram: &[u8]) -> Option<(u32, [u8; COOKIE_SIZE], &[u8])> {
+    if datagram.len() < REQUEST_HEADER {
+        return None;
+    }
+    let request_id = u32::from_be_bytes(datagram[..4].try_into().ok()?);
+    let cookie = datagram[4..REQUEST_HEADER].try_into().ok()?;
+    Some((request_id, cookie, &datagram[REQUEST_HEADER..]))
+}
+
+/// Splits `body` across as many datagrams of at most `max_datagram` bytes as
+/// it takes, each numbered so the client can put them back together.
+/// `Config::validate` makes sure 255 are always enough.
+pub fn split_response(request_id: u32, code: u8, body: &[u8], max_datagram: usize) -> Vec<Vec<u8>> {
+    let parts: Vec<&[u8]> = match body {
+        [] => vec![&[]],
+        body => body.chunks(max_datagram - RESPONSE_HEADER).collect(),
+    };
+    let count = parts.len() as u8;
+    parts
+        .into_iter()
+        .enumerate()
+        .map(|(index, part)| {
+            let mut datagram = request_id.to_be_bytes().to_vec();
+            datagram.extend([code, index as u8, count]);
+            datagram.extend(part);
+            datagram
+        })
+        .collect()
+}
+
+fn epoch() -> u64 {
+    crate::database::now() / COOKIE_EPOCH
+}
+
+/// Cookies are a MAC of the client's ip and the epoch, so checking them
+/// needs no state.
+pub struct Cookies {
+    key: [u8; 32],
+}
+
+impl Cookies {
+    pub fn new() -> io::Result<Self> {
+        let mut key = [0; 32];
+        getrandom::getrandom(&mut key).map_err(io::Error::other)?;
+        Ok(Self { key })
+    }
+
+    pub fn issue(&self, client: IpAddr, epoch: u64) -> [u8; COOKIE_SIZE] {
+        let ip = match client.to_canonical() {
+            IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+            IpAddr::V6(ip) => ip,
+        };
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC takes keys of any size");
+        mac.update(&ip.octets());
+        mac.update(&epoch.to_be_bytes());
+        let mut cookie = [0; COOKIE_SIZE];
+        cookie.copy_from_slice(&mac.finalize().into_bytes()[..COOKIE_SIZE]);
+        cookie
+    }
+
+    /// Whether `cookie` was issued to `client` this epoch or the last.
+    pub fn check(&self, client: IpAddr, cookie: [u8; COOKIE_SIZE]) -> bool {
+        let epoch = epoch();
+        [epoch, epoch.saturating_sub(1)].into_iter().any(|epoch| {
+            self.issue(client, epoch)
+                .iter()
+                .zip(cookie)
+                .fold(0, |difference, (left, right)| difference | (left ^ right))
+                == 0
+        })
+    }
+}
@@ -0,0 +1,213 @@
+use super::udp::*;
+use crate::{
+    config::{Rate, RateLimits},
+    database::{self, Hub, InMemory, Lobby, LobbyStore},
+    limiter::Limiter,
+    protocol::{Flags, IpAddress, Region},
+    serve, Listeners, Serialise,
+};
+use std::{
+    net::{IpAddr, SocketAddr, UdpSocket},
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+
+const NO_COOKIE: [u8; 8] = [0; 8];
+
+fn start_server() -> (UdpSocket, SocketAddr, Arc<dyn LobbyStore>) {
+    start_server_with(RateLimits::default())
+}
+
+fn start_server_with(rates: RateLimits) -> (UdpSocket, SocketAddr, Arc<dyn LobbyStore>) {
+    let udp = Udp::bind(&["127.0.0.1:0".parse().unwrap()]).unwrap();
+    let address = udp.sockets()[0].local_addr().unwrap();
+    let database: Arc<dyn LobbyStore> = Arc::new(InMemory::new());
+
+    let server_database = Arc::clone(&database);
+    let limiter = Arc::new(Limiter::new(rates));
+    let listeners = Listeners {
+        tcp: Vec::new(),
+        udp,
+        http: None,
+        websocket: None,
+    };
+    thread::spawn(move || serve(listeners, server_database, Arc::new(Hub::new()), limiter, 2));
+
+    let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+    client
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    (client, address, database)
+}
+
+fn lobby(port: u16, lobby_name: &str) -> Lobby {
+    Lobby::new(
+        Flags::new(false, true, false),
+        Region::Europe,
+        IpAddress::IpV4([127, 0, 0, 1]),
+        port,
+        8,
+        String::from(lobby_name),
+        String::new(),
+    )
+    .unwrap()
+}
+
+/// Sends a request and reads back every datagram of its response, returning
+/// the code and the body put back together.
+fn exchange(
+    client: &UdpSocket,
+    server: SocketAddr,
+    request_id: u32,
+    cookie: [u8; 8],
+    message: &[u8],
+) -> (u8, Vec<u8>) {
+    let mut request = request_id.to_be_bytes().to_vec();
+    request.extend(cookie);
+    request.extend(message);
+    client.send_to(&request, server).unwrap();
+
+    let mut parts = Vec::new();
+    let code = loop {
+        let mut buffer = [0; 65536];
+        let length = client.recv(&mut buffer).unwrap();
+        let datagram = &buffer[..length];
+        assert!(length <= request.len() || cookie != NO_COOKIE);
+        assert_eq!(datagram[..4], request_id.to_be_bytes());
+        parts.push((datagram[5], datagram[7..].to_vec()));
+        if parts.len() == datagram[6] as usize {
+            break datagram[4];
+        }
+    };
+    parts.sort();
+    (code, parts.into_iter().flat_map(|(_, part)| part).collect())
+}
+
+fn get_message() -> Vec<u8> {
+    vec![0x8 << 4, 0, 0, 0]
+}
+
+#[test]
+fn splits_responses() {
+    let body: Vec<u8> = (0..2000).map(|byte| byte as u8).collect();
+    let datagrams = split_response(7, 10, &body, 1000);
+
+    assert_eq!(datagrams.len(), 3);
+    for (index, datagram) in datagrams.iter().enumerate() {
+        assert!(datagram.len() <= 1000);
+        assert_eq!(datagram[..7], [0, 0, 0, 7, 10, index as u8, 3]);
+    }
+    let joined: Vec<u8> = datagrams
+        .iter()
+        .flat_map(|datagram| datagram[RESPONSE_HEADER..].to_vec())
+        .collect();
+    assert_eq!(joined, body);
+
+    assert_eq!(split_response(7, 52, &[], 1000), [[0, 0, 0, 7, 52, 0, 1]]);
+}
+
+#[test]
+fn cookies_are_per_ip() {
+    let cookies = Cookies::new().unwrap();
+    let client: IpAddr = "192.168.1.111".parse().unwrap();
+    let epoch = database::now() / 60;
+
+    assert!(cookies.check(client, cookies.issue(client, epoch)));
+    assert!(cookies.check(client, cookies.issue(client, epoch - 1)));
+    assert!(!cookies.check(client, cookies.issue(client, epoch - 2)));
+    assert!(!cookies.check(
+        "192.168.1.112".parse().unwrap(),
+        cookies.issue(client, epoch)
+    ));
+    // The same client on a dual stack socket.
+    let mapped: IpAddr = "::ffff:192.168.1.111".parse().unwrap();
+    assert!(cookies.check(mapped, cookies.issue(client, epoch)));
+
+    assert!(!Cookies::new()
+        .unwrap()
+        .check(client, cookies.issue(client, epoch)));
+}
+
+/// Asks for a cookie with a Get, as a client starts out.
+fn cookie(client: &UdpSocket, server: SocketAddr) -> [u8; 8] {
+    let (code, cookie) = exchange(client, server, 0, NO_COOKIE, &get_message());
+    assert_eq!(code, COOKIE_REQUIRED);
+    cookie.try_into().unwrap()
+}
+
+#[test]
+fn every_request_needs_a_cookie() {
+    let (client, server, database) = start_server();
+    for port in 1..=3 {
+        database.create(lobby(port, "A lobby")).unwrap();
+    }
+
+    let (code, cookie) = exchange(&client, server, 2, NO_COOKIE, &get_message());
+    assert_eq!(code, COOKIE_REQUIRED);
+    let cookie: [u8; 8] = cookie.try_into().unwrap();
+
+    let (code, body) = exchange(&client, server, 3, cookie, &get_message());
+    assert_eq!(code, 10);
+    let names = body.windows(7).filter(|window| window == b"A lobby");
+    assert_eq!(names.count(), 3);
+}
+
+#[test]
+fn unproven_requests_are_not_rate_limited() {
+    let once = Rate {
+        per_second: 0.001,
+        burst: 1,
+    };
+    let (client, server, _) = start_server_with(RateLimits {
+        types: [(String::from("get"), once)].into(),
+        ..RateLimits::default()
+    });
+
+    // Whoever sent these, they cost the client at this address nothing.
+    for request_id in 0..5 {
+        let (code, _) = exchange(&client, server, request_id, NO_COOKIE, &get_message());
+        assert_eq!(code, COOKIE_REQUIRED);
+    }
+
+    let cookie = cookie(&client, server);
+    assert_eq!(exchange(&client, server, 6, cookie, &get_message()).0, 10);
+    assert_eq!(exchange(&client, server, 7, cookie, &get_message()).0, 61);
+}
+
+#[test]
+fn modify_needs_a_cookie() {
+    let (client, server, database) = start_server();
+    let created = database.create(lobby(1, "Before")).unwrap();
+
+    let mut modify = vec![(0x2 << 4) | 1, 0b010, 127, 0, 0, 1];
+    modify.extend(1u16.serialise());
+    modify.extend([4, 8]); // Europe, 8 players
+    modify.extend(String::from("After").serialise());
+    modify.extend(String::new().serialise());
+    modify.push(5);
+    modify.extend(created.token.serialise());
+
+    let (code, cookie) = exchange(&client, server, 4, NO_COOKIE, &modify);
+    assert_eq!(code, COOKIE_REQUIRED);
+    assert_eq!(database.list().unwrap()[0].lobby_name, "Before");
+
+    let (code, body) = exchange(&client, server, 5, cookie.try_into().unwrap(), &modify);
+    assert_eq!((code, body), (10, Vec::new()));
+    let lobbies = database.list().unwrap();
+    assert_eq!(lobbies[0].lobby_name, "After");
+    assert_eq!(lobbies[0].current_players, 5);
+}
+
+#[test]
+fn only_get_and_modify() {
+    let (client, server, _) = start_server();
+    let mut create = vec![0x1 << 4, 0b010, 127, 0, 0, 1];
+    create.extend(1u16.serialise());
+    create.extend([4, 8]);
+    create.extend(String::from("Over UDP").serialise());
+
+    let cookie = cookie(&client, server);
+    let (code, _) = exchange(&client, server, 6, cookie, &create);
+    assert_eq!(code, 41);
+}
@@ -0,0 +1,181 @@
+//! WebSocket clients, for browsers that cannot open a raw socket. Each binary
+//! message carries one protocol message, without the length prefix since the
+//! WebSocket frame has its own, and is answered with one binary message laid
+//! out like a TCP response: `[u8 code]`, then `[u16 length][body]` if there is
+//! a body. A connection carries requests until the client closes it or stays
+//! idle for `timeouts.session_idle`, and they are answered in the order sent.
+
+use crate::{
+    config,
+    database::LobbyStore,
+    http::{self, Request},
+    limiter::{ConnectionGuard, Limiter, Refusal},
+    Serialise,
+};
+use std::{
+    io::{self, Write},
+    net::{SocketAddr, TcpStream},
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+use tracing::{debug, info_span, warn};
+use tungstenite::{
+    handshake::derive_accept_key,
+    protocol::{frame::coding::CloseCode, CloseFrame, Role, WebSocketConfig},
+    Message, WebSocket,
+};
+
+/// Turns a connection away without reading it, like `crate::refuse_connection`.
+/// Browsers only see that the handshake failed.
+pub fn refuse_connection(stream: &mut TcpStream) {
+    let code = Refusal::TooManyConnections as u8;
+    let body = b"Too many connections";
+    if let Err(err) = http::write_response(stream, "503 Service Unavailable", "text/plain", body) {
+        debug!(%err, "Failed to refuse a WebSocket connection");
+    }
+    crate::metrics::METRICS.record_request(None, Some(code), Duration::ZERO);
+}
+
+/// Completes the handshake, then answers messages on a thread of their own
+/// so that an idle browser does not hold on to a worker.
+pub fn handle_connection(
+    mut stream: TcpStream,
+    database: Arc<dyn LobbyStore>,
+    limiter: Arc<Limiter>,
+    guard: ConnectionGuard,
+) {
+    let client_address = match stream.peer_addr() {
+        Ok(address) => address,
+        Err(err) => return warn!(%err, "Failed to get client address"),
+    };
+    let span = info_span!("websocket", client = %client_address);
+    let entered = span.enter();
+
+    let timeouts = &config::get().timeouts;
+    let deadline = Instant::now() + Duration::from_secs(timeouts.receive);
+    let request = match http::read_request(&mut stream, deadline) {
+        Ok(request) => request,
+        Err(err) => return debug!(%err, "Failed to read the WebSocket handshake"),
+    };
+    let Some(key) = upgrade_key(&request) else {
+        debug!(method = request.method, "Not a WebSocket handshake");
+        let body = b"Expected a WebSocket handshake";
+        if let Err(err) = http::write_response(&mut stream, "400 Bad Request", "text/plain", body) {
+            debug!(%err, "Failed to refuse a WebSocket handshake");
+        }
+        return;
+    };
+    let accepted = write!(
+        stream,
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        derive_accept_key(key.as_bytes())
+    );
+    if let Err(err) = accepted {
+        return debug!(%err, "Failed to accept a WebSocket handshake");
+    }
+    debug!("WebSocket opened");
+
+    // Anything read past the handshake already belongs to the first frame.
+    let socket = WebSocket::from_partially_read(stream, request.body, Role::Server, Some(limits()));
+    drop(entered);
+    thread::spawn(move || {
+        let _entered = span.enter();
+        run(socket, client_address, database.as_ref(), &limiter);
+        drop(guard);
+    });
+}
+
+/// The `Sec-WebSocket-Key` of a valid upgrade request.
+fn upgrade_key(request: &Request) -> Option<&str> {
+    let upgrade = request.header("upgrade")?;
+    let connection = request.header("connection")?;
+    let upgrading = request.method == "GET"
+        && upgrade.eq_ignore_ascii_case("websocket")
+        && connection
+            .split(',')
+            .any(|option| option.trim().eq_ignore_ascii_case("upgrade"))
+        && request.header("sec-websocket-version") == Some("13");
+    upgrading.then(|| request.header("sec-websocket-key"))?
+}
+
+/// No message can be longer than a long TCP frame, and most are a few dozen
+/// bytes, so each connection keeps a small buffer.
+fn limits() -> WebSocketConfig {
+    let longest = u16::MAX as usize;
+    WebSocketConfig::default()
+        .read_buffer_size(4096)
+        .max_message_size(Some(longest))
+        .max_frame_size(Some(longest))
+}
+
+/// Answers messages one at a time until the client is done.
+fn run(
+    mut socket: WebSocket<TcpStream>,
+    client_address: SocketAddr,
+    database: &dyn LobbyStore,
+    limiter: &Limiter,
+) {
+    let timeouts = &config::get().timeouts;
+    let stream = socket.get_mut();
+    let timeouts_set = stream
+        .set_read_timeout(Some(Duration::from_secs(timeouts.session_idle)))
+        .and_then(|()| stream.set_write_timeout(Some(Duration::from_secs(timeouts.receive))));
+    if let Err(err) = timeouts_set {
+        return warn!(%err, "Failed to set WebSocket timeouts");
+    }
+
+    loop {
+        let message = match socket.read() {
+            Ok(Message::Binary(message)) => message,
+            Ok(Message::Text(_)) => {
+                debug!("Text message, closing");
+                close(&mut socket, CloseCode::Unsupported, "Binary messages only");
+                continue;
+            }
+            // Pings and the closing handshake are answered by tungstenite.
+            Ok(_) => continue,
+            Err(tungstenite::Error::ConnectionClosed) => {
+                return debug!("WebSocket closed by the client");
+            }
+            Err(tungstenite::Error::Io(err))
+                if matches!(
+                    err.kind(),
+                    io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+                ) =>
+            {
+                debug!("WebSocket idle, closing");
+                return close(&mut socket, CloseCode::Away, "Idle");
+            }
+            Err(err) => return debug!(%err, "WebSocket failed"),
+        };
+
+        let start = Instant::now();
+        let response = crate::respond(&message, client_address, database, limiter, true);
+        let mut reply = vec![response.code];
+        if !response.body.is_empty() {
+            reply.extend((response.body.len() as u16).serialise());
+            reply.extend(response.body);
+        }
+        if let Err(err) = socket.send(Message::binary(reply)) {
+            warn!(%err, "Failed to write WebSocket response");
+            return;
+        }
+        crate::log_request(
+            None,
+            response.message_type,
+            Some(response.code),
+            start.elapsed(),
+        );
+    }
+}
+
+fn close(socket: &mut WebSocket<TcpStream>, code: CloseCode, reason: &'static str) {
+    let frame = CloseFrame {
+        code,
+        reason: reason.into(),
+    };
+    if let Err(err) = socket.close(Some(frame)) {
+        debug!(%err, "Failed to close the WebSocket");
+    }
+}
@@ -0,0 +1,138 @@
+use crate::{
+    config::Rate,
+    database::{Hub, InMemory, LobbyStore},
+    limiter::{Limiter, Refusal},
+    protocol::ParseError,
+    serve,
+    server_tests::{get_message, host_token, lobby_message, request, unlimited, FIRST_PORT},
+    udp::Udp,
+    Listeners, Serialise,
+};
+use std::{
+    io::{Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    sync::Arc,
+    thread,
+};
+use tungstenite::{Message, WebSocket};
+
+/// The WebSocket and TCP addresses of a server sharing one store.
+fn start_server(rate_limits: crate::config::RateLimits) -> (SocketAddr, SocketAddr) {
+    let websocket = TcpListener::bind("127.0.0.1:0").unwrap();
+    let tcp = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addresses = (websocket.local_addr().unwrap(), tcp.local_addr().unwrap());
+    let database: Arc<dyn LobbyStore> = Arc::new(InMemory::new());
+
+    let listeners = Listeners {
+        tcp: vec![tcp],
+        udp: Udp::bind(&[]).unwrap(),
+        http: None,
+        websocket: Some(websocket),
+    };
+    let limiter = Arc::new(Limiter::new(rate_limits));
+    thread::spawn(move || serve(listeners, database, Arc::new(Hub::new()), limiter, 2));
+
+    addresses
+}
+
+fn connect(address: SocketAddr) -> WebSocket<TcpStream> {
+    let stream = TcpStream::connect(address).unwrap();
+    let (socket, _) = tungstenite::client(format!("ws://{address}/"), stream).unwrap();
+    socket
+}
+
+/// Sends one message, returning the code and the rest of the reply, laid
+/// out as `request` returns them.
+fn send(socket: &mut WebSocket<TcpStream>, message: &[u8]) -> (u8, Vec<u8>) {
+    socket.send(Message::binary(message.to_vec())).unwrap();
+    match socket.read().unwrap() {
+        Message::Binary(reply) => (reply[0], reply[1..].to_vec()),
+        other => panic!("expected a binary reply, got {other:?}"),
+    }
+}
+
+#[test]
+fn shares_lobbies_with_tcp() {
+    let (websocket, tcp) = start_server(unlimited());
+    let mut socket = connect(websocket);
+
+    let (code, body) = send(&mut socket, &lobby_message(0x1, FIRST_PORT, "Browser"));
+    assert_eq!(code, 10);
+    assert_eq!(
+        u16::from_be_bytes([body[0], body[1]]) as usize,
+        body.len() - 2
+    );
+    let token = host_token(&body);
+    assert_eq!(
+        request(tcp, &lobby_message(0x1, FIRST_PORT + 1, "Native")).0,
+        10
+    );
+
+    // One connection carries every request.
+    let (code, page) = send(&mut socket, &get_message(0));
+    assert_eq!(code, 10);
+    let page = String::from_utf8_lossy(&page);
+    assert!(page.contains("Browser") && page.contains("Native"));
+
+    let mut destroy = vec![(0x4 << 4) | 1, 0, 127, 0, 0, 1];
+    destroy.extend(FIRST_PORT.serialise());
+    destroy.extend(token);
+    assert_eq!(send(&mut socket, &destroy), (10, Vec::new()));
+    let (_, page) = request(tcp, &get_message(0));
+    assert!(!String::from_utf8_lossy(&page).contains("Browser"));
+
+    // Sessions and subscriptions need a raw socket.
+    let session = [0x9 << 4, 0];
+    assert_eq!(send(&mut socket, &session).0, ParseError::InvalidType as u8);
+    socket.close(None).unwrap();
+}
+
+#[test]
+fn rate_limited_per_client() {
+    let mut rate_limits = unlimited();
+    rate_limits.types.insert(
+        String::from("create"),
+        Rate {
+            per_second: 0.001,
+            burst: 1,
+        },
+    );
+    let (websocket, tcp) = start_server(rate_limits);
+
+    let mut socket = connect(websocket);
+    assert_eq!(
+        send(&mut socket, &lobby_message(0x1, FIRST_PORT, "First")).0,
+        10
+    );
+    let refused = send(&mut socket, &lobby_message(0x1, FIRST_PORT + 1, "Second"));
+    assert_eq!(refused.0, Refusal::RateLimited as u8);
+    // The bucket is the client's, whichever listener it comes through.
+    let refused = request(tcp, &lobby_message(0x1, FIRST_PORT + 2, "Third"));
+    assert_eq!(refused.0, Refusal::RateLimited as u8);
+    assert_eq!(send(&mut socket, &get_message(0)).0, 10);
+}
+
+#[test]
+fn text_messages_close_the_socket() {
+    let (websocket, _) = start_server(unlimited());
+    let mut socket = connect(websocket);
+
+    socket.send(Message::text("hello")).unwrap();
+    match socket.read().unwrap() {
+        Message::Close(Some(frame)) => assert_eq!(u16::from(frame.code), 1003),
+        other => panic!("expected a close, got {other:?}"),
+    }
+}
+
+#[test]
+fn refuses_plain_http() {
+    let (websocket, _) = start_server(unlimited());
+    let mut stream = TcpStream::connect(websocket).unwrap();
+    stream
+        .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+}